@@ -0,0 +1,123 @@
+//! Extraction over a synthetic extract from `write_synthetic_osm`; the same
+//! file can be produced with `extract_street_polygons gen-bench`. PBF input
+//! is benchmarked in `pbf.rs`.
+//!
+//! Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use extract_street_polygons::{
+    extract_entries, write_synthetic_osm, ExtractOptions, PlaceCellSize, SyntheticOsm,
+};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+fn extract_synthetic(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bench.osm");
+    let shape = SyntheticOsm {
+        nodes: 20_000,
+        ways: 2_000,
+        names: 200,
+    };
+    write_synthetic_osm(&path, shape).unwrap();
+    let options = ExtractOptions::default();
+
+    let entries = extract_entries(&path, &options).unwrap();
+    assert!(entries.iter().any(|entry| entry.kind == "street"));
+
+    c.bench_function("extract_entries 2k ways", |b| {
+        b.iter(|| extract_entries(&path, &options).unwrap())
+    });
+}
+
+/// City association with the place index at the default, automatic and
+/// smallest cell sizes.
+fn place_cell_size(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bench.osm");
+    let shape = SyntheticOsm {
+        nodes: 20_000,
+        ways: 5_000,
+        names: 5_000,
+    };
+    write_synthetic_osm(&path, shape).unwrap();
+
+    let mut group = c.benchmark_group("place_cell_size");
+    for (label, size) in [("1.0", "1.0"), ("auto", "auto"), ("0.1", "0.1")] {
+        let options = ExtractOptions {
+            place_cell_size: PlaceCellSize::parse(size).unwrap(),
+            ..ExtractOptions::default()
+        };
+        group.bench_function(label, |b| {
+            b.iter(|| extract_entries(&path, &options).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Writes `streets` two-node streets over a 12×12 grid of villages 0.02°
+/// apart, with two towns, so that matching places dominates extraction.
+fn write_place_grid_osm(path: &Path, streets: usize) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, r#"<osm version="0.6">"#)?;
+    let mut places = Vec::new();
+    for i in 0..12 {
+        for j in 0..12 {
+            let coord = (13.0 + i as f64 * 0.02, 52.0 + j as f64 * 0.02);
+            places.push(("village", format!("Place {i}/{j}"), coord));
+        }
+    }
+    places.push(("town", "Town 0".to_string(), (13.05, 52.05)));
+    places.push(("town", "Town 1".to_string(), (13.17, 52.11)));
+    for (id, (place, name, (lon, lat))) in places.iter().enumerate() {
+        writeln!(
+            out,
+            r#"<node id="{}" lat="{lat}" lon="{lon}"><tag k="place" v="{place}" /><tag k="name" v="{name}" /></node>"#,
+            1_000_000 + id
+        )?;
+    }
+    for street in 0..streets {
+        let lon = 12.98 + (street % 491) as f64 * 0.0005;
+        let lat = 51.98 + (street / 491 % 443) as f64 * 0.0006;
+        let (from, to) = (2 * street + 1, 2 * street + 2);
+        writeln!(out, r#"<node id="{from}" lat="{lat:.7}" lon="{lon:.7}" />"#)?;
+        writeln!(
+            out,
+            r#"<node id="{to}" lat="{lat:.7}" lon="{:.7}" />"#,
+            lon + 0.0002
+        )?;
+        writeln!(
+            out,
+            r#"<way id="{}"><nd ref="{from}" /><nd ref="{to}" /><tag k="highway" v="residential" /><tag k="name" v="Street {street}" /></way>"#,
+            street + 1
+        )?;
+    }
+    writeln!(out, "</osm>")?;
+    out.flush()
+}
+
+/// City field resolution among densely packed places, which the per-cell
+/// place match cache speeds up.
+fn place_match_cache(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("places.osm");
+    write_place_grid_osm(&path, 50_000).unwrap();
+    let options = ExtractOptions::default();
+
+    let entries = extract_entries(&path, &options).unwrap();
+    let streets = entries.iter().filter(|entry| entry.kind == "street");
+    assert_eq!(streets.clone().count(), 50_000);
+    assert!(streets.clone().all(|entry| !entry.city_resolved.is_empty()));
+
+    c.bench_function("place_match_cache 50k streets", |b| {
+        b.iter(|| extract_entries(&path, &options).unwrap())
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = extract_synthetic, place_cell_size, place_match_cache
+}
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+//! `.pbf` reading over a synthetic extract from `write_synthetic_osm`, which
+//! needs the `pbf-writer` feature.
+//!
+//! Run with `cargo bench --features pbf-writer --bench pbf`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use extract_street_polygons::{extract_entries, write_synthetic_osm, ExtractOptions, SyntheticOsm};
+
+/// `.pbf` input read through the file handle and with `--mmap`.
+fn pbf_read(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bench.pbf");
+    let shape = SyntheticOsm {
+        nodes: 200_000,
+        ways: 20_000,
+        names: 2_000,
+    };
+    write_synthetic_osm(&path, shape).unwrap();
+
+    let mut group = c.benchmark_group("pbf_read");
+    for (label, mmap) in [("buffered", false), ("mmap", true)] {
+        let options = ExtractOptions {
+            mmap,
+            ..ExtractOptions::default()
+        };
+        group.bench_function(label, |b| {
+            b.iter(|| extract_entries(&path, &options).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = pbf_read
+}
+criterion_main!(benches);
@@ -0,0 +1,9796 @@
+//! Extracts named streets, POIs and places from OpenStreetMap `.pbf` or
+//! `.osm` files into merged, city-annotated rows.
+
+use csv::{ReaderBuilder, Terminator, Writer, WriterBuilder};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use memmap2::Mmap;
+use osmpbfreader::{NodeId, OsmId, OsmObj, OsmPbfReader, Tags, WayId};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use rayon::prelude::*;
+use regex::Regex;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "pyo3")]
+mod python;
+#[cfg(feature = "pbf-writer")]
+mod synthetic_pbf;
+
+/// Everything that can go wrong while reading input or writing output.
+#[derive(Debug, thiserror::Error)]
+pub enum ExtractError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("invalid OSM XML: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("invalid PBF: {0}")]
+    Pbf(#[from] osmpbfreader::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// An input whose extension or contents is neither PBF nor OSM XML.
+    #[error("unsupported input format: {0}")]
+    UnsupportedFormat(String),
+    /// An option value or argument that cannot be used.
+    #[error("{0}")]
+    BadArgs(String),
+    /// Input that parsed but does not make sense: a degenerate geometry, a
+    /// foreign CSV, a corrupt checkpoint.
+    #[error("{0}")]
+    InvalidData(String),
+    /// The run took longer than `ExtractOptions::timeout`.
+    #[error("extraction exceeded the timeout of {0:?}")]
+    Timeout(Duration),
+}
+
+impl From<std::num::ParseFloatError> for ExtractError {
+    fn from(err: std::num::ParseFloatError) -> Self {
+        ExtractError::invalid_data(format!("invalid number: {err}"))
+    }
+}
+
+impl From<std::num::ParseIntError> for ExtractError {
+    fn from(err: std::num::ParseIntError) -> Self {
+        ExtractError::invalid_data(format!("invalid integer: {err}"))
+    }
+}
+
+impl ExtractError {
+    fn bad_args(message: impl Into<String>) -> Self {
+        ExtractError::BadArgs(message.into())
+    }
+
+    fn invalid_data(message: impl Into<String>) -> Self {
+        ExtractError::InvalidData(message.into())
+    }
+}
+
+impl From<quick_xml::events::attributes::AttrError> for ExtractError {
+    fn from(err: quick_xml::events::attributes::AttrError) -> Self {
+        ExtractError::Xml(err.into())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ExtractError>;
+
+/// Tag keys read as names, in the default precedence order: the first key
+/// present on an element gives its primary name (the row name with
+/// `--aliases`). `ExtractOptions::name_keys` reorders or narrows this list.
+/// Each key's `key:<lang>` variants are read as well, after all plain keys.
+pub const NAME_KEYS: [&str; 8] = [
+    "name",
+    "alt_name",
+    "old_name",
+    "official_name",
+    "loc_name",
+    "short_name",
+    "int_name",
+    "nat_name",
+];
+
+fn is_name_key(key: &str) -> bool {
+    NAME_KEYS.contains(&key) || lang_name_key(key, &NAME_KEYS).is_some()
+}
+
+/// The language of a `key:<lang>` name key whose base is one of `keys`.
+fn lang_name_key<'k>(key: &'k str, keys: &[&str]) -> Option<&'k str> {
+    let (base, lang) = key.split_once(':')?;
+    keys.contains(&base).then_some(lang)
+}
+
+/// The name keys to read, in precedence order.
+fn name_keys(options: &ExtractOptions) -> Vec<&str> {
+    if options.name_keys.is_empty() {
+        NAME_KEYS.to_vec()
+    } else {
+        options.name_keys.iter().map(String::as_str).collect()
+    }
+}
+
+/// Parses a comma-separated `--name-keys` precedence list. Only keys from
+/// [`NAME_KEYS`] are accepted.
+pub fn parse_name_keys(value: &str) -> Result<Vec<String>> {
+    let mut keys: Vec<String> = Vec::new();
+    for key in value.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+        if !NAME_KEYS.contains(&key) {
+            return Err(ExtractError::bad_args(format!(
+                "unknown --name-keys key: {key} (expected some of {})",
+                NAME_KEYS.join(",")
+            )));
+        }
+        if !keys.iter().any(|existing| existing == key) {
+            keys.push(key.to_string());
+        }
+    }
+    if keys.is_empty() {
+        return Err(ExtractError::bad_args("--name-keys requires at least one key"));
+    }
+    Ok(keys)
+}
+
+/// Splits a `;`-separated multi-value name. With `split` off, the whole
+/// trimmed value is one name (for regions where `;` is punctuation).
+fn split_names(value: &str, split: bool) -> Vec<String> {
+    if !split {
+        let value = value.trim();
+        return if value.is_empty() { Vec::new() } else { vec![value.to_string()] };
+    }
+    value
+        .split(';')
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_string())
+        .collect()
+}
+
+/// Strips control characters and invisible zero-width marks (ZWSP, word
+/// joiner, BOM) and collapses every whitespace run, non-breaking spaces
+/// included, into one space. ZWNJ and ZWJ are kept: they change the
+/// rendering of e.g. Persian and Indic names.
+fn normalize_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    let mut pending_space = false;
+    for ch in name.chars() {
+        if ch.is_whitespace() {
+            pending_space = !normalized.is_empty();
+        } else if !(ch.is_control() || matches!(ch, '\u{200b}' | '\u{2060}' | '\u{feff}')) {
+            if pending_space {
+                normalized.push(' ');
+                pending_space = false;
+            }
+            normalized.push(ch);
+        }
+    }
+    normalized
+}
+
+#[derive(Clone)]
+struct NameVariant {
+    name: String,
+    lang: String,
+}
+
+fn add_names(
+    value: &str,
+    lang: &str,
+    variants: &mut Vec<NameVariant>,
+    seen: &mut HashSet<(String, String)>,
+    options: &ExtractOptions,
+) {
+    for name in split_names(value, !options.no_split_names) {
+        let name = if options.raw_names { name } else { normalize_name(&name) };
+        let name = match &options.rename_map {
+            Some(map) => map.rename(name),
+            None => name,
+        };
+        if name.is_empty() {
+            continue;
+        }
+        let key = (name.clone(), lang.to_string());
+        if seen.insert(key.clone()) {
+            variants.push(NameVariant {
+                name,
+                lang: lang.to_string(),
+            });
+        }
+    }
+}
+
+fn collect_name_variants(tags: &Tags, options: &ExtractOptions) -> Vec<NameVariant> {
+    let mut variants = Vec::new();
+    let mut seen: HashSet<(String, String)> = HashSet::new();
+    let keys = name_keys(options);
+
+    for key in &keys {
+        if let Some(value) = tags.get(*key) {
+            if !value.is_empty() {
+                add_names(value, "", &mut variants, &mut seen, options);
+            }
+        }
+    }
+
+    for (key, value) in tags.iter() {
+        if value.is_empty() {
+            continue;
+        }
+        if let Some(lang) = lang_name_key(key, &keys) {
+            add_names(value, lang, &mut variants, &mut seen, options);
+        }
+    }
+
+    if variants.is_empty() {
+        if let Some((_, value)) = fallback_name(tags, options) {
+            // One name per road, not one per signed destination.
+            add_names(&value.replace(';', " / "), "", &mut variants, &mut seen, options);
+        }
+    }
+    variants
+}
+
+/// With `--use-destination`, the tag a highway without any name tag is
+/// named after instead (`destination`, else `junction:ref`), and its value.
+fn fallback_name<'t>(tags: &'t Tags, options: &ExtractOptions) -> Option<(&'static str, &'t str)> {
+    if !options.use_destination || !tags.contains_key("highway") || has_name_tags(tags) {
+        return None;
+    }
+    ["destination", "junction:ref"].into_iter().find_map(|key| {
+        let value = tags.get(key)?.trim();
+        (!value.is_empty()).then_some((key, value))
+    })
+}
+
+fn polygon_centroid(coords: &[(f64, f64)]) -> Result<(f64, f64)> {
+    if coords.len() < 3 {
+        return Err(ExtractError::invalid_data("polygon must have at least 3 points"));
+    }
+
+    let is_closed = coords.len() >= 4 && coords.first() == coords.last();
+    let ring = if is_closed { &coords[..coords.len() - 1] } else { coords };
+    // Work relative to the first vertex so the cross products stay small
+    // far from the origin; the closing edge is always implied.
+    let (ox, oy) = ring[0];
+    let mut area = 0.0;
+    let mut cx = 0.0;
+    let mut cy = 0.0;
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (ox, oy, ox, oy);
+
+    for i in 0..ring.len() {
+        let (x0, y0) = (ring[i].0 - ox, ring[i].1 - oy);
+        let (x1, y1) = {
+            let (x, y) = ring[(i + 1) % ring.len()];
+            (x - ox, y - oy)
+        };
+        let cross = x0 * y1 - x1 * y0;
+        area += cross;
+        cx += (x0 + x1) * cross;
+        cy += (y0 + y1) * cross;
+        min_x = min_x.min(ring[i].0);
+        min_y = min_y.min(ring[i].1);
+        max_x = max_x.max(ring[i].0);
+        max_y = max_y.max(ring[i].1);
+    }
+    area *= 0.5;
+
+    // The signs of `area` and the moments flip together with the winding,
+    // so the quotient is orientation-independent; only its magnitude
+    // decides whether the ring is too thin to trust.
+    let extent = (max_x - min_x).max(max_y - min_y);
+    if area.abs() > 1e-9 * extent * extent {
+        let centroid = (ox + cx / (6.0 * area), oy + cy / (6.0 * area));
+        let inside_bbox = (min_x..=max_x).contains(&centroid.0)
+            && (min_y..=max_y).contains(&centroid.1);
+        if inside_bbox {
+            return Ok(centroid);
+        }
+    }
+
+    let count = ring.len() as f64;
+    let sum_x: f64 = ring.iter().map(|(x, _)| x).sum();
+    let sum_y: f64 = ring.iter().map(|(_, y)| y).sum();
+    Ok((sum_x / count, sum_y / count))
+}
+
+/// Signed shoelace area of a ring in squared degrees (positive when
+/// counter-clockwise). The closing edge is implied for open rings.
+fn ring_area(coords: &[(f64, f64)]) -> f64 {
+    if coords.len() < 3 {
+        return 0.0;
+    }
+    let mut area = 0.0;
+    for i in 0..coords.len() {
+        let (x0, y0) = coords[i];
+        let (x1, y1) = coords[(i + 1) % coords.len()];
+        area += x0 * y1 - x1 * y0;
+    }
+    area * 0.5
+}
+
+/// Even-odd ray casting test; `ring` may be open or closed.
+fn point_in_ring(point: (f64, f64), ring: &[(f64, f64)]) -> bool {
+    let (x, y) = point;
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let (xi, yi) = ring[i];
+        let (xj, yj) = ring[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Joins way node sequences that share endpoints into closed rings.
+/// Sequences that cannot be closed are dropped.
+fn assemble_rings<T: Copy + PartialEq>(segments: Vec<Vec<T>>) -> Vec<Vec<T>> {
+    let mut pending: Vec<Vec<T>> = segments.into_iter().filter(|seg| seg.len() >= 2).collect();
+    let mut rings = Vec::new();
+    while let Some(mut ring) = pending.pop() {
+        while ring.first() != ring.last() {
+            let tail = *ring.last().unwrap();
+            let Some(position) = pending
+                .iter()
+                .position(|seg| seg.first() == Some(&tail) || seg.last() == Some(&tail))
+            else {
+                break;
+            };
+            let mut next = pending.swap_remove(position);
+            if next.first() != Some(&tail) {
+                next.reverse();
+            }
+            ring.extend(next.into_iter().skip(1));
+        }
+        if ring.len() >= 4 && ring.first() == ring.last() {
+            rings.push(ring);
+        }
+    }
+    rings
+}
+
+/// Center of a multipolygon given its outer member ways as node id lists:
+/// the centroid of the largest assembled outer ring.
+fn multipolygon_center<F>(outer_ways: Vec<Vec<i64>>, lookup: F) -> Option<(f64, f64)>
+where
+    F: Fn(i64) -> Option<(f64, f64)>,
+{
+    let mut best: Option<(Vec<(f64, f64)>, f64)> = None;
+    for ring in assemble_rings(outer_ways) {
+        let coords: Option<Vec<(f64, f64)>> = ring.iter().map(|id| lookup(*id)).collect();
+        let Some(coords) = coords else {
+            continue;
+        };
+        let area = ring_area(&coords).abs();
+        if best.as_ref().map(|(_, best_area)| area > *best_area).unwrap_or(true) {
+            best = Some((coords, area));
+        }
+    }
+    best.and_then(|(coords, _)| polygon_centroid(&coords).ok())
+}
+
+/// A named `boundary=administrative` way or relation with an
+/// `admin_level`, for `--boundaries-output`.
+fn is_admin_boundary(tags: &Tags) -> bool {
+    has_tag_value(tags, "boundary", &["administrative"])
+        && has_tag(tags, "admin_level")
+        && tags.get("name").is_some_and(|name| !name.trim().is_empty())
+}
+
+/// One row of the `--boundaries-output` CSV.
+struct Boundary {
+    name: String,
+    admin_level: String,
+    center: (f64, f64),
+    area_km2: f64,
+    /// Outer rings, for `ExtractOptions::prefer_places_in_boundary`.
+    rings: Vec<Vec<(f64, f64)>>,
+}
+
+/// Default of `ExtractOptions::max_nodes_per_way`: far above any street
+/// or building, but below coastlines and other long boundary lines.
+const DEFAULT_MAX_NODES_PER_WAY: usize = 100_000;
+
+fn warn_oversized_ways(input_path: &Path, skipped: usize, options: &ExtractOptions) {
+    if skipped > 0 {
+        eprintln!(
+            "warning: {}: skipped {skipped} ways with more than {} nodes",
+            input_path.display(),
+            options.max_nodes_per_way()
+        );
+    }
+}
+
+/// Whether boundaries are collected while reading: for
+/// `--boundaries-output` or `--prefer-places-in-boundary`.
+fn collects_boundaries(options: &ExtractOptions) -> bool {
+    options.boundaries_output.is_some() || options.prefer_places_in_boundary
+}
+
+/// Assembles a boundary from its outer ways (node id lists; a closed way
+/// is one ring on its own). The center is the centroid of the largest
+/// ring, as in [`multipolygon_center`], and the area sums every ring.
+fn boundary_from_outer_ways<F>(
+    tags: &Tags,
+    outer_ways: Vec<Vec<i64>>,
+    lookup: F,
+) -> Option<Boundary>
+where
+    F: Fn(i64) -> Option<(f64, f64)>,
+{
+    let mut rings = Vec::new();
+    let mut largest: Option<(usize, f64)> = None;
+    let mut area_km2 = 0.0;
+    for ring in assemble_rings(outer_ways) {
+        let Some(coords) = ring.iter().map(|id| lookup(*id)).collect::<Option<Vec<_>>>() else {
+            continue;
+        };
+        let area = ring_area_km2(&coords);
+        area_km2 += area;
+        if largest.is_none_or(|(_, largest_area)| area > largest_area) {
+            largest = Some((rings.len(), area));
+        }
+        rings.push(coords);
+    }
+    let center = polygon_centroid(&rings[largest?.0]).ok()?;
+    Some(Boundary {
+        name: tags.get("name")?.trim().to_string(),
+        admin_level: tags.get("admin_level")?.to_string(),
+        center,
+        area_km2,
+        rings,
+    })
+}
+
+/// Writes `boundaries` as `name,admin_level,center_lon,center_lat,area_km2`.
+fn write_boundaries<'b>(path: &Path, boundaries: impl Iterator<Item = &'b Boundary>) -> Result<()> {
+    let mut writer = Writer::from_path(path)?;
+    writer.write_record(["name", "admin_level", "center_lon", "center_lat", "area_km2"])?;
+    for boundary in boundaries {
+        writer.write_record([
+            boundary.name.clone(),
+            boundary.admin_level.clone(),
+            boundary.center.0.to_string(),
+            boundary.center.1.to_string(),
+            boundary.area_km2.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn is_multipolygon(tags: &Tags) -> bool {
+    has_tag_value(tags, "type", &["multipolygon"])
+}
+
+fn is_outer_role(role: &str) -> bool {
+    role.is_empty() || role == "outer"
+}
+
+fn is_associated_street(tags: &Tags) -> bool {
+    has_tag_value(tags, "type", &["associatedStreet"])
+}
+
+/// Street names from `type=associatedStreet` relations, keyed by member.
+type StreetMembership = HashMap<(MemberType, i64), String>;
+
+/// Records the relation's street name for each of its node/way members other
+/// than the `street` itself. The relation's `name` wins; otherwise the name of
+/// its first named `street` way is used.
+fn add_associated_street(
+    membership: &mut StreetMembership,
+    tags: &Tags,
+    members: &[(MemberType, i64, &str)],
+    way_name: impl Fn(i64) -> Option<String>,
+) {
+    let street_name = resolve_first_non_empty(&[tags.get("name").map(|value| value.as_str())])
+        .or_else(|| {
+            members
+                .iter()
+                .filter(|(member_type, _, role)| {
+                    *member_type == MemberType::Way && *role == "street"
+                })
+                .find_map(|(_, id, _)| way_name(*id).filter(|name| !name.is_empty()))
+        });
+    let Some(street_name) = street_name else {
+        return;
+    };
+    for (member_type, id, role) in members {
+        if *role != "street" && *member_type != MemberType::Relation {
+            membership
+                .entry((*member_type, *id))
+                .or_insert_with(|| street_name.clone());
+        }
+    }
+}
+
+/// `addr:street` of a POI, falling back to the street of an associatedStreet
+/// relation the POI is a member of.
+fn poi_addr_street(tags: &Tags, associated: Option<&String>) -> String {
+    resolve_first_non_empty(&[
+        tags.get("addr:street").map(|value| value.as_str()),
+        associated.map(|value| value.as_str()),
+    ])
+    .unwrap_or_default()
+}
+
+fn line_midpoint(coords: &[(f64, f64)]) -> Result<(f64, f64)> {
+    if coords.len() < 2 {
+        return Err(ExtractError::invalid_data("line must have at least 2 points"));
+    }
+
+    let mut total = 0.0;
+    for i in 0..(coords.len() - 1) {
+        let (x0, y0) = coords[i];
+        let (x1, y1) = coords[i + 1];
+        let dx = x1 - x0;
+        let dy = y1 - y0;
+        total += (dx * dx + dy * dy).sqrt();
+    }
+
+    if total == 0.0 {
+        let mut sum_x = 0.0;
+        let mut sum_y = 0.0;
+        for (x, y) in coords {
+            sum_x += x;
+            sum_y += y;
+        }
+        let count = coords.len() as f64;
+        return Ok((sum_x / count, sum_y / count));
+    }
+
+    let halfway = total / 2.0;
+    let mut acc = 0.0;
+    for i in 0..(coords.len() - 1) {
+        let (x0, y0) = coords[i];
+        let (x1, y1) = coords[i + 1];
+        let dx = x1 - x0;
+        let dy = y1 - y0;
+        let len = (dx * dx + dy * dy).sqrt();
+        if acc + len >= halfway {
+            let t = (halfway - acc) / len;
+            return Ok((x0 + (x1 - x0) * t, y0 + (y1 - y0) * t));
+        }
+        acc += len;
+    }
+
+    Ok(*coords.last().unwrap())
+}
+
+/// The vertex of `coords` closest to `point`, for `--center-on-geometry`.
+/// Returns `point` itself when `coords` is empty.
+fn nearest_vertex(point: (f64, f64), coords: &[(f64, f64)]) -> (f64, f64) {
+    coords
+        .iter()
+        .copied()
+        .min_by(|a, b| haversine_km(point, *a).total_cmp(&haversine_km(point, *b)))
+        .unwrap_or(point)
+}
+
+fn has_name_tags(tags: &Tags) -> bool {
+    tags.iter()
+        .any(|(key, value)| is_name_key(key) && !value.is_empty())
+}
+
+/// `highway` values of roads that are not (or no longer) in use.
+const LIFECYCLE_HIGHWAYS: [&str; 7] = [
+    "proposed",
+    "planned",
+    "construction",
+    "abandoned",
+    "disused",
+    "razed",
+    "demolished",
+];
+
+/// The `status` column for an element: `proposed` for `highway=proposed`
+/// and `planned`, `construction`, or `active` for everything else.
+fn lifecycle_status(tags: &Tags) -> &'static str {
+    let highway = tags.get("highway").map_or("", |value| split_multi_value(value).0);
+    match highway {
+        "proposed" | "planned" => "proposed",
+        "construction" => "construction",
+        _ => "active",
+    }
+}
+
+fn is_street(tags: &Tags, options: &ExtractOptions) -> bool {
+    let highway = tags.get("highway").map_or("", |value| split_multi_value(value).0);
+    if highway.is_empty() || !(has_name_tags(tags) || fallback_name(tags, options).is_some()) {
+        return false;
+    }
+    if LIFECYCLE_HIGHWAYS.contains(&highway)
+        && !(options.include_proposed && lifecycle_status(tags) != "active")
+    {
+        return false;
+    }
+    // A lingering name next to noname=yes is usually stale.
+    options.keep_noname || !has_tag_value(tags, "noname", &["yes"])
+}
+
+fn is_place_node(tags: &Tags) -> bool {
+    let place = tags.get("place").map(|value| value.as_str()).unwrap_or("");
+    let has_name = has_name_tags(tags);
+    has_name
+        && matches!(
+            place,
+            "city" | "town" | "village" | "hamlet" | "suburb" | "locality"
+        )
+}
+
+fn place_node_from_tags(
+    tags: &Tags,
+    coord: (f64, f64),
+    options: &ExtractOptions,
+) -> Option<PlaceNode> {
+    if !is_place_node(tags) {
+        return None;
+    }
+    let names = collect_name_variants(tags, options);
+    if names.is_empty() {
+        return None;
+    }
+    let place_type = tags.get("place")?.to_string();
+    if !options.place_filter.is_empty() && !options.place_filter.contains(&place_type) {
+        return None;
+    }
+    let population = tags
+        .get("population")
+        .and_then(|value| parse_population(value));
+    Some(PlaceNode::new(names, place_type, coord, population))
+}
+
+fn is_city_or_town(place_type: &str) -> bool {
+    matches!(place_type, "city" | "town")
+}
+
+fn haversine_km(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lon1, lat1) = a;
+    let (lon2, lat2) = b;
+    let r = 6371.0_f64;
+    let dlat = (lat2 - lat1).to_radians();
+    let dlon = (lon2 - lon1).to_radians();
+    let lat1 = lat1.to_radians();
+    let lat2 = lat2.to_radians();
+    let sin_dlat = (dlat / 2.0).sin();
+    let sin_dlon = (dlon / 2.0).sin();
+    let h = sin_dlat * sin_dlat + lat1.cos() * lat2.cos() * sin_dlon * sin_dlon;
+    2.0 * r * h.sqrt().asin()
+}
+
+/// Highway values that describe an area whenever the way is closed.
+const AREA_HIGHWAYS: [&str; 2] = ["rest_area", "services"];
+
+/// Whether a closed highway way is a real area (e.g. a pedestrian plaza)
+/// rather than a linear loop.
+fn is_highway_area(tags: &Tags) -> bool {
+    has_tag_value(tags, "area", &["yes"]) || has_tag_value(tags, "highway", &AREA_HIGHWAYS)
+}
+
+/// Approximate area of a ring in km², projecting onto a local
+/// equirectangular plane around the ring's mean latitude.
+fn ring_area_km2(coords: &[(f64, f64)]) -> f64 {
+    if coords.is_empty() {
+        return 0.0;
+    }
+    let mean_lat = coords.iter().map(|(_, lat)| lat).sum::<f64>() / coords.len() as f64;
+    let km_per_deg = EARTH_RADIUS_KM.to_radians();
+    let cos_lat = mean_lat.to_radians().cos();
+    let projected: Vec<(f64, f64)> = coords
+        .iter()
+        .map(|(lon, lat)| (lon * km_per_deg * cos_lat, lat * km_per_deg))
+        .collect();
+    ring_area(&projected).abs()
+}
+
+fn path_length_km(coords: &[(f64, f64)]) -> f64 {
+    if coords.len() < 2 {
+        return 0.0;
+    }
+    let mut total = 0.0;
+    for i in 0..(coords.len() - 1) {
+        total += haversine_km(coords[i], coords[i + 1]);
+    }
+    total
+}
+
+const MAX_PLACE_DISTANCE_KM: f64 = 75.0;
+const EARTH_RADIUS_KM: f64 = 6371.0;
+const DEFAULT_PLACE_CELL_DEG: f64 = 1.0;
+const TARGET_PLACES_PER_CELL: f64 = 16.0;
+const MIN_PLACE_CELL_DEG: f64 = 0.1;
+const MAX_PLACE_CELL_DEG: f64 = 5.0;
+/// Side in degrees of the cells whose nearest places `PlaceMatchCache`
+/// memoizes; roughly 100 m, so a cell holds a few streets of one town.
+const PLACE_MATCH_CELL_DEG: f64 = 0.001;
+
+/// Grid cell size of the place index used for city association.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PlaceCellSize {
+    Fixed(f64),
+    Auto,
+}
+
+impl Default for PlaceCellSize {
+    fn default() -> Self {
+        PlaceCellSize::Fixed(DEFAULT_PLACE_CELL_DEG)
+    }
+}
+
+impl PlaceCellSize {
+    pub fn parse(value: &str) -> Result<Self> {
+        if value == "auto" {
+            return Ok(PlaceCellSize::Auto);
+        }
+        match value.parse::<f64>() {
+            Ok(size) if (MIN_PLACE_CELL_DEG..=MAX_PLACE_CELL_DEG).contains(&size) => {
+                Ok(PlaceCellSize::Fixed(size))
+            }
+            _ => Err(ExtractError::bad_args(format!(
+                "invalid --place-cell-size: {value} (expected auto or \
+                 {MIN_PLACE_CELL_DEG} to {MAX_PLACE_CELL_DEG} degrees)"
+            ))),
+        }
+    }
+}
+
+/// Association radii by place rank. Hamlets and localities use `hamlet_km`;
+/// every other place type (city, town, village, suburb) uses `town_km`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PlaceRadii {
+    pub town_km: f64,
+    pub hamlet_km: f64,
+}
+
+impl Default for PlaceRadii {
+    fn default() -> Self {
+        Self {
+            town_km: MAX_PLACE_DISTANCE_KM,
+            hamlet_km: MAX_PLACE_DISTANCE_KM,
+        }
+    }
+}
+
+impl PlaceRadii {
+    fn for_place_type(&self, place_type: &str) -> f64 {
+        match place_type {
+            "hamlet" | "locality" => self.hamlet_km,
+            _ => self.town_km,
+        }
+    }
+
+    fn max_km(&self) -> f64 {
+        self.town_km.max(self.hamlet_km)
+    }
+}
+
+/// Rank of a `place=*` value, lowest first. Types outside this list rank
+/// with `locality`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PlaceRank {
+    #[default]
+    Locality,
+    Suburb,
+    Hamlet,
+    Village,
+    Town,
+    City,
+}
+
+impl PlaceRank {
+    fn of(place_type: &str) -> Self {
+        match place_type {
+            "city" => PlaceRank::City,
+            "town" => PlaceRank::Town,
+            "village" => PlaceRank::Village,
+            "hamlet" => PlaceRank::Hamlet,
+            "suburb" => PlaceRank::Suburb,
+            _ => PlaceRank::Locality,
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "city" | "town" | "village" | "hamlet" | "suburb" | "locality" => Ok(Self::of(value)),
+            _ => Err(ExtractError::bad_args(format!(
+                "invalid --min-place-rank (expected city, town, village, hamlet, suburb or \
+                 locality): {value}"
+            ))),
+        }
+    }
+}
+
+/// Parses a comma-separated `--place-filter` list of place types.
+pub fn parse_place_filter(value: &str) -> Result<Vec<String>> {
+    let mut types: Vec<String> = Vec::new();
+    for place_type in value.split(',').map(str::trim).filter(|value| !value.is_empty()) {
+        if !matches!(place_type, "city" | "town" | "village" | "hamlet" | "suburb" | "locality") {
+            return Err(ExtractError::bad_args(format!(
+                "invalid --place-filter type (expected city, town, village, hamlet, suburb or \
+                 locality): {place_type}"
+            )));
+        }
+        if !types.iter().any(|existing| existing == place_type) {
+            types.push(place_type.to_string());
+        }
+    }
+    if types.is_empty() {
+        return Err(ExtractError::bad_args("--place-filter requires at least one place type"));
+    }
+    Ok(types)
+}
+
+/// The places a lookup considers: those ranked from `min` to `max`.
+#[derive(Copy, Clone)]
+struct PlaceFilter {
+    min: PlaceRank,
+    max: PlaceRank,
+}
+
+impl PlaceFilter {
+    #[cfg(test)]
+    const ANY: Self = Self::at_least(PlaceRank::Locality);
+    const SUBURB: Self = Self {
+        min: PlaceRank::Suburb,
+        max: PlaceRank::Suburb,
+    };
+
+    const fn at_least(min: PlaceRank) -> Self {
+        Self {
+            min,
+            max: PlaceRank::City,
+        }
+    }
+
+    fn accepts(self, place: &PlaceNode) -> bool {
+        (self.min..=self.max).contains(&place.rank)
+    }
+}
+
+/// Items bucketed into square lon/lat cells of `cell_size_deg` degrees.
+struct SpatialGrid<T> {
+    cells: HashMap<(i32, i32), Vec<T>>,
+    cell_size_deg: f64,
+}
+
+impl<T> SpatialGrid<T> {
+    fn new(cell_size_deg: f64) -> Self {
+        Self {
+            cells: HashMap::new(),
+            cell_size_deg,
+        }
+    }
+
+    fn insert(&mut self, coord: (f64, f64), item: T) {
+        let cell = self.cell_for(coord);
+        self.cells.entry(cell).or_default().push(item);
+    }
+
+    /// Items in every cell that overlaps the `radius_km` window around
+    /// `coord`. This is a superset of the items within `radius_km`; callers
+    /// apply their own exact distance test.
+    fn neighbors(&self, coord: (f64, f64), radius_km: f64) -> impl Iterator<Item = &T> + '_ {
+        let (delta_lon, delta_lat) = search_window_deg(coord.1, radius_km);
+        let (min_lat, max_lat) = (coord.1 - delta_lat, coord.1 + delta_lat);
+        let (min_lon, max_lon) = (coord.0 - delta_lon, coord.0 + delta_lon);
+        // A window crossing the antimeridian is split into the part on
+        // each side of it.
+        let spans = if max_lon - min_lon >= 360.0 {
+            [Some((-180.0, 180.0)), None]
+        } else if min_lon < -180.0 {
+            [Some((-180.0, max_lon)), Some((min_lon + 360.0, 180.0))]
+        } else if max_lon > 180.0 {
+            [Some((min_lon, 180.0)), Some((-180.0, max_lon - 360.0))]
+        } else {
+            [Some((min_lon, max_lon)), None]
+        };
+        spans
+            .into_iter()
+            .flatten()
+            .flat_map(move |(west, east)| self.in_box((west, min_lat), (east, max_lat)))
+    }
+
+    /// The cells of `neighbors(coord, radius_km)` in rings around the cell
+    /// of `coord`, nearest ring first. Each ring comes with a lower bound in
+    /// km on the distance from `coord` to any item in it or in a later ring,
+    /// so a nearest-item search can stop once that passes its best hit.
+    fn rings(
+        &self,
+        coord: (f64, f64),
+        radius_km: f64,
+    ) -> impl Iterator<Item = (f64, impl Iterator<Item = &T> + '_)> + '_ {
+        let size = self.cell_size_deg;
+        let (delta_lon, delta_lat) = search_window_deg(coord.1, radius_km);
+        let center = self.cell_for(coord);
+        // Columns around the globe. Offsets wrap across the antimeridian
+        // and stop short of reaching the same column from both sides.
+        let first_column = (-180.0 / size).floor() as i32;
+        let last_column = (180.0 / size).ceil() as i32 - 1;
+        let columns = last_column - first_column + 1;
+        // The last column is narrower unless the size divides 360, so
+        // allow one spare column each side and one column less of gap.
+        let narrow = i32::from((360.0 / size).fract() != 0.0);
+        let west = self.cell_for((coord.0 - delta_lon, coord.1)).0 - center.0 - 1;
+        let west = west.max(-(columns - 1) / 2);
+        let east = self.cell_for((coord.0 + delta_lon, coord.1)).0 - center.0 + 1;
+        let east = east.min(columns / 2);
+        let south = self.cell_for((coord.0, coord.1 - delta_lat)).1 - center.1;
+        let north = self.cell_for((coord.0, coord.1 + delta_lat)).1 - center.1;
+        let last_ring = (-west).max(east).max(-south).max(north);
+        // Longitude degrees are shortest at the window's highest latitude.
+        let lowest = f64::from(center.1 + south) * size;
+        let highest = f64::from(center.1 + north + 1) * size;
+        let top_lat = lowest.abs().max(highest.abs());
+        let lon_km_per_deg = EARTH_RADIUS_KM.to_radians() * top_lat.min(90.0).to_radians().cos();
+        (0..=last_ring).map(move |ring| {
+            let lat_gap_km = f64::from((ring - 1).max(0)) * size * EARTH_RADIUS_KM.to_radians();
+            let lon_gap_km = f64::from((ring - 1 - narrow).max(0)) * size * lon_km_per_deg;
+            let sides = if ring == 0 { 1 } else { 2 };
+            let rows = [-ring, ring]
+                .into_iter()
+                .take(sides)
+                .filter(move |dy| (south..=north).contains(dy))
+                .flat_map(move |dy| (west.max(-ring)..=east.min(ring)).map(move |dx| (dx, dy)));
+            let columns_between = south.max(1 - ring)..=north.min(ring - 1);
+            let side_columns = [-ring, ring]
+                .into_iter()
+                .take(sides)
+                .filter(move |dx| (west..=east).contains(dx))
+                .flat_map(move |dx| columns_between.clone().map(move |dy| (dx, dy)));
+            let items = rows
+                .chain(side_columns)
+                .filter_map(move |(dx, dy)| {
+                    let mut x = center.0 + dx;
+                    if x > last_column {
+                        x -= columns;
+                    } else if x < first_column {
+                        x += columns;
+                    }
+                    self.cells.get(&(x, center.1 + dy))
+                })
+                .flatten();
+            (lat_gap_km.min(lon_gap_km), items)
+        })
+    }
+
+    /// Items in every cell that overlaps the lon/lat box from `min` to `max`.
+    fn in_box(&self, min: (f64, f64), max: (f64, f64)) -> impl Iterator<Item = &T> + '_ {
+        let min_cell = self.cell_for(min);
+        let max_cell = self.cell_for(max);
+        (min_cell.0..=max_cell.0)
+            .flat_map(move |x| (min_cell.1..=max_cell.1).map(move |y| (x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+    }
+
+    fn cell_for(&self, coord: (f64, f64)) -> (i32, i32) {
+        let x = (coord.0 / self.cell_size_deg).floor() as i32;
+        let y = (coord.1 / self.cell_size_deg).floor() as i32;
+        (x, y)
+    }
+}
+
+/// Half-widths in degrees (lon, lat) of a box covering `radius_km` around a
+/// point at `lat`; longitude degrees shrink with `cos(lat)`.
+fn search_window_deg(lat: f64, radius_km: f64) -> (f64, f64) {
+    let cos_lat = lat.to_radians().cos().abs();
+    let delta_lat = radius_km / 111.0;
+    let delta_lon = if cos_lat < 1e-6 {
+        180.0
+    } else {
+        radius_km / (111.0 * cos_lat)
+    };
+    (delta_lon, delta_lat)
+}
+
+struct PlaceIndex {
+    places: Vec<PlaceNode>,
+    grid: SpatialGrid<usize>,
+    radii: PlaceRadii,
+    /// Places ranked below this are never matched.
+    min_rank: PlaceRank,
+    /// Set with `ExtractOptions::prefer_places_in_boundary`.
+    boundaries: Option<BoundaryIndex>,
+    match_cache: PlaceMatchCache,
+}
+
+/// How much nearer a place counts when it lies in the same boundary as the
+/// point being matched; see [`PlaceIndex::preferred_boundary`].
+const BOUNDARY_PLACE_BOOST: f64 = 0.5;
+
+/// Administrative boundaries, smallest first, and the boundaries each
+/// place node of a [`PlaceIndex`] lies in.
+struct BoundaryIndex {
+    boundaries: Vec<Boundary>,
+    /// Box around each boundary's rings, for a cheap rejection.
+    bounds: Vec<BoundingBox>,
+    place_in: Vec<Vec<usize>>,
+}
+
+impl BoundaryIndex {
+    fn new(mut boundaries: Vec<Boundary>, places: &[PlaceNode]) -> Self {
+        boundaries.sort_by(|a, b| a.area_km2.total_cmp(&b.area_km2));
+        let bounds = boundaries
+            .iter()
+            .map(|boundary| {
+                let mut bounds = BoundingBox {
+                    min_lon: f64::INFINITY,
+                    min_lat: f64::INFINITY,
+                    max_lon: f64::NEG_INFINITY,
+                    max_lat: f64::NEG_INFINITY,
+                };
+                for (lon, lat) in boundary.rings.iter().flatten() {
+                    bounds.min_lon = bounds.min_lon.min(*lon);
+                    bounds.min_lat = bounds.min_lat.min(*lat);
+                    bounds.max_lon = bounds.max_lon.max(*lon);
+                    bounds.max_lat = bounds.max_lat.max(*lat);
+                }
+                bounds
+            })
+            .collect();
+        let mut index = Self {
+            boundaries,
+            bounds,
+            place_in: Vec::new(),
+        };
+        let place_in = places.iter().map(|place| index.containing(place.coord).collect());
+        index.place_in = place_in.collect();
+        index
+    }
+
+    /// Whether a boundary named `name` lies around `point`.
+    fn encloses_named(&self, point: (f64, f64), name: &str) -> bool {
+        self.containing(point).any(|idx| self.boundaries[idx].name == name)
+    }
+
+    /// The boundaries around `point`, smallest first.
+    fn containing(&self, point: (f64, f64)) -> impl Iterator<Item = usize> + '_ {
+        let shapes = self.boundaries.iter().zip(&self.bounds).enumerate();
+        shapes.filter_map(move |(idx, (boundary, bounds))| {
+            let inside = bounds.contains(point)
+                && boundary.rings.iter().any(|ring| point_in_ring(point, ring));
+            inside.then_some(idx)
+        })
+    }
+}
+
+/// The places a point's city fields derive from, as indices into
+/// `PlaceIndex::places`: the nearest place and the nearest city or town.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct PlaceMatch {
+    place: Option<usize>,
+    city: Option<usize>,
+}
+
+/// Per-cell memo of [`PlaceMatch`]es, safe to share between threads.
+///
+/// A cell is only cached when its match is the same for every point in it;
+/// cells near the boundary between two places are stored as `None` and
+/// resolved per point.
+struct PlaceMatchCache {
+    cell_size_deg: f64,
+    cells: RwLock<HashMap<(i32, i32), Option<PlaceMatch>>>,
+}
+
+impl PlaceMatchCache {
+    fn new(cell_size_deg: f64) -> Self {
+        Self {
+            cell_size_deg,
+            cells: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn cell_for(&self, coord: (f64, f64)) -> (i32, i32) {
+        let x = (coord.0 / self.cell_size_deg).floor() as i32;
+        let y = (coord.1 / self.cell_size_deg).floor() as i32;
+        (x, y)
+    }
+
+    /// Upper bound in km on the distance from the center of a cell at `lat`
+    /// to any point in it, with headroom for the equirectangular
+    /// approximation.
+    fn slack_km(&self, lat: f64) -> f64 {
+        let half_lat_km = self.cell_size_deg * 111.0 / 2.0;
+        let half_lon_km = half_lat_km * lat.to_radians().cos().abs();
+        half_lat_km.hypot(half_lon_km) * 1.05
+    }
+}
+
+impl PlaceIndex {
+    fn new(places: Vec<PlaceNode>, cell_size_deg: f64) -> Self {
+        let mut grid = SpatialGrid::new(cell_size_deg);
+        for (idx, place) in places.iter().enumerate() {
+            grid.insert(place.coord, idx);
+        }
+        Self {
+            places,
+            grid,
+            radii: PlaceRadii::default(),
+            min_rank: PlaceRank::default(),
+            boundaries: None,
+            match_cache: PlaceMatchCache::new(PLACE_MATCH_CELL_DEG),
+        }
+    }
+
+    fn with_radii(self, radii: PlaceRadii) -> Self {
+        let match_cache = PlaceMatchCache::new(self.match_cache.cell_size_deg);
+        Self {
+            radii,
+            match_cache,
+            ..self
+        }
+    }
+
+    fn with_min_rank(self, min_rank: PlaceRank) -> Self {
+        let match_cache = PlaceMatchCache::new(self.match_cache.cell_size_deg);
+        Self {
+            min_rank,
+            match_cache,
+            ..self
+        }
+    }
+
+    fn with_boundaries(self, boundaries: Vec<Boundary>) -> Self {
+        let boundaries = BoundaryIndex::new(boundaries, &self.places);
+        Self {
+            boundaries: Some(boundaries),
+            ..self
+        }
+    }
+
+    /// The smallest boundary around `point` that also holds a place
+    /// `filter` accepts within its radius. Such places count as
+    /// `BOUNDARY_PLACE_BOOST` times their distance, so a street prefers a
+    /// place in its own municipality over a slightly nearer one across the
+    /// border. Larger boundaries (up to the country) are only used when no
+    /// smaller one holds a candidate.
+    fn preferred_boundary(
+        &self,
+        point: (f64, f64),
+        filter: PlaceFilter,
+    ) -> Option<(&BoundaryIndex, usize)> {
+        let boundaries = self.boundaries.as_ref()?;
+        let candidates: Vec<usize> = self
+            .grid
+            .neighbors(point, self.radii.max_km())
+            .copied()
+            .filter(|&idx| {
+                let place = &self.places[idx];
+                filter.accepts(place)
+                    && haversine_km(point, place.coord)
+                        <= self.radii.for_place_type(&place.place_type)
+            })
+            .collect();
+        let boundary = boundaries.containing(point).find(|boundary| {
+            candidates.iter().any(|idx| boundaries.place_in[*idx].contains(boundary))
+        })?;
+        Some((boundaries, boundary))
+    }
+
+    /// Cities and towns, or only cities under a `min_rank` of `city`.
+    fn city_filter(&self) -> PlaceFilter {
+        PlaceFilter::at_least(self.min_rank.max(PlaceRank::Town))
+    }
+
+    fn with_cell_size(places: Vec<PlaceNode>, cell_size: PlaceCellSize) -> Self {
+        let cell_size_deg = match cell_size {
+            PlaceCellSize::Fixed(size) => size,
+            PlaceCellSize::Auto => Self::auto_cell_size(&places),
+        };
+        Self::new(places, cell_size_deg)
+    }
+
+    /// Picks a cell size so that the places' bounding box holds roughly
+    /// `TARGET_PLACES_PER_CELL` places per cell, assuming uniform spread.
+    fn auto_cell_size(places: &[PlaceNode]) -> f64 {
+        if places.is_empty() {
+            return DEFAULT_PLACE_CELL_DEG;
+        }
+        let mut min = places[0].coord;
+        let mut max = places[0].coord;
+        for place in places {
+            min = (min.0.min(place.coord.0), min.1.min(place.coord.1));
+            max = (max.0.max(place.coord.0), max.1.max(place.coord.1));
+        }
+        let width = (max.0 - min.0).max(MIN_PLACE_CELL_DEG);
+        let height = (max.1 - min.1).max(MIN_PLACE_CELL_DEG);
+        let cells = (places.len() as f64 / TARGET_PLACES_PER_CELL).max(1.0);
+        (width * height / cells)
+            .sqrt()
+            .clamp(MIN_PLACE_CELL_DEG, MAX_PLACE_CELL_DEG)
+    }
+
+    fn nearest(&self, point: (f64, f64), filter: PlaceFilter) -> Option<&PlaceNode> {
+        self.nearest_within(point, filter, 0.0)
+            .0
+            .map(|idx| &self.places[idx])
+    }
+
+    /// The index of the nearest place to `point`, and whether that answer
+    /// holds for every point within `slack_km` of it: the winner stays in
+    /// its radius and no other place can come closer or into range.
+    fn nearest_within(
+        &self,
+        point: (f64, f64),
+        filter: PlaceFilter,
+        slack_km: f64,
+    ) -> (Option<usize>, bool) {
+        let (lon, lat) = point;
+        let lat_rad = lat.to_radians();
+        let lon_rad = lon.to_radians();
+        let cos_lat = lat_rad.cos().abs();
+
+        let preferred = self.preferred_boundary(point, filter);
+        let mut best: Option<(usize, f64)> = None;
+        // Closest distance of a place that could be in range somewhere in
+        // the slack, other than the current best.
+        let mut runner_up = f64::INFINITY;
+        let mut best_has_margin = true;
+        // Places in the preferred boundary count for less than their
+        // distance, so no ring is out of reach until this much farther.
+        let boost = if preferred.is_some() { BOUNDARY_PLACE_BOOST } else { 1.0 };
+        for (ring_km, ring) in self.grid.rings(point, self.radii.max_km() + slack_km) {
+            // Nothing from here on can win or come within the slack of
+            // the winner.
+            if best.is_some_and(|(_, distance)| ring_km * boost - slack_km > distance + slack_km) {
+                break;
+            }
+            for &idx in ring {
+                let place = &self.places[idx];
+                if !filter.accepts(place) {
+                    continue;
+                }
+                let distance = equirectangular_km(
+                    lon_rad,
+                    lat_rad,
+                    cos_lat,
+                    place.lon_rad,
+                    place.lat_rad,
+                    place.cos_lat,
+                );
+                let radius = self.radii.for_place_type(&place.place_type);
+                if distance - slack_km > radius {
+                    continue;
+                }
+                if distance > radius {
+                    runner_up = runner_up.min(distance);
+                    continue;
+                }
+                let distance = match preferred {
+                    Some((boundaries, boundary))
+                        if boundaries.place_in[idx].contains(&boundary) =>
+                    {
+                        distance * BOUNDARY_PLACE_BOOST
+                    }
+                    _ => distance,
+                };
+                match best {
+                    Some((_, best_distance)) if distance >= best_distance => {
+                        runner_up = runner_up.min(distance);
+                    }
+                    _ => {
+                        if let Some((_, previous)) = best {
+                            runner_up = runner_up.min(previous);
+                        }
+                        best = Some((idx, distance));
+                        best_has_margin = distance + slack_km <= radius;
+                    }
+                }
+            }
+        }
+        let stable = match best {
+            Some((_, distance)) => best_has_margin && runner_up - slack_km > distance + slack_km,
+            None => runner_up.is_infinite(),
+        };
+        (best.map(|(idx, _)| idx), stable)
+    }
+
+    /// The nearest place and city or town around `point`, and whether they
+    /// hold within `slack_km` of it.
+    fn place_match_within(&self, point: (f64, f64), slack_km: f64) -> (PlaceMatch, bool) {
+        let place_filter = PlaceFilter::at_least(self.min_rank);
+        let (place, place_stable) = self.nearest_within(point, place_filter, slack_km);
+        let (city, city_stable) = match place {
+            Some(idx) if self.city_filter().accepts(&self.places[idx]) => (Some(idx), true),
+            Some(_) => self.nearest_within(point, self.city_filter(), slack_km),
+            None => (None, true),
+        };
+        (PlaceMatch { place, city }, place_stable && city_stable)
+    }
+
+    /// Same as `place_match_within(point, 0.0)`, but reuses the answer for
+    /// every point in a cell of `match_cache` where it cannot change.
+    fn place_match(&self, point: (f64, f64)) -> PlaceMatch {
+        // Boundary edges do not follow cache cells.
+        if self.boundaries.is_some() {
+            return self.place_match_within(point, 0.0).0;
+        }
+        let cache = &self.match_cache;
+        let cell = cache.cell_for(point);
+        let cells = cache.cells.read().unwrap_or_else(|err| err.into_inner());
+        let cached = cells.get(&cell).copied();
+        drop(cells);
+        let cell_match = match cached {
+            Some(cell_match) => cell_match,
+            None => {
+                let center = (
+                    (cell.0 as f64 + 0.5) * cache.cell_size_deg,
+                    (cell.1 as f64 + 0.5) * cache.cell_size_deg,
+                );
+                let slack_km = cache.slack_km(center.1);
+                let (cell_match, stable) = self.place_match_within(center, slack_km);
+                let cell_match = stable.then_some(cell_match);
+                cache
+                    .cells
+                    .write()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .insert(cell, cell_match);
+                cell_match
+            }
+        };
+        cell_match.unwrap_or_else(|| self.place_match_within(point, 0.0).0)
+    }
+}
+
+fn equirectangular_km(
+    lon1: f64,
+    lat1: f64,
+    cos_lat1: f64,
+    lon2: f64,
+    lat2: f64,
+    cos_lat2: f64,
+) -> f64 {
+    let avg_cos = (cos_lat1 + cos_lat2) * 0.5;
+    // Take the short way around across the antimeridian.
+    let mut delta_lon = lon2 - lon1;
+    if delta_lon > std::f64::consts::PI {
+        delta_lon -= std::f64::consts::TAU;
+    } else if delta_lon < -std::f64::consts::PI {
+        delta_lon += std::f64::consts::TAU;
+    }
+    let x = delta_lon * avg_cos;
+    let y = lat2 - lat1;
+    EARTH_RADIUS_KM * (x * x + y * y).sqrt()
+}
+
+fn is_in_city(tags: &Tags) -> Option<String> {
+    for key in ["is_in:city", "is_in:town", "is_in:municipality", "is_in:locality"] {
+        if let Some(value) = tags.get(key) {
+            if !value.is_empty() {
+                return Some(value.to_string());
+            }
+        }
+    }
+    if let Some(value) = tags.get("is_in") {
+        let parts: Vec<&str> = value.split(',').map(|part| part.trim()).collect();
+        if let Some(first) = parts.first() {
+            if !first.is_empty() {
+                return Some(first.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// `is_in` tokens naming a continent rather than a country.
+const CONTINENTS: [&str; 8] = [
+    "Africa",
+    "Antarctica",
+    "Asia",
+    "Europe",
+    "North America",
+    "Oceania",
+    "South America",
+    "Australia and Oceania",
+];
+
+/// Region and country from the explicit `is_in:*` keys, falling back to
+/// the `is_in` list after its first (city) token: the next token is taken
+/// as the region and the last one that is not a continent as the country.
+fn is_in_region_country(tags: &Tags) -> (String, String) {
+    let explicit = |keys: &[&str]| {
+        keys.iter()
+            .filter_map(|key| tags.get(*key))
+            .find(|value| !value.is_empty())
+            .map(|value| value.to_string())
+    };
+    let parts: Vec<&str> = tags
+        .get("is_in")
+        .map(|value| value.split(',').map(str::trim).filter(|part| !part.is_empty()).collect())
+        .unwrap_or_default();
+    let rest = parts.get(1..).unwrap_or_default();
+    let region = explicit(&["is_in:region", "is_in:state", "is_in:province", "is_in:county"])
+        .or_else(|| rest.first().map(|part| part.to_string()))
+        .unwrap_or_default();
+    let country = explicit(&["is_in:country"])
+        .or_else(|| {
+            rest.iter()
+                .rev()
+                .find(|part| !CONTINENTS.iter().any(|name| name.eq_ignore_ascii_case(part)))
+                .map(|part| part.to_string())
+        })
+        .unwrap_or_default();
+    (region, country)
+}
+
+fn resolve_first_non_empty(values: &[Option<&str>]) -> Option<String> {
+    values
+        .iter()
+        .find_map(|value| value.and_then(|text| if text.is_empty() { None } else { Some(text) }))
+        .map(|value| value.to_string())
+}
+
+/// Splits a `;`-separated multi-value tag (`residential;service`) into its
+/// first non-empty token and the remaining ones.
+fn split_multi_value(value: &str) -> (&str, Vec<&str>) {
+    let mut tokens = value.split(';').map(str::trim).filter(|token| !token.is_empty());
+    let first = tokens.next().unwrap_or("");
+    (first, tokens.collect())
+}
+
+/// Multi-value tags are classified by their first token only, so
+/// `highway=bus_stop;platform` counts as a bus stop and
+/// `highway=residential;service` as a residential street.
+fn has_tag_value(tags: &Tags, key: &str, values: &[&str]) -> bool {
+    tags.get(key)
+        .map(|value| values.contains(&split_multi_value(value).0))
+        .unwrap_or(false)
+}
+
+fn has_tag(tags: &Tags, key: &str) -> bool {
+    tags.get(key).map(|value| !value.is_empty()).unwrap_or(false)
+}
+
+fn is_airport(tags: &Tags) -> bool {
+    has_tag_value(tags, "aeroway", &["aerodrome", "airport", "terminal"])
+}
+
+fn is_train_station(tags: &Tags) -> bool {
+    has_tag_value(tags, "railway", &["station", "halt"])
+        || has_tag_value(tags, "public_transport", &["station"])
+}
+
+fn is_bus_stop(tags: &Tags) -> bool {
+    has_tag_value(tags, "highway", &["bus_stop"])
+        || has_tag_value(tags, "public_transport", &["platform", "stop_position"])
+}
+
+fn is_ferry_terminal(tags: &Tags) -> bool {
+    has_tag_value(tags, "amenity", &["ferry_terminal"])
+        || has_tag_value(tags, "seamark:type", &["ferry_terminal"])
+}
+
+fn is_university(tags: &Tags) -> bool {
+    has_tag_value(tags, "amenity", &["university", "college"])
+        || has_tag_value(tags, "education", &["university", "college"])
+        || has_tag_value(tags, "building", &["university", "college"])
+}
+
+fn is_museum(tags: &Tags) -> bool {
+    has_tag_value(tags, "tourism", &["museum"])
+        || has_tag_value(tags, "amenity", &["museum"])
+        || has_tag_value(tags, "building", &["museum"])
+}
+
+fn is_civic_building(tags: &Tags) -> bool {
+    has_tag_value(
+        tags,
+        "amenity",
+        &[
+            "library",
+            "theatre",
+            "arts_centre",
+            "community_centre",
+            "townhall",
+            "courthouse",
+            "public_building",
+        ],
+    ) || has_tag_value(
+        tags,
+        "building",
+        &[
+            "library",
+            "theatre",
+            "arts_centre",
+            "community_centre",
+            "townhall",
+            "courthouse",
+            "public",
+        ],
+    ) || has_tag_value(tags, "tourism", &["gallery"])
+        || has_tag_value(tags, "office", &["government"])
+}
+
+fn has_wiki_tag(tags: &Tags) -> bool {
+    tags.contains_key("wikipedia") || tags.contains_key("wikidata")
+}
+
+fn is_major_sight(tags: &Tags) -> bool {
+    if !has_name_tags(tags) {
+        return false;
+    }
+    if !has_wiki_tag(tags) {
+        return false;
+    }
+    let has_tourism = has_tag(tags, "tourism");
+    let has_historic = has_tag(tags, "historic");
+    let has_man_made = has_tag(tags, "man_made");
+    let has_landmark = has_tag(tags, "landmark");
+    let has_tower = has_tag(tags, "tower:type");
+    has_tourism || has_historic || has_man_made || has_landmark || has_tower
+}
+
+/// A named `building=*` that is not `building=no`. With `need_wiki`, a
+/// `wikipedia` or `wikidata` tag is required too, which skips the many
+/// named but unremarkable buildings.
+fn is_named_building(tags: &Tags, need_wiki: bool) -> bool {
+    let is_building = tags.get("building").is_some_and(|value| value != "no");
+    if !is_building || !has_name_tags(tags) {
+        return false;
+    }
+    !need_wiki || has_wiki_tag(tags)
+}
+
+/// A named `natural=*` feature (peak, bay, cape, water, ...) or a
+/// `place=island`/`islet`, which are not settlements and so not place
+/// nodes.
+fn is_natural_feature(tags: &Tags) -> bool {
+    has_name_tags(tags)
+        && (has_tag(tags, "natural") || has_tag_value(tags, "place", &["island", "islet"]))
+}
+
+/// Key prefixes (`disused:railway=station`) and `=yes` flags
+/// (`disused=yes`) marking a feature as no longer in use.
+const LIFECYCLE_PREFIXES: [&str; 5] = ["disused", "abandoned", "was", "razed", "demolished"];
+
+/// The tags the POI predicates look at. Out-of-use features (`disused=yes`
+/// and the like) give `None` unless `include_disused` is set, in which case
+/// lifecycle-prefixed keys also stand in for their plain key.
+fn poi_tag_view<'t>(tags: &'t Tags, options: &ExtractOptions) -> Option<Cow<'t, Tags>> {
+    if !options.include_disused {
+        let out_of_use = LIFECYCLE_PREFIXES.iter().any(|key| has_tag_value(tags, key, &["yes"]));
+        return (!out_of_use).then_some(Cow::Borrowed(tags));
+    }
+    let mut view = Cow::Borrowed(tags);
+    for (key, value) in tags.iter() {
+        let Some((prefix, plain)) = key.split_once(':') else {
+            continue;
+        };
+        if LIFECYCLE_PREFIXES.contains(&prefix) && !tags.contains_key(plain) {
+            view.to_mut().insert(plain.into(), value.clone());
+        }
+    }
+    Some(view)
+}
+
+fn poi_kind(tags: &Tags, options: &ExtractOptions) -> Option<&'static str> {
+    let view = poi_tag_view(tags, options)?;
+    let tags = view.as_ref();
+    if !has_name_tags(tags) {
+        return None;
+    }
+    if options.only_with_wiki && !has_wiki_tag(tags) {
+        return None;
+    }
+    if is_airport(tags) {
+        return Some("airport");
+    }
+    if is_train_station(tags) {
+        return Some("train_station");
+    }
+    if is_bus_stop(tags) {
+        return Some("bus_stop");
+    }
+    if is_ferry_terminal(tags) {
+        return Some("ferry_terminal");
+    }
+    if is_university(tags) {
+        return Some("university");
+    }
+    if is_museum(tags) {
+        return Some("museum");
+    }
+    if is_civic_building(tags) {
+        return Some("civic_building");
+    }
+    if is_major_sight(tags) {
+        return Some("sight");
+    }
+    if options.include_natural && is_natural_feature(tags) {
+        return Some("natural");
+    }
+    let need_wiki = options.named_buildings_need_wiki;
+    if options.include_named_buildings && is_named_building(tags, need_wiki) {
+        return Some("building");
+    }
+    None
+}
+
+fn is_poi(tags: &Tags, options: &ExtractOptions) -> bool {
+    poi_kind(tags, options).is_some()
+}
+
+/// `city_confidence` of each `city_resolved` source, in resolution order.
+/// A tagged `addr:city`/`addr:place` is trusted fully and a free-form
+/// `is_in` less so. See [`nearest_place_confidence`] for cities inferred
+/// from place nodes, and [`boundary_confidence`] for cities an enclosing
+/// boundary confirms.
+const CONFIDENCE_ADDR: f64 = 1.0;
+const CONFIDENCE_BOUNDARY: f64 = 0.9;
+const CONFIDENCE_IS_IN: f64 = 0.6;
+const CONFIDENCE_NEAREST_PLACE: f64 = 0.4;
+
+/// Confidence that `place`, `distance_km` away, names the feature's city:
+///
+/// `0.4 × rank × (1 − distance_km / radius_km)`, floored at 0,
+///
+/// where `radius_km` is the association radius of the place's type and
+/// `rank` is 1 for a city or town and 0.75 for smaller places, whose name
+/// is a weaker stand-in for the city.
+fn nearest_place_confidence(place: &PlaceNode, distance_km: f64, radii: PlaceRadii) -> f64 {
+    let rank = if place.is_city_town { 1.0 } else { 0.75 };
+    let radius_km = radii.for_place_type(&place.place_type);
+    let decay = (1.0 - distance_km / radius_km).max(0.0);
+    CONFIDENCE_NEAREST_PLACE * rank * decay
+}
+
+/// `confidence` raised to [`CONFIDENCE_BOUNDARY`] when a boundary named
+/// `city` encloses `point`. Boundaries are only loaded under
+/// `prefer_places_in_boundary`; they confirm the resolved city but never
+/// pick it.
+fn boundary_confidence(
+    place_index: &PlaceIndex,
+    point: (f64, f64),
+    city: &str,
+    confidence: f64,
+) -> f64 {
+    let confirmed = place_index
+        .boundaries
+        .as_ref()
+        .is_some_and(|boundaries| !city.is_empty() && boundaries.encloses_named(point, city));
+    if confirmed {
+        confidence.max(CONFIDENCE_BOUNDARY)
+    } else {
+        confidence
+    }
+}
+
+/// The city fields of a feature at `center`, whose places `cached` holds
+/// (see [`PlaceIndex::place_match`]).
+fn resolve_city_fields(
+    tags: &Tags,
+    center: (f64, f64),
+    cached: PlaceMatch,
+    place_index: &PlaceIndex,
+) -> (String, String, String, String, String, f64) {
+    let city_addr = tags.get("addr:city");
+    let city_place = tags.get("addr:place");
+    let city = city_addr.or(city_place);
+    // Only the place-derived fields are cached; `addr:*` and `is_in` are
+    // read from each element's own tags.
+    let place_match = cached.place.map(|idx| &place_index.places[idx]);
+    let city_place_node = place_match.map(|place| place.name.clone());
+    let city_place_type = place_match.map(|place| place.place_type.clone());
+    let city_place_city_node = cached.city.map(|idx| &place_index.places[idx]);
+    let city_place_city = city_place_city_node.map(|place| place.name.clone());
+    let city_is_in = is_in_city(tags);
+    let place_confidence = |place: Option<&PlaceNode>| {
+        place.map_or(0.0, |place| {
+            let distance_km = haversine_km(center, place.coord);
+            nearest_place_confidence(place, distance_km, place_index.radii)
+        })
+    };
+    let sources = [
+        (city.map(|value| value.as_str()), CONFIDENCE_ADDR),
+        (city_is_in.as_deref(), CONFIDENCE_IS_IN),
+        (city_place_city.as_deref(), place_confidence(city_place_city_node)),
+        (city_place_node.as_deref(), place_confidence(place_match)),
+    ];
+    let (city_resolved, city_confidence) = sources
+        .into_iter()
+        .find(|(value, _)| value.is_some_and(|text| !text.is_empty()))
+        .map_or((None, 0.0), |(value, confidence)| (value.map(str::to_string), confidence));
+    let city_resolved = city_resolved.unwrap_or_default();
+    let city_confidence = boundary_confidence(place_index, center, &city_resolved, city_confidence);
+    let city_population = city_place_city_node
+        .and_then(|place| place.population)
+        .or_else(|| place_match.and_then(|place| place.population))
+        .map(|value: u64| value.to_string())
+        .unwrap_or_default();
+    (
+        city_place_node.unwrap_or_default(),
+        city_place_type.unwrap_or_default(),
+        city_place_city.unwrap_or_default(),
+        city_resolved,
+        city_population,
+        city_confidence,
+    )
+}
+
+/// The `suburb` column: `addr:suburb`, then `addr:district`, then the
+/// nearest `place=suburb` node. Kept apart from the city fields, which
+/// stay at town level.
+fn resolve_suburb(tags: &Tags, center: (f64, f64), place_index: &PlaceIndex) -> String {
+    let tagged = ["addr:suburb", "addr:district"]
+        .iter()
+        .filter_map(|key| tags.get(*key))
+        .find(|value| !value.is_empty());
+    match tagged {
+        Some(value) => value.to_string(),
+        None => place_index
+            .nearest(center, PlaceFilter::SUBURB)
+            .map(|place| place.name.clone())
+            .unwrap_or_default(),
+    }
+}
+
+fn city_fields_for_place(
+    place: &PlaceNode,
+    place_index: &PlaceIndex,
+) -> (String, String, String, String, String, f64) {
+    let city_place_node = place.name.clone();
+    let city_place_type = place.place_type.clone();
+    let city_place_city_node = if place.is_city_town {
+        Some(place)
+    } else {
+        place_index.nearest(place.coord, place_index.city_filter())
+    };
+    let city_place_city = city_place_city_node
+        .map(|city| city.name.clone())
+        .unwrap_or_default();
+    let city_resolved = if !city_place_city.is_empty() {
+        city_place_city.clone()
+    } else {
+        city_place_node.clone()
+    };
+    let city_confidence = match city_place_city_node {
+        Some(city) if std::ptr::eq(city, place) => CONFIDENCE_ADDR,
+        Some(city) => {
+            let distance_km = haversine_km(place.coord, city.coord);
+            nearest_place_confidence(city, distance_km, place_index.radii)
+        }
+        None => nearest_place_confidence(place, 0.0, place_index.radii),
+    };
+    let city_confidence =
+        boundary_confidence(place_index, place.coord, &city_resolved, city_confidence);
+    let city_population = city_place_city_node
+        .and_then(|city| city.population)
+        .or(place.population)
+        .map(|value| value.to_string())
+        .unwrap_or_default();
+    (
+        city_place_node,
+        city_place_type,
+        city_place_city,
+        city_resolved,
+        city_population,
+        city_confidence,
+    )
+}
+
+/// The `kind` of the row every place node becomes.
+fn place_row_kind(options: &ExtractOptions) -> &'static str {
+    if options.include_places {
+        "place"
+    } else {
+        "city"
+    }
+}
+
+fn add_place_entries(
+    place_nodes: &[PlaceNode],
+    place_index: &PlaceIndex,
+    entries: &mut Vec<StreetEntry>,
+    options: &ExtractOptions,
+) {
+    for place in place_nodes {
+        let (
+            city_place_node,
+            city_place_type,
+            city_place_city,
+            city_resolved,
+            city_population,
+            city_confidence,
+        ) = city_fields_for_place(place, place_index);
+        let entry = StreetEntry {
+            name: String::new(),
+            name_lang: String::new(),
+            aliases: Vec::new(),
+            kind: place_row_kind(options).to_string(),
+            center_lon: place.coord.0,
+            center_lat: place.coord.1,
+            length_km: 0.0,
+            area_km2: 0.0,
+            city_place_node,
+            city_place_type,
+            city_place_city,
+            city_resolved,
+            city_population,
+            city_confidence,
+            city_place_coord: Some(place.coord),
+            suburb: match place.place_type.as_str() {
+                "suburb" if options.suburb => place.name.clone(),
+                _ => String::new(),
+            },
+            addr_street: String::new(),
+            status: "active".to_string(),
+            ele_m: String::new(),
+            level: String::new(),
+            surface: String::new(),
+            smoothness: String::new(),
+            operator: String::new(),
+            brand: String::new(),
+            is_in_region: String::new(),
+            is_in_country: String::new(),
+            extra_tags: vec![String::new(); options.extra_tags.len()],
+            osm_version: String::new(),
+            osm_timestamp: String::new(),
+            segment_count: 1,
+            wikidata: String::new(),
+            wikipedia: String::new(),
+            node_count: 1,
+            endpoint_nodes: Vec::new(),
+            merged_from: Vec::new(),
+            name_source: String::new(),
+            geometry: Vec::new(),
+            chord_km: 0.0,
+        };
+        push_named_entries(entries, place.names.clone(), entry, options);
+    }
+}
+
+/// Builds the shared part of a feature's rows. The name fields are left
+/// empty for `push_named_entries`, the city fields for `resolve_features`.
+fn feature_entry(
+    tags: &Tags,
+    kind: &str,
+    center: (f64, f64),
+    meta: &ElementMeta,
+    options: &ExtractOptions,
+) -> StreetEntry {
+    let (osm_version, osm_timestamp) = meta.to_columns();
+    let extra_tags = options
+        .extra_tags
+        .iter()
+        .map(|key| tags.get(key.as_str()).map(|value| value.to_string()).unwrap_or_default())
+        .collect();
+    let street_tag = |key: &str| match tags.get(key) {
+        Some(value) if kind == "street" => value.to_string(),
+        _ => String::new(),
+    };
+    let poi_tag = |key: &str| match tags.get(key) {
+        Some(value) if kind != "street" => value.trim().to_string(),
+        _ => String::new(),
+    };
+    let (is_in_region, is_in_country) = is_in_region_country(tags);
+    StreetEntry {
+        name: String::new(),
+        name_lang: String::new(),
+        aliases: Vec::new(),
+        kind: kind.to_string(),
+        center_lon: center.0,
+        center_lat: center.1,
+        length_km: 0.0,
+        area_km2: 0.0,
+        city_place_node: String::new(),
+        city_place_type: String::new(),
+        city_place_city: String::new(),
+        city_resolved: String::new(),
+        city_population: String::new(),
+        city_confidence: 0.0,
+        city_place_coord: None,
+        suburb: String::new(),
+        addr_street: String::new(),
+        status: lifecycle_status(tags).to_string(),
+        ele_m: match tags.get("ele") {
+            Some(value) if kind != "street" => {
+                parse_ele_m(value).map(|meters| meters.to_string()).unwrap_or_default()
+            }
+            _ => String::new(),
+        },
+        level: ["level", "layer"]
+            .iter()
+            .find_map(|key| tags.get(*key).filter(|value| !value.is_empty()))
+            .map(|value| value.to_string())
+            .unwrap_or_default(),
+        surface: street_tag("surface"),
+        smoothness: street_tag("smoothness"),
+        operator: poi_tag("operator"),
+        brand: poi_tag("brand"),
+        is_in_region,
+        is_in_country,
+        extra_tags,
+        osm_version,
+        osm_timestamp,
+        segment_count: 1,
+        wikidata: poi_tag("wikidata"),
+        wikipedia: poi_tag("wikipedia"),
+        node_count: 1,
+        endpoint_nodes: Vec::new(),
+        merged_from: Vec::new(),
+        name_source: fallback_name(tags, options).map_or(String::new(), |(key, _)| key.to_string()),
+        geometry: Vec::new(),
+        chord_km: 0.0,
+    }
+}
+
+/// Parses an `ele` tag to meters, e.g. `324`, `324 m` or `1063 ft`.
+/// Unparsable values (`~300`, ranges) give `None`.
+fn parse_ele_m(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let (number, factor) = [("ft", 0.3048), ("'", 0.3048), ("m", 1.0)]
+        .iter()
+        .find_map(|(suffix, factor)| value.strip_suffix(suffix).map(|rest| (rest, *factor)))
+        .unwrap_or((value, 1.0));
+    let meters = number.trim().parse::<f64>().ok()? * factor;
+    meters.is_finite().then_some((meters * 10.0).round() / 10.0)
+}
+
+/// A named feature whose city fields are resolved once the place nodes of
+/// every input are known.
+struct PendingFeature {
+    tags: Tags,
+    names: Vec<NameVariant>,
+    entry: StreetEntry,
+    shape: FeatureShape,
+}
+
+/// What `dedup_poi_in_building` needs to know about a feature's geometry.
+enum FeatureShape {
+    Node,
+    /// Outline of a closed POI way tagged `building`; only kept when
+    /// `dedup_poi_in_building` is set.
+    Building(Vec<(f64, f64)>),
+    Other,
+}
+
+impl FeatureShape {
+    fn for_way(
+        tags: &Tags,
+        is_street: bool,
+        is_closed: bool,
+        coords: &[(f64, f64)],
+        options: &ExtractOptions,
+    ) -> Self {
+        let is_building = tags.get("building").is_some_and(|value| value != "no");
+        if options.dedup_poi_in_building && !is_street && is_closed && is_building {
+            FeatureShape::Building(coords.to_vec())
+        } else {
+            FeatureShape::Other
+        }
+    }
+}
+
+/// Grid cell size used to find POI nodes inside building outlines.
+const BUILDING_CELL_DEG: f64 = 0.01;
+
+/// Drops building ways that contain a named POI node: the node names the
+/// specific feature, the building only houses it.
+fn drop_buildings_with_poi_nodes(features: &mut Vec<PendingFeature>) {
+    let mut nodes = SpatialGrid::new(BUILDING_CELL_DEG);
+    for feature in features.iter() {
+        if matches!(feature.shape, FeatureShape::Node) {
+            let center = (feature.entry.center_lon, feature.entry.center_lat);
+            nodes.insert(center, center);
+        }
+    }
+    features.retain(|feature| {
+        let FeatureShape::Building(outline) = &feature.shape else {
+            return true;
+        };
+        let mut min = outline[0];
+        let mut max = outline[0];
+        for &(lon, lat) in outline {
+            min = (min.0.min(lon), min.1.min(lat));
+            max = (max.0.max(lon), max.1.max(lat));
+        }
+        !nodes.in_box(min, max).any(|&node| point_in_ring(node, outline))
+    });
+}
+
+/// Everything one input file contributes before city association.
+#[derive(Default)]
+struct CollectedInput {
+    place_nodes: Vec<PlaceNode>,
+    features: Vec<PendingFeature>,
+    /// Only collected when [`collects_boundaries`].
+    boundaries: Vec<Boundary>,
+}
+
+/// Builds one place index over all inputs and turns their places and
+/// features into rows.
+fn resolve_features(inputs: Vec<CollectedInput>, options: &ExtractOptions) -> Vec<StreetEntry> {
+    resolved_rows(inputs, options).collect()
+}
+
+/// Like [`resolve_features`], but resolves each feature's city fields and
+/// rows only when the iterator reaches it.
+fn resolved_rows(
+    inputs: Vec<CollectedInput>,
+    options: &ExtractOptions,
+) -> impl Iterator<Item = StreetEntry> + '_ {
+    let mut place_nodes = Vec::new();
+    let mut features = Vec::new();
+    let mut boundaries = Vec::new();
+    for input in inputs {
+        place_nodes.extend(input.place_nodes);
+        features.extend(input.features);
+        boundaries.extend(input.boundaries);
+    }
+    if options.dedup_poi_in_building {
+        drop_buildings_with_poi_nodes(&mut features);
+    }
+    let mut place_index = PlaceIndex::with_cell_size(place_nodes, options.place_cell_size)
+        .with_radii(options.place_radii)
+        .with_min_rank(options.min_place_rank);
+    if options.prefer_places_in_boundary {
+        place_index = place_index.with_boundaries(boundaries);
+    }
+    let mut place_rows: Vec<StreetEntry> = Vec::new();
+    add_place_entries(&place_index.places, &place_index, &mut place_rows, options);
+    let feature_rows = features.into_iter().flat_map(move |feature| {
+        let center = (feature.entry.center_lon, feature.entry.center_lat);
+        let place_match = place_index.place_match(center);
+        let (
+            city_place_node,
+            city_place_type,
+            city_place_city,
+            city_resolved,
+            city_population,
+            city_confidence,
+        ) = resolve_city_fields(&feature.tags, center, place_match, &place_index);
+        let city_place_coord = place_match.place.map(|idx| place_index.places[idx].coord);
+        let suburb = if options.suburb {
+            resolve_suburb(&feature.tags, center, &place_index)
+        } else {
+            String::new()
+        };
+        let entry = StreetEntry {
+            city_place_node,
+            city_place_type,
+            city_place_city,
+            city_resolved,
+            city_population,
+            city_confidence,
+            city_place_coord,
+            suburb,
+            ..feature.entry
+        };
+        let mut rows = Vec::new();
+        push_named_entries(&mut rows, feature.names, entry, options);
+        rows
+    });
+    place_rows.into_iter().chain(feature_rows)
+}
+
+/// Splits an element's names into its primary name (the first `NAME_KEYS`
+/// match) and the remaining distinct names as aliases.
+fn primary_and_aliases(names: Vec<NameVariant>) -> Option<(NameVariant, Vec<String>)> {
+    let mut names = names.into_iter();
+    let primary = names.next()?;
+    let mut seen: HashSet<String> = HashSet::from([primary.name.clone()]);
+    let aliases = names
+        .map(|variant| variant.name)
+        .filter(|name| seen.insert(name.clone()))
+        .collect();
+    Some((primary, aliases))
+}
+
+fn name_passes_filters(name: &str, options: &ExtractOptions) -> bool {
+    options.name_include.as_ref().is_none_or(|re| re.is_match(name))
+        && !options.name_exclude.as_ref().is_some_and(|re| re.is_match(name))
+}
+
+fn push_named_entries(
+    entries: &mut Vec<StreetEntry>,
+    names: Vec<NameVariant>,
+    entry: StreetEntry,
+    options: &ExtractOptions,
+) {
+    let names: Vec<NameVariant> = names
+        .into_iter()
+        .filter(|variant| name_passes_filters(&variant.name, options))
+        .collect();
+    if options.group_aliases {
+        if let Some((primary, aliases)) = primary_and_aliases(names) {
+            entries.push(StreetEntry {
+                name: primary.name,
+                name_lang: primary.lang,
+                aliases,
+                ..entry
+            });
+        }
+        return;
+    }
+    for name in names {
+        entries.push(StreetEntry {
+            name: name.name,
+            name_lang: name.lang,
+            ..entry.clone()
+        });
+    }
+}
+
+/// Fills `coords` with the coordinates of `refs`, reusing its allocation
+/// across ways. Stops and returns `false` at the first unresolved node.
+fn fill_way_coords<N>(
+    refs: &[N],
+    lookup: impl Fn(&N) -> Option<(f64, f64)>,
+    coords: &mut Vec<(f64, f64)>,
+) -> bool {
+    coords.clear();
+    for node in refs {
+        match lookup(node) {
+            Some(coord) => coords.push(coord),
+            None => return false,
+        }
+    }
+    true
+}
+
+/// How far apart (about 3 m) the ends of a way may be for it to still
+/// count as a ring, to absorb rounding and sloppy digitizing.
+const RING_CLOSURE_TOLERANCE_KM: f64 = 0.003;
+
+/// Whether a way is closed: its first and last node are the same, or
+/// (with at least four nodes) lie within `RING_CLOSURE_TOLERANCE_KM`.
+/// `coords` are the way's node coordinates, in `refs` order.
+fn is_ring<N: PartialEq>(refs: &[N], coords: &[(f64, f64)]) -> bool {
+    if refs.len() >= 2 && refs.first() == refs.last() {
+        return true;
+    }
+    match (coords.first(), coords.last()) {
+        (Some(&first), Some(&last)) if coords.len() >= 4 => {
+            haversine_km(first, last) <= RING_CLOSURE_TOLERANCE_KM
+        }
+        _ => false,
+    }
+}
+
+/// The first and last node ref of a way (one id for a closed way).
+fn way_endpoints<N: Copy + PartialEq>(refs: &[N]) -> Vec<N> {
+    match (refs.first(), refs.last()) {
+        (Some(first), Some(last)) if first != last => vec![*first, *last],
+        (Some(first), _) => vec![*first],
+        _ => Vec::new(),
+    }
+}
+
+/// Center, length and area derived from a way's coordinates.
+///
+/// A closed way is only treated as a polygon if it has no `highway` tag or
+/// is a highway area; closed linear ways such as roundabouts keep the line
+/// midpoint and their full length.
+struct WayGeometry {
+    center: (f64, f64),
+    length_km: f64,
+    area_km2: f64,
+    /// Enclosed area of a polygon, street or not; `None` for lines.
+    ring_area_km2: Option<f64>,
+    /// Straight-line distance between the ends of a street line; 0 for
+    /// polygons and POIs.
+    chord_km: f64,
+}
+
+impl WayGeometry {
+    /// A polygon enclosing less than `ExtractOptions::min_area_m2`, such as
+    /// a sliver whose nodes are nearly collinear.
+    fn is_below_min_area(&self, min_area_m2: Option<f64>) -> bool {
+        match (self.ring_area_km2, min_area_m2) {
+            (Some(area_km2), Some(min_area_m2)) => area_km2 * 1e6 < min_area_m2,
+            _ => false,
+        }
+    }
+
+    /// A street whose nodes all share one coordinate, which is almost
+    /// always a mapping error; see `ExtractOptions::drop_zero_length`.
+    fn is_collapsed_street(&self, is_street: bool) -> bool {
+        is_street && self.length_km == 0.0 && self.area_km2 == 0.0
+    }
+}
+
+fn way_geometry(
+    coords: &[(f64, f64)],
+    is_closed: bool,
+    is_street: bool,
+    tags: &Tags,
+    poi_length: PoiLength,
+) -> Option<WayGeometry> {
+    let is_polygon = is_closed && (!tags.contains_key("highway") || is_highway_area(tags));
+    if is_polygon {
+        // A ring needs three distinct points plus the closing one; smaller
+        // rings are judged by `is_below_min_area` instead.
+        if coords.len() < 4 {
+            return None;
+        }
+        let center = polygon_centroid(coords).ok()?;
+        let ring_area = ring_area_km2(coords);
+        // A street polygon is a highway area; a plaza's perimeter is not a
+        // meaningful street length.
+        let area_km2 = if is_street { ring_area } else { 0.0 };
+        let length_km = if is_street { 0.0 } else { poi_length.length_km(coords) };
+        Some(WayGeometry {
+            center,
+            length_km,
+            area_km2,
+            ring_area_km2: Some(ring_area),
+            chord_km: 0.0,
+        })
+    } else {
+        if coords.len() < 2 {
+            return None;
+        }
+        let center = line_midpoint(coords).ok()?;
+        let length_km = if is_street {
+            path_length_km(coords)
+        } else {
+            poi_length.length_km(coords)
+        };
+        let chord_km = if is_street {
+            haversine_km(coords[0], coords[coords.len() - 1])
+        } else {
+            0.0
+        };
+        Some(WayGeometry {
+            center,
+            length_km,
+            area_km2: 0.0,
+            ring_area_km2: None,
+            chord_km,
+        })
+    }
+}
+
+/// Scans `objs` for place nodes on rayon's pool. Each node is checked on
+/// its own, and `PlaceIndex` does not depend on the order of the places.
+fn collect_pbf_place_nodes(
+    objs: &BTreeMap<OsmId, OsmObj>,
+    options: &ExtractOptions,
+) -> Vec<PlaceNode> {
+    objs.par_iter()
+        .filter_map(|(_, obj)| match obj {
+            OsmObj::Node(node) => {
+                place_node_from_tags(&node.tags, (node.lon(), node.lat()), options)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Default, Clone)]
+struct WayData {
+    id: Option<i64>,
+    node_refs: Vec<i64>,
+    tags: Tags,
+    meta: ElementMeta,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum MemberType {
+    Node,
+    Way,
+    Relation,
+}
+
+impl MemberType {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "node" => Some(MemberType::Node),
+            "way" => Some(MemberType::Way),
+            "relation" => Some(MemberType::Relation),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+struct RelationData {
+    id: Option<i64>,
+    members: Vec<(MemberType, i64, String)>,
+    tags: Tags,
+    meta: ElementMeta,
+}
+
+#[derive(Clone)]
+struct PlaceNode {
+    name: String,
+    names: Vec<NameVariant>,
+    place_type: String,
+    coord: (f64, f64),
+    lat_rad: f64,
+    lon_rad: f64,
+    cos_lat: f64,
+    is_city_town: bool,
+    rank: PlaceRank,
+    population: Option<u64>,
+}
+
+impl PlaceNode {
+    fn new(
+        mut names: Vec<NameVariant>,
+        place_type: String,
+        coord: (f64, f64),
+        population: Option<u64>,
+    ) -> Self {
+        if names.is_empty() {
+            names.push(NameVariant {
+                name: "".to_string(),
+                lang: "".to_string(),
+            });
+        }
+        let name = names[0].name.clone();
+        let lat_rad = coord.1.to_radians();
+        let lon_rad = coord.0.to_radians();
+        let cos_lat = lat_rad.cos();
+        let is_city_town = is_city_or_town(&place_type);
+        let rank = PlaceRank::of(&place_type);
+        Self {
+            name,
+            names,
+            place_type,
+            coord,
+            lat_rad,
+            lon_rad,
+            cos_lat,
+            is_city_town,
+            rank,
+            population,
+        }
+    }
+}
+
+/// Writes the `--places-output` gazetteer: one row per place node, under
+/// its primary name.
+fn write_places<'p>(path: &Path, places: impl Iterator<Item = &'p PlaceNode>) -> Result<()> {
+    let mut writer = Writer::from_path(path)?;
+    writer.write_record(["name", "place_type", "lon", "lat", "is_city_town", "population"])?;
+    for place in places {
+        writer.write_record([
+            place.name.clone(),
+            place.place_type.clone(),
+            place.coord.0.to_string(),
+            place.coord.1.to_string(),
+            place.is_city_town.to_string(),
+            place.population.map(|population| population.to_string()).unwrap_or_default(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct NodeData {
+    id: Option<i64>,
+    coord: Option<(f64, f64)>,
+    tags: Tags,
+    meta: ElementMeta,
+}
+
+/// Edit metadata of an OSM element (`version`/`timestamp` attributes).
+#[derive(Default, Clone)]
+struct ElementMeta {
+    version: Option<u32>,
+    timestamp: Option<String>,
+}
+
+impl ElementMeta {
+    fn to_columns(&self) -> (String, String) {
+        (
+            self.version.map(|value| value.to_string()).unwrap_or_default(),
+            self.timestamp.clone().unwrap_or_default(),
+        )
+    }
+
+    fn date(&self) -> Option<(i32, u32, u32)> {
+        self.timestamp
+            .as_deref()
+            .and_then(|timestamp| timestamp.get(..10))
+            .and_then(parse_date)
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date into a comparable (year, month, day) tuple.
+pub fn parse_date(value: &str) -> Option<(i32, u32, u32)> {
+    let mut parts = value.split('-');
+    let year = parts.next()?;
+    let month = parts.next()?;
+    let day = parts.next()?;
+    if parts.next().is_some() || year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let date = (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    if !(1..=12).contains(&date.1) || !(1..=31).contains(&date.2) {
+        return None;
+    }
+    Some(date)
+}
+
+/// Applies `--after-date`: keeps elements edited on or after the cutoff day.
+/// Elements without a timestamp are kept unless `--drop-undated` is set.
+fn passes_date_filter(meta: &ElementMeta, options: &ExtractOptions) -> bool {
+    let Some(cutoff) = options.after_date else {
+        return true;
+    };
+    match meta.date() {
+        Some(date) => date >= cutoff,
+        None => !options.drop_undated,
+    }
+}
+
+/// One output row: a named street, POI or place with its center and the
+/// associated city fields.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreetEntry {
+    pub name: String,
+    pub name_lang: String,
+    /// Non-primary names, only filled when `group_aliases` is set.
+    pub aliases: Vec<String>,
+    pub kind: String,
+    pub center_lon: f64,
+    pub center_lat: f64,
+    pub length_km: f64,
+    pub area_km2: f64,
+    pub city_place_node: String,
+    pub city_place_type: String,
+    pub city_place_city: String,
+    pub city_resolved: String,
+    pub city_population: String,
+    /// How much to trust `city_resolved`, 0–1; see [`CONFIDENCE_ADDR`]
+    /// and [`nearest_place_confidence`].
+    pub city_confidence: f64,
+    /// Lon/lat of the place named in `city_place_node`.
+    pub city_place_coord: Option<(f64, f64)>,
+    /// Only filled when `ExtractOptions::suburb` is set.
+    pub suburb: String,
+    /// Street a POI is addressed on; empty for streets and places.
+    pub addr_street: String,
+    /// `proposed`, `construction` or `active`; see [`ExtractOptions::include_proposed`].
+    pub status: String,
+    /// A POI's `ele` in meters; see [`ExtractOptions::with_ele`].
+    pub ele_m: String,
+    /// `level` (or `layer`) tag; part of the merge key.
+    pub level: String,
+    /// `surface` and `smoothness` of a street; empty for POIs and places.
+    pub surface: String,
+    pub smoothness: String,
+    /// `operator` and `brand` of a POI; empty for streets and places.
+    pub operator: String,
+    pub brand: String,
+    /// Region and country parsed from the element's `is_in` tags.
+    pub is_in_region: String,
+    pub is_in_country: String,
+    /// Values of `ExtractOptions::extra_tags`, in the same order.
+    pub extra_tags: Vec<String>,
+    pub osm_version: String,
+    pub osm_timestamp: String,
+    pub segment_count: usize,
+    /// `wikidata` and `wikipedia` of a POI; empty for streets and places.
+    pub wikidata: String,
+    pub wikipedia: String,
+    /// OSM nodes behind the row (a way's node refs, 1 for nodes and
+    /// relation centers); summed when merging.
+    pub node_count: usize,
+    /// Ids of the end nodes of the way(s) behind the row; empty for nodes,
+    /// places and relations. Rows sharing one are merged regardless of
+    /// their center distance.
+    pub endpoint_nodes: Vec<i64>,
+    /// OSM elements behind the row, as `n<id>`, `w<id>` or `r<id>`; empty
+    /// for places. Concatenated when merging.
+    pub merged_from: Vec<String>,
+    /// Tag a name was taken from under `ExtractOptions::use_destination`
+    /// (`destination` or `junction:ref`); empty for regular name tags.
+    pub name_source: String,
+    /// Coordinates of the way(s) behind the row, one line per way. Only
+    /// kept for [`OutputFormat::Jsonl`]; concatenated when merging.
+    pub geometry: Vec<Vec<(f64, f64)>>,
+    /// Straight-line distance between the ends of the street way(s) behind
+    /// the row; 0 for closed ways, POIs and places. Summed when merging.
+    pub chord_km: f64,
+}
+
+/// `StreetEntry::geometry` of a way with `coords`, if the output needs it.
+fn way_geometry_lines(coords: &[(f64, f64)], options: &ExtractOptions) -> Vec<Vec<(f64, f64)>> {
+    match options.format {
+        OutputFormat::Jsonl => vec![coords.to_vec()],
+        _ => Vec::new(),
+    }
+}
+
+/// `merged_from` of a single element: `prefix` (`n`, `w` or `r`) and its
+/// id, or nothing if the id is unknown.
+fn element_ref(prefix: char, id: Option<i64>) -> Vec<String> {
+    id.map(|id| format!("{prefix}{id}")).into_iter().collect()
+}
+
+const METERS_PER_DEGREE_LAT: f64 = 111_320.0;
+
+/// Coarsens output centers, either to a number of decimal places or to a
+/// grid of roughly fixed size in meters.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CoordSnap {
+    Decimals(u32),
+    Meters(f64),
+}
+
+impl CoordSnap {
+    fn apply(self, (lon, lat): (f64, f64)) -> (f64, f64) {
+        match self {
+            CoordSnap::Decimals(places) => {
+                let factor = 10f64.powi(places as i32);
+                ((lon * factor).round() / factor, (lat * factor).round() / factor)
+            }
+            CoordSnap::Meters(meters) => {
+                let lat_step = meters / METERS_PER_DEGREE_LAT;
+                let lat = (lat / lat_step).round() * lat_step;
+                let cos_lat = lat.to_radians().cos().max(1e-6);
+                let lon_step = meters / (METERS_PER_DEGREE_LAT * cos_lat);
+                ((lon / lon_step).round() * lon_step, lat)
+            }
+        }
+    }
+}
+
+/// Unit of the optional length column.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LengthUnit {
+    Kilometers,
+    Miles,
+    Meters,
+}
+
+impl LengthUnit {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "km" => Ok(LengthUnit::Kilometers),
+            "mi" => Ok(LengthUnit::Miles),
+            "m" => Ok(LengthUnit::Meters),
+            _ => Err(ExtractError::bad_args(format!(
+                "invalid --length-unit: {value} (expected km, mi or m)"
+            ))),
+        }
+    }
+
+    fn header(self) -> &'static str {
+        match self {
+            LengthUnit::Kilometers => "length_km",
+            LengthUnit::Miles => "length_mi",
+            LengthUnit::Meters => "length_m",
+        }
+    }
+
+    fn convert_km(self, km: f64) -> f64 {
+        match self {
+            LengthUnit::Kilometers => km,
+            LengthUnit::Miles => km / 1.609_344,
+            LengthUnit::Meters => km * 1000.0,
+        }
+    }
+}
+
+/// File format of the extracted rows.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// One JSON array of objects keyed by the CSV header, with the same
+    /// (string) values.
+    Json,
+    /// One GeoJSON Feature per line (a feature sequence): the way geometry,
+    /// or the center as a Point, with the `Json` object as properties.
+    /// Geometries stay WGS84 whatever the `crs`.
+    Jsonl,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            _ => Err(ExtractError::bad_args(format!(
+                "invalid --format: {value} (expected csv, json or jsonl)"
+            ))),
+        }
+    }
+}
+
+/// Coordinate reference system of the center columns.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Crs {
+    /// EPSG:4326 degrees in `center_lon`/`center_lat`.
+    #[default]
+    Wgs84,
+    /// EPSG:3857 meters in `x`/`y`.
+    WebMercator,
+}
+
+/// Radius of the sphere Web Mercator projects onto, in meters.
+const WEB_MERCATOR_RADIUS_M: f64 = 6_378_137.0;
+/// Latitude where Web Mercator's square world ends.
+const WEB_MERCATOR_MAX_LAT: f64 = 85.051_128_779_806_59;
+
+impl Crs {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim_start_matches("EPSG:") {
+            "4326" => Ok(Crs::Wgs84),
+            "3857" => Ok(Crs::WebMercator),
+            _ => Err(ExtractError::bad_args(format!(
+                "invalid --crs: {value} (expected 4326 or 3857)"
+            ))),
+        }
+    }
+
+    fn center_headers(self) -> [&'static str; 2] {
+        match self {
+            Crs::Wgs84 => ["center_lon", "center_lat"],
+            Crs::WebMercator => ["x", "y"],
+        }
+    }
+
+    /// Projects a lon/lat point; latitudes beyond Web Mercator's range are
+    /// clamped to its edge.
+    fn project(self, (lon, lat): (f64, f64)) -> (f64, f64) {
+        match self {
+            Crs::Wgs84 => (lon, lat),
+            Crs::WebMercator => {
+                let lat = lat.clamp(-WEB_MERCATOR_MAX_LAT, WEB_MERCATOR_MAX_LAT).to_radians();
+                let x = WEB_MERCATOR_RADIUS_M * lon.to_radians();
+                let y = (std::f64::consts::FRAC_PI_4 + lat / 2.0).tan().ln();
+                (x, WEB_MERCATOR_RADIUS_M * y)
+            }
+        }
+    }
+
+    /// Inverse of [`Crs::project`].
+    fn unproject(self, (x, y): (f64, f64)) -> (f64, f64) {
+        match self {
+            Crs::Wgs84 => (x, y),
+            Crs::WebMercator => {
+                let lon = (x / WEB_MERCATOR_RADIUS_M).to_degrees();
+                let lat = 2.0 * (y / WEB_MERCATOR_RADIUS_M).exp().atan();
+                (lon, (lat - std::f64::consts::FRAC_PI_2).to_degrees())
+            }
+        }
+    }
+
+    /// Decimals for the center columns: meters are written to the
+    /// centimeter when degrees would have been rounded.
+    fn precision(self, degree_precision: Option<usize>) -> Option<usize> {
+        match self {
+            Crs::Wgs84 => degree_precision,
+            Crs::WebMercator => degree_precision.map(|_| 2),
+        }
+    }
+}
+
+/// Length reported for POIs mapped as ways.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum PoiLength {
+    /// The way's outline length.
+    Perimeter,
+    /// The diagonal of the way's bounding box.
+    Extent,
+    #[default]
+    Zero,
+}
+
+impl PoiLength {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "perimeter" => Ok(PoiLength::Perimeter),
+            "extent" => Ok(PoiLength::Extent),
+            "zero" => Ok(PoiLength::Zero),
+            _ => Err(ExtractError::bad_args(format!(
+                "invalid --poi-length: {value} (expected perimeter, extent or zero)"
+            ))),
+        }
+    }
+
+    fn length_km(self, coords: &[(f64, f64)]) -> f64 {
+        match self {
+            PoiLength::Perimeter => path_length_km(coords),
+            PoiLength::Extent => coords
+                .iter()
+                .skip(1)
+                .fold(ClusterBounds::new(coords[0]), |bounds, point| bounds.extended(*point))
+                .diagonal_km(),
+            PoiLength::Zero => 0.0,
+        }
+    }
+}
+
+/// How much each row pulls on the center of the row it is merged into.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum MergeWeight {
+    /// By street length; rows without a length (POIs, places) count as 1.
+    #[default]
+    Length,
+    /// By the number of OSM nodes behind the row.
+    Count,
+    /// Every row counts the same.
+    Equal,
+}
+
+impl MergeWeight {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "length" => Ok(MergeWeight::Length),
+            "count" => Ok(MergeWeight::Count),
+            "equal" => Ok(MergeWeight::Equal),
+            _ => Err(ExtractError::bad_args(format!(
+                "invalid --merge-weight: {value} (expected length, count or equal)"
+            ))),
+        }
+    }
+
+    fn weight(self, entry: &StreetEntry) -> f64 {
+        match self {
+            MergeWeight::Length if entry.length_km > 0.0 => entry.length_km,
+            MergeWeight::Count => entry.node_count.max(1) as f64,
+            MergeWeight::Length | MergeWeight::Equal => 1.0,
+        }
+    }
+}
+
+/// A lon/lat rectangle, inclusive on all edges.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl BoundingBox {
+    /// Parses `min_lon,min_lat,max_lon,max_lat`.
+    pub fn parse(value: &str) -> Result<Self> {
+        let parts: Vec<f64> = value
+            .split(',')
+            .map(|part| part.trim().parse::<f64>())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| ExtractError::bad_args(format!("invalid bbox: {value}")))?;
+        match parts[..] {
+            [min_lon, min_lat, max_lon, max_lat] if min_lon <= max_lon && min_lat <= max_lat => {
+                Ok(Self {
+                    min_lon,
+                    min_lat,
+                    max_lon,
+                    max_lat,
+                })
+            }
+            _ => Err(ExtractError::bad_args(format!(
+                "invalid bbox (expected min_lon,min_lat,max_lon,max_lat): {value}"
+            ))),
+        }
+    }
+
+    pub fn contains(&self, point: (f64, f64)) -> bool {
+        (self.min_lon..=self.max_lon).contains(&point.0)
+            && (self.min_lat..=self.max_lat).contains(&point.1)
+    }
+
+    /// The box around every node coordinate in `input_path`, for
+    /// `--bbox-from`. Only nodes are read: no rows are built or cities
+    /// resolved, and `options` only affects how coordinates are parsed.
+    pub fn from_input(input_path: &Path, options: &ExtractOptions) -> Result<Self> {
+        let mut bounds: Option<ClusterBounds> = None;
+        let mut extend = |coord: (f64, f64)| {
+            bounds = Some(match bounds {
+                Some(bounds) => bounds.extended(coord),
+                None => ClusterBounds::new(coord),
+            });
+        };
+        match input_path.extension().and_then(|value| value.to_str()) {
+            Some("osm" | "osc") => {
+                let mut reader = Reader::from_reader(BufReader::new(File::open(input_path)?));
+                reader.trim_text(true);
+                let mut swapped = 0;
+                // Nodes deleted by an osmChange are not part of the extent.
+                let mut in_delete = false;
+                let mut buf = Vec::new();
+                loop {
+                    match reader.read_event_into(&mut buf)? {
+                        Event::Eof => break,
+                        Event::Start(e) if e.local_name().as_ref() == b"delete" => in_delete = true,
+                        Event::End(e) if e.local_name().as_ref() == b"delete" => in_delete = false,
+                        Event::Start(e) | Event::Empty(e)
+                            if !in_delete && e.local_name().as_ref() == b"node" =>
+                        {
+                            if let Some(coord) = read_node_coord(&e, &mut swapped, options)? {
+                                extend(coord);
+                            }
+                        }
+                        _ => {}
+                    }
+                    buf.clear();
+                }
+            }
+            Some("pbf") | None => {
+                let mut pbf = OsmPbfReader::new(File::open(input_path)?);
+                for obj in pbf.iter() {
+                    if let OsmObj::Node(node) = obj? {
+                        extend((node.lon(), node.lat()));
+                    }
+                }
+            }
+            Some(_) => {
+                return Err(ExtractError::UnsupportedFormat(input_path.display().to_string()))
+            }
+        }
+        let bounds = bounds.ok_or_else(|| {
+            ExtractError::invalid_data(format!("no nodes in {}", input_path.display()))
+        })?;
+        Ok(Self {
+            min_lon: bounds.min.0,
+            min_lat: bounds.min.1,
+            max_lon: bounds.max.0,
+            max_lat: bounds.max.1,
+        })
+    }
+}
+
+/// A clip area loaded from GeoJSON: one or more polygons, each an outer ring
+/// followed by its holes, in (lon, lat) order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClipPolygon {
+    polygons: Vec<Vec<Vec<(f64, f64)>>>,
+}
+
+impl ClipPolygon {
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_geojson(&std::fs::read_to_string(path)?)
+    }
+
+    /// Accepts a bare Polygon/MultiPolygon geometry, a Feature wrapping one,
+    /// or a FeatureCollection with exactly one such feature.
+    pub fn from_geojson(text: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(text)?;
+        let geometry = match value["type"].as_str() {
+            Some("FeatureCollection") => match value["features"].as_array() {
+                Some(features) if features.len() == 1 => &features[0]["geometry"],
+                _ => return Err(ExtractError::invalid_data(
+                    "clip FeatureCollection must contain exactly one feature",
+                )),
+            },
+            Some("Feature") => &value["geometry"],
+            _ => &value,
+        };
+        Self::from_geometry(geometry)
+    }
+
+    fn from_geometry(geometry: &serde_json::Value) -> Result<Self> {
+        let coordinates = &geometry["coordinates"];
+        let polygons = match geometry["type"].as_str() {
+            Some("Polygon") => vec![parse_geojson_polygon(coordinates)?],
+            Some("MultiPolygon") => coordinates
+                .as_array()
+                .ok_or_else(|| {
+                    ExtractError::invalid_data("MultiPolygon coordinates must be an array")
+                })?
+                .iter()
+                .map(parse_geojson_polygon)
+                .collect::<Result<_>>()?,
+            other => {
+                return Err(ExtractError::invalid_data(format!(
+                    "clip geometry must be a Polygon or MultiPolygon, got {}",
+                    other.unwrap_or("nothing")
+                )))
+            }
+        };
+        Ok(Self { polygons })
+    }
+
+    /// Box around all outer rings, for a cheap rejection before `contains`.
+    fn bounds(&self) -> BoundingBox {
+        let mut bounds = BoundingBox {
+            min_lon: f64::INFINITY,
+            min_lat: f64::INFINITY,
+            max_lon: f64::NEG_INFINITY,
+            max_lat: f64::NEG_INFINITY,
+        };
+        for (lon, lat) in self.polygons.iter().flat_map(|rings| &rings[0]) {
+            bounds.min_lon = bounds.min_lon.min(*lon);
+            bounds.min_lat = bounds.min_lat.min(*lat);
+            bounds.max_lon = bounds.max_lon.max(*lon);
+            bounds.max_lat = bounds.max_lat.max(*lat);
+        }
+        bounds
+    }
+
+    /// True if `point` lies inside an outer ring and outside that
+    /// polygon's holes.
+    pub fn contains(&self, point: (f64, f64)) -> bool {
+        self.polygons.iter().any(|rings| {
+            let mut rings = rings.iter();
+            rings.next().is_some_and(|outer| point_in_ring(point, outer))
+                && rings.all(|hole| !point_in_ring(point, hole))
+        })
+    }
+}
+
+/// Property keys that may hold a feature's ISO 3166-1 alpha-2 code, in the
+/// order they are tried (Natural Earth, then common alternatives).
+const COUNTRY_CODE_KEYS: [&str; 4] = ["ISO_A2", "iso_a2", "ISO3166-1-Alpha-2", "iso"];
+
+/// Canonical spellings for `--rename-map`, applied to every name before
+/// merging so corrected names group together.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenameMap {
+    names: BTreeMap<String, String>,
+}
+
+impl RenameMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_csv(&std::fs::read_to_string(path)?)
+    }
+
+    /// Reads `from,to` rows; an optional `from,to` header and `#` comment
+    /// lines are skipped. A later row for the same `from` wins.
+    pub fn from_csv(text: &str) -> Result<Self> {
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_reader(text.as_bytes());
+        let mut names = BTreeMap::new();
+        for (line, record) in reader.records().enumerate() {
+            let record = record?;
+            match (record.get(0), record.get(1), record.len()) {
+                (Some("from"), Some("to"), 2) if line == 0 => {}
+                (Some(from), Some(to), 2) if !from.is_empty() && !to.is_empty() => {
+                    names.insert(from.to_string(), to.to_string());
+                }
+                _ => {
+                    return Err(ExtractError::invalid_data(format!(
+                        "rename map row {} is not from,to",
+                        line + 1
+                    )))
+                }
+            }
+        }
+        Ok(Self { names })
+    }
+
+    /// The canonical spelling of `name`, or `name` itself if unmapped.
+    pub fn rename(&self, name: String) -> String {
+        match self.names.get(&name) {
+            Some(canonical) => canonical.clone(),
+            None => name,
+        }
+    }
+}
+
+/// Country outlines for the optional `country_code` column, loaded from a
+/// GeoJSON FeatureCollection with one (Multi)Polygon feature per country.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CountryPolygons {
+    countries: Vec<(String, BoundingBox, ClipPolygon)>,
+}
+
+impl CountryPolygons {
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_geojson(&std::fs::read_to_string(path)?)
+    }
+
+    /// Features without a two-letter code under one of `COUNTRY_CODE_KEYS`
+    /// (e.g. Natural Earth's `-99`) are skipped.
+    pub fn from_geojson(text: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(text)?;
+        let features = value["features"]
+            .as_array()
+            .ok_or_else(|| {
+                ExtractError::invalid_data("country polygons must be a GeoJSON FeatureCollection")
+            })?;
+        let mut countries = Vec::new();
+        for feature in features {
+            let code = COUNTRY_CODE_KEYS
+                .iter()
+                .find_map(|key| feature["properties"][key].as_str())
+                .filter(|code| code.len() == 2 && code.chars().all(|ch| ch.is_ascii_alphabetic()));
+            let Some(code) = code else {
+                continue;
+            };
+            let polygon = ClipPolygon::from_geometry(&feature["geometry"])?;
+            countries.push((code.to_ascii_uppercase(), polygon.bounds(), polygon));
+        }
+        if countries.is_empty() {
+            return Err(ExtractError::invalid_data(
+                "country polygons contain no feature with an ISO alpha-2 code",
+            ));
+        }
+        Ok(Self { countries })
+    }
+
+    /// The code of the first country containing `point`.
+    pub fn code_for(&self, point: (f64, f64)) -> Option<&str> {
+        self.countries
+            .iter()
+            .find(|(_, bounds, polygon)| bounds.contains(point) && polygon.contains(point))
+            .map(|(code, _, _)| code.as_str())
+    }
+}
+
+fn parse_geojson_polygon(value: &serde_json::Value) -> Result<Vec<Vec<(f64, f64)>>> {
+    let rings = value
+        .as_array()
+        .ok_or_else(|| ExtractError::invalid_data("Polygon coordinates must be an array"))?;
+    let mut parsed = Vec::with_capacity(rings.len());
+    for ring in rings {
+        let mut coords = Vec::new();
+        let positions = ring
+            .as_array()
+            .ok_or_else(|| ExtractError::invalid_data("polygon ring must be an array"))?;
+        for position in positions {
+            match (position[0].as_f64(), position[1].as_f64()) {
+                (Some(lon), Some(lat)) => coords.push((lon, lat)),
+                _ => return Err(ExtractError::invalid_data("polygon position must be [lon, lat]")),
+            }
+        }
+        if coords.len() < 3 {
+            return Err(ExtractError::invalid_data("polygon ring needs at least 3 positions"));
+        }
+        parsed.push(coords);
+    }
+    if parsed.is_empty() {
+        return Err(ExtractError::invalid_data("polygon has no rings"));
+    }
+    Ok(parsed)
+}
+
+/// Knobs for the extraction pipeline; the defaults match the plain CLI run.
+#[derive(Clone, Debug, Default)]
+pub struct ExtractOptions {
+    pub with_metadata: bool,
+    pub place_cell_size: PlaceCellSize,
+    pub include_poi_relations: bool,
+    pub snap: Option<CoordSnap>,
+    pub length_unit: Option<LengthUnit>,
+    pub max_cluster_span_km: Option<f64>,
+    pub after_date: Option<(i32, u32, u32)>,
+    pub drop_undated: bool,
+    pub keep_noname: bool,
+    /// Memory-map `.pbf` input; see `read_pbf_objects` and the `pbf_read`
+    /// bench.
+    pub mmap: bool,
+    pub group_aliases: bool,
+    pub clip: Option<ClipPolygon>,
+    pub tolerant_numbers: bool,
+    /// Swap back `.osm` node coordinates whose lat and lon attributes look
+    /// exchanged (see `unswap_coord`), with a warning giving the count.
+    pub detect_swapped_coords: bool,
+    pub bbox: Option<BoundingBox>,
+    pub exclude_bboxes: Vec<BoundingBox>,
+    pub compact_coords: bool,
+    /// Tag keys written as extra columns, in this order.
+    pub extra_tags: Vec<String>,
+    /// Interval between throughput lines on stderr; `None` keeps quiet.
+    pub progress: Option<Duration>,
+    /// Wall-clock limit for reading and merging, after which the run fails
+    /// with [`ExtractError::Timeout`]; `None` never gives up.
+    pub timeout: Option<Duration>,
+    /// Length of the `geohash` column; `None` leaves the column out.
+    pub geohash_len: Option<usize>,
+    pub place_radii: PlaceRadii,
+    /// Write a `# schema_version=N` line before the CSV header.
+    pub schema_comment: bool,
+    /// Only names matching this pattern become rows.
+    pub name_include: Option<Regex>,
+    /// Names matching this pattern are dropped, after `name_include`.
+    pub name_exclude: Option<Regex>,
+    /// `(column, header)` renames applied to the written header only; the
+    /// column order and data are unchanged.
+    pub header_map: Vec<(String, String)>,
+    /// The [`output_columns`] to write, in this order; empty writes all of
+    /// them.
+    pub columns: Vec<String>,
+    pub merge_weight: MergeWeight,
+    /// Merge names that differ only in letter case, e.g. `Hauptstraße` and
+    /// `HAUPTSTRASSE`. The most common spelling is written.
+    pub merge_case_insensitive: bool,
+    /// Merge names that differ only in accents, e.g. `Rue de l'Église` and
+    /// `Rue de l'Eglise`. The most common spelling is written.
+    pub merge_accent_insensitive: bool,
+    /// Where the un-merged rows are saved and resumed from; see
+    /// [`extract_entries_from`].
+    pub checkpoint: Option<PathBuf>,
+    /// Outlines for the `country_code` column; `None` leaves it out.
+    pub countries: Option<CountryPolygons>,
+    /// Keep a `;` in name values as part of the name instead of splitting.
+    pub no_split_names: bool,
+    /// Name key precedence; empty uses [`NAME_KEYS`].
+    pub name_keys: Vec<String>,
+    pub format: OutputFormat,
+    /// Indent `OutputFormat::Json` output; meant for small extracts.
+    pub pretty: bool,
+    /// End `OutputFormat::Csv` records with `\r\n` instead of `\n`.
+    pub crlf: bool,
+    /// Gzip the output. Implied by an output path ending in `.gz`.
+    pub gzip: bool,
+    pub poi_length: PoiLength,
+    /// Move the center of a way to its vertex nearest the midpoint or
+    /// centroid, so that it lies on the way itself.
+    pub center_on_geometry: bool,
+    /// Skip street ways of zero length (all nodes at one coordinate).
+    pub drop_zero_length: bool,
+    /// Skip closed ways mapped as areas (plazas, POI outlines) enclosing
+    /// less than this many square meters.
+    pub min_area_m2: Option<f64>,
+    /// Skip street and POI ways with more nodes than this; defaults to
+    /// `DEFAULT_MAX_NODES_PER_WAY`.
+    pub max_nodes_per_way: Option<usize>,
+    /// Treat an output without data rows as an error.
+    pub fail_on_empty: bool,
+    /// Treat a node id repeated with a different coordinate in an `.osm`
+    /// file as an error instead of a warning.
+    pub strict: bool,
+    /// Add a `name_ascii` column with the name transliterated to ASCII.
+    pub ascii_name: bool,
+    /// Keep named `highway=proposed`/`planned`/`construction` ways instead of
+    /// dropping them, and add a `status` column.
+    pub include_proposed: bool,
+    /// Add `surface` and `smoothness` columns for streets.
+    pub with_surface: bool,
+    /// Projection of the center columns; geohash and country lookups still
+    /// use lon/lat.
+    pub crs: Crs,
+    /// Add `is_in_region` and `is_in_country` columns.
+    pub is_in_hierarchy: bool,
+    /// Drop named building ways that contain a named POI node.
+    pub dedup_poi_in_building: bool,
+    /// Add `place_node_lon`/`place_node_lat` columns with the location of
+    /// the place in `city_place_node`, in the same `crs` as the center.
+    pub place_coords: bool,
+    /// Add a `suburb` column; see `resolve_suburb`.
+    pub suburb: bool,
+    /// Add a `level` column from `level`, else `layer`. Features on
+    /// different levels are never merged, with or without the column.
+    pub with_level: bool,
+    /// Never associate rows with places ranked below this.
+    pub min_place_rank: PlaceRank,
+    /// `place=*` types loaded at all; empty loads every type. Places of
+    /// other types are neither associated with rows nor written as rows.
+    pub place_filter: Vec<String>,
+    /// Write place rows as `kind=place` with a `place_type` column, for a
+    /// gazetteer of settlements, rather than as `kind=city`.
+    pub include_places: bool,
+    /// Also write every named administrative boundary (name, admin_level,
+    /// centroid, area) to this CSV.
+    pub boundaries_output: Option<PathBuf>,
+    /// Also write every place node (name, type, location, population) to
+    /// this CSV.
+    pub places_output: Option<PathBuf>,
+    /// Prefer places in the same administrative boundary as the feature;
+    /// see `PlaceIndex::preferred_boundary`.
+    pub prefer_places_in_boundary: bool,
+    /// Replace names with their canonical spelling before merging.
+    pub rename_map: Option<RenameMap>,
+    /// After merging, keep only the longest of same-named rows of one kind
+    /// and city whose centers are this close; see `drop_nearby_duplicates`.
+    pub dedup_distance_km: Option<f64>,
+    /// Write at most this many rows per name; see `cap_rows_per_name`.
+    pub max_rows_per_name: Option<usize>,
+    /// Keep this fraction (0.0–1.0) of merged rows, chosen by a stable
+    /// hash; see `in_sample`.
+    pub sample_rate: Option<f64>,
+    /// Add an `ele_m` column with a POI's `ele` tag in meters.
+    pub with_ele: bool,
+    /// Add `operator` and `brand` columns for POIs.
+    pub with_operator_brand: bool,
+    /// Add a `merged_from` column listing the OSM elements fused into each
+    /// row (e.g. `w40;w41`).
+    pub with_merged_from: bool,
+    /// Name highways without name tags after their `destination` (or
+    /// `junction:ref`), and add a `name_source` column.
+    pub use_destination: bool,
+    /// Add a `sinuosity` column: a street's length over the straight-line
+    /// distance between its ends, empty for closed ways and non-streets.
+    pub with_sinuosity: bool,
+    /// Extract named `natural=*` features and islands as `kind=natural`.
+    pub include_natural: bool,
+    /// Extract named buildings that match no other POI kind, as
+    /// `kind=building`.
+    pub include_named_buildings: bool,
+    /// Only take named buildings that have a wiki tag.
+    pub named_buildings_need_wiki: bool,
+    /// Keep only POIs of any kind that carry a `wikipedia` or `wikidata`
+    /// tag.
+    pub only_with_wiki: bool,
+    /// Add a `city_confidence` column (0–1, two decimals).
+    pub city_confidence: bool,
+    /// Write names as tagged instead of through `normalize_name`.
+    pub raw_names: bool,
+    /// Keep POIs marked out of use (`disused=yes`, `disused:railway=station`
+    /// and other lifecycle prefixes) as if they were active.
+    pub include_disused: bool,
+}
+
+impl ExtractOptions {
+    fn max_nodes_per_way(&self) -> usize {
+        self.max_nodes_per_way.unwrap_or(DEFAULT_MAX_NODES_PER_WAY)
+    }
+}
+
+const MERGE_DISTANCE_KM: f64 = 1.0;
+
+fn merge_city_key(entry: &StreetEntry) -> String {
+    if !entry.city_resolved.is_empty() {
+        return entry.city_resolved.clone();
+    }
+    if !entry.city_place_city.is_empty() {
+        return entry.city_place_city.clone();
+    }
+    if !entry.city_place_node.is_empty() {
+        return entry.city_place_node.clone();
+    }
+    String::new()
+}
+
+fn pick_mode(
+    entries: &[StreetEntry],
+    indices: &[usize],
+    getter: impl Fn(&StreetEntry) -> &str,
+) -> String {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for idx in indices {
+        let value = getter(&entries[*idx]);
+        if value.is_empty() {
+            continue;
+        }
+        *counts.entry(value.to_string()).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(value, _)| value)
+        .unwrap_or_default()
+}
+
+fn merge_cluster(
+    entries: &[StreetEntry],
+    indices: &[usize],
+    weighting: MergeWeight,
+) -> StreetEntry {
+    let mut weighted_lon = 0.0;
+    let mut weighted_lat = 0.0;
+    let mut weight_sum = 0.0;
+    let mut length_sum = 0.0;
+    let mut area_sum = 0.0;
+
+    for idx in indices {
+        let entry = &entries[*idx];
+        let weight = weighting.weight(entry);
+        weighted_lon += entry.center_lon * weight;
+        weighted_lat += entry.center_lat * weight;
+        weight_sum += weight;
+        length_sum += entry.length_km;
+        area_sum += entry.area_km2;
+    }
+
+    let center_lon = if weight_sum > 0.0 {
+        weighted_lon / weight_sum
+    } else {
+        entries[indices[0]].center_lon
+    };
+    let center_lat = if weight_sum > 0.0 {
+        weighted_lat / weight_sum
+    } else {
+        entries[indices[0]].center_lat
+    };
+
+    // Names may differ in case or accents under `merge_case_insensitive`
+    // or `merge_accent_insensitive`; keep the most common spelling, and the
+    // first one seen on a tie.
+    let mut spellings: Vec<(usize, usize)> = Vec::new();
+    for (position, idx) in indices.iter().enumerate() {
+        let name = &entries[*idx].name;
+        match spellings.iter_mut().find(|(first, _)| entries[indices[*first]].name == *name) {
+            Some((_, count)) => *count += 1,
+            None => spellings.push((position, 1)),
+        }
+    }
+    let (first, _) = spellings.into_iter().min_by_key(|(_, count)| Reverse(*count)).unwrap();
+    let name = entries[indices[first]].name.clone();
+    let name_lang = entries[indices[first]].name_lang.clone();
+    let mut aliases: Vec<String> = Vec::new();
+    for idx in indices {
+        for alias in &entries[*idx].aliases {
+            if !aliases.contains(alias) {
+                aliases.push(alias.clone());
+            }
+        }
+    }
+    let kind = pick_mode(entries, indices, |e| e.kind.as_str());
+    let city_place_node = pick_mode(entries, indices, |e| e.city_place_node.as_str());
+    let city_place_type = pick_mode(entries, indices, |e| e.city_place_type.as_str());
+    let city_place_city = pick_mode(entries, indices, |e| e.city_place_city.as_str());
+    let city_resolved = pick_mode(entries, indices, |e| e.city_resolved.as_str());
+    let city_population = pick_mode(entries, indices, |e| e.city_population.as_str());
+    let wikidata = pick_mode(entries, indices, |e| e.wikidata.as_str());
+    let wikipedia = pick_mode(entries, indices, |e| e.wikipedia.as_str());
+    let city_confidence = indices
+        .iter()
+        .map(|idx| &entries[*idx])
+        .filter(|entry| entry.city_resolved == city_resolved)
+        .map(|entry| entry.city_confidence)
+        .fold(0.0, f64::max);
+    let suburb = pick_mode(entries, indices, |e| e.suburb.as_str());
+    let city_place_coord = indices
+        .iter()
+        .map(|idx| &entries[*idx])
+        .find(|entry| entry.city_place_node == city_place_node)
+        .and_then(|entry| entry.city_place_coord);
+    let addr_street = pick_mode(entries, indices, |e| e.addr_street.as_str());
+    let status = pick_mode(entries, indices, |e| e.status.as_str());
+    let ele_m = pick_mode(entries, indices, |e| e.ele_m.as_str());
+    let level = pick_mode(entries, indices, |e| e.level.as_str());
+    let surface = pick_mode(entries, indices, |e| e.surface.as_str());
+    let smoothness = pick_mode(entries, indices, |e| e.smoothness.as_str());
+    let operator = pick_mode(entries, indices, |e| e.operator.as_str());
+    let brand = pick_mode(entries, indices, |e| e.brand.as_str());
+    let is_in_region = pick_mode(entries, indices, |e| e.is_in_region.as_str());
+    let is_in_country = pick_mode(entries, indices, |e| e.is_in_country.as_str());
+    let extra_tags = (0..entries[indices[0]].extra_tags.len())
+        .map(|key| pick_mode(entries, indices, |e| e.extra_tags[key].as_str()))
+        .collect();
+    let newest = indices
+        .iter()
+        .map(|idx| &entries[*idx])
+        .max_by(|a, b| a.osm_timestamp.cmp(&b.osm_timestamp))
+        .unwrap();
+    let osm_version = newest.osm_version.clone();
+    let osm_timestamp = newest.osm_timestamp.clone();
+    let mut endpoint_nodes: Vec<i64> = Vec::new();
+    for idx in indices {
+        for node in &entries[*idx].endpoint_nodes {
+            if !endpoint_nodes.contains(node) {
+                endpoint_nodes.push(*node);
+            }
+        }
+    }
+    let name_source = pick_mode(entries, indices, |e| e.name_source.as_str());
+    let geometry = indices.iter().flat_map(|idx| entries[*idx].geometry.clone()).collect();
+    let mut merged_from: Vec<String> = Vec::new();
+    for idx in indices {
+        for element in &entries[*idx].merged_from {
+            if !merged_from.contains(element) {
+                merged_from.push(element.clone());
+            }
+        }
+    }
+
+    StreetEntry {
+        name,
+        name_lang,
+        aliases,
+        kind,
+        center_lon,
+        center_lat,
+        length_km: length_sum,
+        area_km2: area_sum,
+        chord_km: indices.iter().map(|idx| entries[*idx].chord_km).sum(),
+        city_place_node,
+        city_place_type,
+        city_place_city,
+        city_resolved,
+        city_population,
+        city_confidence,
+        city_place_coord,
+        suburb,
+        addr_street,
+        status,
+        ele_m,
+        level,
+        surface,
+        smoothness,
+        operator,
+        brand,
+        is_in_region,
+        is_in_country,
+        extra_tags,
+        osm_version,
+        osm_timestamp,
+        segment_count: indices
+            .iter()
+            .map(|idx| entries[*idx].segment_count)
+            .sum(),
+        wikidata,
+        wikipedia,
+        node_count: indices.iter().map(|idx| entries[*idx].node_count).sum(),
+        endpoint_nodes,
+        merged_from,
+        name_source,
+        geometry,
+    }
+}
+
+/// Bounding box of the centers merged into one cluster so far.
+#[derive(Copy, Clone)]
+struct ClusterBounds {
+    min: (f64, f64),
+    max: (f64, f64),
+}
+
+impl ClusterBounds {
+    fn new(point: (f64, f64)) -> Self {
+        Self {
+            min: point,
+            max: point,
+        }
+    }
+
+    fn extended(self, point: (f64, f64)) -> Self {
+        Self {
+            min: (self.min.0.min(point.0), self.min.1.min(point.1)),
+            max: (self.max.0.max(point.0), self.max.1.max(point.1)),
+        }
+    }
+
+    fn diagonal_km(&self) -> f64 {
+        haversine_km(self.min, self.max)
+    }
+}
+
+/// The form of `name` that rows are grouped by in [`merge_entries`]: the
+/// name itself, lowercased (with `ß` as `ss`) under
+/// `merge_case_insensitive`, and transliterated to ASCII under
+/// `merge_accent_insensitive`.
+fn merge_name_key<'n>(name: &'n str, options: &ExtractOptions) -> Cow<'n, str> {
+    let mut key = Cow::Borrowed(name);
+    if options.merge_accent_insensitive {
+        key = Cow::Owned(deunicode::deunicode(&key));
+    }
+    if options.merge_case_insensitive {
+        key = Cow::Owned(key.to_lowercase().replace('ß', "ss"));
+    }
+    key
+}
+
+/// Merges rows of the same name, kind and city that lie within about 1 km
+/// of each other into one row per street. This is the
+/// step [`extract_entries`] runs after reading; pair it with
+/// [`stream_entries`] to get the same rows from a stream.
+pub fn merge_entries(entries: Vec<StreetEntry>, options: &ExtractOptions) -> Vec<StreetEntry> {
+    merge_entries_until(entries, options, Deadline::start(None))
+        .expect("merging without a time limit cannot time out")
+}
+
+/// [`merge_entries`], giving up with [`ExtractError::Timeout`] once
+/// `deadline` passes between two groups.
+fn merge_entries_until(
+    entries: Vec<StreetEntry>,
+    options: &ExtractOptions,
+    deadline: Deadline,
+) -> Result<Vec<StreetEntry>> {
+    let mut grouped: Vec<((String, String), Vec<StreetEntry>)> = Vec::new();
+    let mut index: HashMap<(String, String), usize> = HashMap::new();
+    for entry in entries {
+        let key = (
+            // A planned extension is not merged into the road it extends,
+            // nor a shop into its namesake on another floor.
+            format!(
+                "{}\u{1f}{}\u{1f}{}\u{1f}{}\u{1f}{}",
+                merge_name_key(&entry.name, options),
+                entry.name_lang,
+                entry.kind,
+                entry.status,
+                entry.level
+            ),
+            merge_city_key(&entry),
+        );
+        if let Some(&position) = index.get(&key) {
+            grouped[position].1.push(entry);
+        } else {
+            index.insert(key.clone(), grouped.len());
+            grouped.push((key, vec![entry]));
+        }
+    }
+
+    let mut merged = Vec::new();
+    for ((_, city_key), group) in grouped {
+        deadline.check()?;
+        if city_key.is_empty() {
+            merged.extend(group);
+            continue;
+        }
+        let mut grid = SpatialGrid::new(MERGE_DISTANCE_KM / 111.0);
+        let mut by_endpoint: HashMap<i64, Vec<usize>> = HashMap::new();
+        for (idx, entry) in group.iter().enumerate() {
+            grid.insert((entry.center_lon, entry.center_lat), idx);
+            for node in &entry.endpoint_nodes {
+                by_endpoint.entry(*node).or_default().push(idx);
+            }
+        }
+        let mut remaining = vec![true; group.len()];
+        for i in 0..group.len() {
+            if !remaining[i] {
+                continue;
+            }
+            remaining[i] = false;
+            let mut cluster = vec![i];
+            let mut queue = vec![i];
+            let mut bounds = ClusterBounds::new((group[i].center_lon, group[i].center_lat));
+
+            while let Some(idx) = queue.pop() {
+                let base = (group[idx].center_lon, group[idx].center_lat);
+                // Segments joined at an end node are one road however far
+                // apart their centers are.
+                let connected: HashSet<usize> = group[idx]
+                    .endpoint_nodes
+                    .iter()
+                    .filter_map(|node| by_endpoint.get(node))
+                    .flatten()
+                    .copied()
+                    .filter(|&j| remaining[j])
+                    .collect();
+                // Visit candidates in index order so clustering (and the span
+                // guard) behaves the same as a full scan of the group.
+                let mut candidates: Vec<usize> = grid
+                    .neighbors(base, MERGE_DISTANCE_KM)
+                    .copied()
+                    .filter(|&j| remaining[j])
+                    .chain(connected.iter().copied())
+                    .collect();
+                candidates.sort_unstable();
+                candidates.dedup();
+                for j in candidates {
+                    let other = (group[j].center_lon, group[j].center_lat);
+                    if connected.contains(&j) || haversine_km(base, other) <= MERGE_DISTANCE_KM {
+                        if let Some(max_span) = options.max_cluster_span_km {
+                            if bounds.extended(other).diagonal_km() > max_span {
+                                continue;
+                            }
+                        }
+                        bounds = bounds.extended(other);
+                        remaining[j] = false;
+                        queue.push(j);
+                        cluster.push(j);
+                    }
+                }
+            }
+
+            merged.push(merge_cluster(&group, &cluster, options.merge_weight));
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Drops rows that repeat the name, kind and city of a longer row whose
+/// center is within `distance_km`. Unlike merging, nothing is combined:
+/// the longest row survives unchanged. Rows without a resolved city are
+/// all kept, as in [`merge_entries`].
+fn drop_nearby_duplicates(entries: &mut Vec<StreetEntry>, distance_km: f64) {
+    let mut keep = vec![true; entries.len()];
+    {
+        let mut order: Vec<usize> = (0..entries.len()).collect();
+        order.sort_by(|&a, &b| entries[b].length_km.total_cmp(&entries[a].length_km));
+        let cell_size_deg = (distance_km / 111.0).max(1e-6);
+        let mut kept = HashMap::new();
+        for idx in order {
+            let entry = &entries[idx];
+            if entry.city_resolved.is_empty() {
+                continue;
+            }
+            let center = (entry.center_lon, entry.center_lat);
+            let grid = kept
+                .entry((&entry.name, &entry.kind, &entry.city_resolved))
+                .or_insert_with(|| SpatialGrid::new(cell_size_deg));
+            let duplicate = grid
+                .neighbors(center, distance_km)
+                .any(|&other| haversine_km(center, other) <= distance_km);
+            if duplicate {
+                keep[idx] = false;
+            } else {
+                grid.insert(center, center);
+            }
+        }
+    }
+    let mut keep = keep.into_iter();
+    entries.retain(|_| keep.next().unwrap_or(true));
+}
+
+/// Keeps at most `max` rows per name: the longest, then the largest by
+/// area, with ties going to the row that came first. Kept rows stay in
+/// their original order.
+fn cap_rows_per_name(entries: &mut Vec<StreetEntry>, max: usize) {
+    let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (idx, entry) in entries.iter().enumerate() {
+        by_name.entry(&entry.name).or_default().push(idx);
+    }
+    let mut keep = vec![true; entries.len()];
+    for indices in by_name.values_mut().filter(|indices| indices.len() > max) {
+        indices.sort_by(|&a, &b| {
+            let (a, b) = (&entries[a], &entries[b]);
+            b.length_km.total_cmp(&a.length_km).then(b.area_km2.total_cmp(&a.area_km2))
+        });
+        for &idx in &indices[max..] {
+            keep[idx] = false;
+        }
+    }
+    drop(by_name);
+    let mut keep = keep.into_iter();
+    entries.retain(|_| keep.next().unwrap_or(true));
+}
+
+fn get_attr_value(event: &BytesStart<'_>, key: &[u8]) -> Result<Option<String>> {
+    for attr in event.attributes().with_checks(false) {
+        let attr = attr?;
+        if attr.key.local_name().as_ref() == key {
+            return Ok(Some(attr.unescape_value()?.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn read_element_meta(event: &BytesStart<'_>) -> Result<ElementMeta> {
+    let version = get_attr_value(event, b"version")?.and_then(|value| value.parse::<u32>().ok());
+    let timestamp = get_attr_value(event, b"timestamp")?.filter(|value| !value.is_empty());
+    Ok(ElementMeta { version, timestamp })
+}
+
+/// Parses a lat/lon attribute. With `tolerant`, surrounding whitespace and a
+/// single decimal comma (`1,5`) are accepted as well.
+fn parse_coordinate(value: &str, tolerant: bool) -> Option<f64> {
+    if let Ok(parsed) = value.parse::<f64>() {
+        return Some(parsed);
+    }
+    if !tolerant {
+        return None;
+    }
+    let trimmed = value.trim();
+    if trimmed.matches(',').count() == 1 && !trimmed.contains('.') {
+        trimmed.replacen(',', ".", 1).parse::<f64>().ok()
+    } else {
+        trimmed.parse::<f64>().ok()
+    }
+}
+
+/// Reads a node's `(lon, lat)`, or `None` if either attribute is missing or
+/// unparsable. Under `ExtractOptions::detect_swapped_coords`, coordinates
+/// that `unswap_coord` corrects are counted in `swapped`.
+fn read_node_coord(
+    event: &BytesStart<'_>,
+    swapped: &mut usize,
+    options: &ExtractOptions,
+) -> Result<Option<(f64, f64)>> {
+    let parse = |value: String| parse_coordinate(&value, options.tolerant_numbers);
+    let lat = get_attr_value(event, b"lat")?.and_then(parse);
+    let lon = get_attr_value(event, b"lon")?.and_then(parse);
+    let Some(coord) = lon.zip(lat) else {
+        return Ok(None);
+    };
+    if options.detect_swapped_coords {
+        if let Some(unswapped) = unswap_coord(coord) {
+            *swapped += 1;
+            return Ok(Some(unswapped));
+        }
+    }
+    Ok(Some(coord))
+}
+
+/// The `(lon, lat)` of a node whose attributes were swapped, or `None` if
+/// `coord` does not look swapped. A coordinate counts as swapped only when
+/// its `lat` is out of range (beyond ±90) while its `lon` is a valid
+/// latitude (within ±90) and its `lat` a valid longitude (within ±180), so
+/// swapping yields a valid point. A `lat` beyond ±180 is left alone: no
+/// swap makes it valid. Swaps that keep both values within ±90 cannot be
+/// told apart from real points and go undetected.
+fn unswap_coord((lon, lat): (f64, f64)) -> Option<(f64, f64)> {
+    (lat.abs() > 90.0 && lat.abs() <= 180.0 && lon.abs() <= 90.0).then_some((lat, lon))
+}
+
+/// Records a node's coordinate. An id seen before with a different
+/// coordinate (e.g. in concatenated exports) is counted in `conflicts` and
+/// the later coordinate wins, unless `ExtractOptions::strict` makes it an
+/// error.
+fn insert_node_coord(
+    nodes: &mut HashMap<i64, (f64, f64)>,
+    id: i64,
+    coord: (f64, f64),
+    conflicts: &mut usize,
+    options: &ExtractOptions,
+) -> Result<()> {
+    if let Some(previous) = nodes.insert(id, coord) {
+        if previous != coord {
+            if options.strict {
+                return Err(ExtractError::invalid_data(format!(
+                    "node {id} appears twice with different coordinates"
+                )));
+            }
+            *conflicts += 1;
+        }
+    }
+    Ok(())
+}
+
+fn read_member(event: &BytesStart<'_>) -> Result<Option<(MemberType, i64, String)>> {
+    let member_type = get_attr_value(event, b"type")?.and_then(|value| MemberType::parse(&value));
+    let reference = get_attr_value(event, b"ref")?.and_then(|value| value.parse::<i64>().ok());
+    let role = get_attr_value(event, b"role")?.unwrap_or_default();
+    Ok(match (member_type, reference) {
+        (Some(member_type), Some(reference)) => Some((member_type, reference, role)),
+        _ => None,
+    })
+}
+
+/// Formats a coordinate with fixed `precision`, or the shortest exact form
+/// if `None`. `compact` trims trailing zeros and the dot from fixed output,
+/// so `0.0000000` becomes `0` and `2.2945000` becomes `2.2945`.
+fn format_coord(value: f64, precision: Option<usize>, compact: bool) -> String {
+    match precision {
+        Some(precision) if compact => {
+            let fixed = format!("{value:.precision$}");
+            let trimmed = if fixed.contains('.') {
+                fixed.trim_end_matches('0').trim_end_matches('.')
+            } else {
+                fixed.as_str()
+            };
+            if trimmed == "-0" {
+                "0".to_string()
+            } else {
+                trimmed.to_string()
+            }
+        }
+        Some(precision) => format!("{value:.precision$}"),
+        None => format!("{value}"),
+    }
+}
+
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+pub const MAX_GEOHASH_LEN: usize = 12;
+
+/// Standard base-32 geohash of `(lon, lat)` with `len` characters.
+fn geohash(point: (f64, f64), len: usize) -> String {
+    let (mut lon_min, mut lon_max) = (-180.0, 180.0);
+    let (mut lat_min, mut lat_max) = (-90.0, 90.0);
+    let mut hash = String::with_capacity(len);
+    let mut even_bit = true;
+    while hash.len() < len {
+        let mut index = 0;
+        for _ in 0..5 {
+            // Bits alternate between longitude and latitude, longitude first.
+            let (value, min, max) = if even_bit {
+                (point.0, &mut lon_min, &mut lon_max)
+            } else {
+                (point.1, &mut lat_min, &mut lat_max)
+            };
+            let mid = (*min + *max) / 2.0;
+            index <<= 1;
+            if value >= mid {
+                index |= 1;
+                *min = mid;
+            } else {
+                *max = mid;
+            }
+            even_bit = !even_bit;
+        }
+        hash.push(GEOHASH_ALPHABET[index] as char);
+    }
+    hash
+}
+
+/// The full column list written for `options`.
+pub fn output_columns(options: &ExtractOptions) -> Vec<&str> {
+    let mut headers = COLUMNS.to_vec();
+    headers[3..5].copy_from_slice(&options.crs.center_headers());
+    if options.with_metadata {
+        headers.extend(METADATA_HEADERS);
+    }
+    if let Some(unit) = options.length_unit {
+        headers.push(unit.header());
+    }
+    if options.group_aliases {
+        headers.push("aliases");
+    }
+    if options.geohash_len.is_some() {
+        headers.push("geohash");
+    }
+    if options.countries.is_some() {
+        headers.push("country_code");
+    }
+    if options.ascii_name {
+        headers.push("name_ascii");
+    }
+    if options.include_proposed {
+        headers.push("status");
+    }
+    if options.with_surface {
+        headers.extend(["surface", "smoothness"]);
+    }
+    if options.is_in_hierarchy {
+        headers.extend(["is_in_region", "is_in_country"]);
+    }
+    if options.place_coords {
+        headers.extend(["place_node_lon", "place_node_lat"]);
+    }
+    if options.suburb {
+        headers.push("suburb");
+    }
+    if options.with_level {
+        headers.push("level");
+    }
+    if options.city_confidence {
+        headers.push("city_confidence");
+    }
+    if options.with_ele {
+        headers.push("ele_m");
+    }
+    if options.with_operator_brand {
+        headers.extend(["operator", "brand"]);
+    }
+    if options.with_merged_from {
+        headers.push("merged_from");
+    }
+    if options.use_destination {
+        headers.push("name_source");
+    }
+    if options.with_sinuosity {
+        headers.push("sinuosity");
+    }
+    if options.include_places {
+        headers.push("place_type");
+    }
+    headers.extend(options.extra_tags.iter().map(String::as_str));
+    headers
+}
+
+/// Positions in [`output_columns`] of `options.columns`, or `None` to write
+/// every column. Naming a column that `options` does not produce is an
+/// error.
+fn column_selection(options: &ExtractOptions) -> Result<Option<Vec<usize>>> {
+    if options.columns.is_empty() {
+        return Ok(None);
+    }
+    let columns = output_columns(options);
+    options
+        .columns
+        .iter()
+        .map(|name| {
+            columns.iter().position(|column| column == name).ok_or_else(|| {
+                ExtractError::bad_args(format!(
+                    "--columns names an unknown column: {name} (expected one of {})",
+                    columns.join(", ")
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Some)
+}
+
+/// Keeps the fields of `record` at `selection`, in that order.
+fn select_fields(record: Vec<String>, selection: &[usize]) -> Vec<String> {
+    let mut fields: Vec<Option<String>> = record.into_iter().map(Some).collect();
+    selection.iter().map(|&idx| fields[idx].take().unwrap_or_default()).collect()
+}
+
+/// The header written for `options`: [`output_columns`] with
+/// `options.header_map` applied, narrowed to `options.columns`. Renaming a
+/// column that is not written, or into a name another column already has,
+/// is an error.
+pub fn output_header(options: &ExtractOptions) -> Result<Vec<String>> {
+    let mut header: Vec<String> =
+        output_columns(options).into_iter().map(str::to_string).collect();
+    let columns = header.clone();
+    for (column, renamed) in &options.header_map {
+        let index = columns
+            .iter()
+            .position(|existing| existing == column)
+            .ok_or_else(|| {
+                ExtractError::bad_args(format!("--header-map renames an unknown column: {column}"))
+            })?;
+        header[index] = renamed.clone();
+    }
+    if let Some(selection) = column_selection(options)? {
+        header = select_fields(header, &selection);
+    }
+    let mut seen = HashSet::new();
+    if let Some(duplicate) = header.iter().find(|name| !seen.insert(name.as_str())) {
+        return Err(ExtractError::bad_args(format!(
+            "--header-map produces a duplicate column: {duplicate}"
+        )));
+    }
+    Ok(header)
+}
+
+/// Formats each entry as a record in `output_columns` order, narrowed to
+/// `options.columns`, and hands it to `sink`. Returns the number of records
+/// written.
+fn write_entries(
+    entries: Vec<StreetEntry>,
+    options: &ExtractOptions,
+    coord_precision: Option<usize>,
+    mut sink: impl FnMut(Vec<String>, RowGeometry) -> Result<()>,
+) -> Result<usize> {
+    let selection = column_selection(options)?;
+    let mut count = 0;
+    let mut written: HashSet<Vec<String>> = HashSet::new();
+    for mut entry in entries {
+        if let Some(snap) = options.snap {
+            (entry.center_lon, entry.center_lat) = snap.apply((entry.center_lon, entry.center_lat));
+        }
+        let sinuosity = entry.sinuosity();
+        // Place rows carry their own type in `city_place_type`.
+        let place_type = if entry.kind == "place" {
+            entry.city_place_type.clone()
+        } else {
+            String::new()
+        };
+        let (x, y) = options.crs.project((entry.center_lon, entry.center_lat));
+        let center_precision = options.crs.precision(coord_precision);
+        let mut record = vec![
+            entry.name,
+            entry.name_lang,
+            entry.kind,
+            format_coord(x, center_precision, options.compact_coords),
+            format_coord(y, center_precision, options.compact_coords),
+            entry.city_place_node,
+            entry.city_place_type,
+            entry.city_place_city,
+            entry.city_resolved,
+            entry.city_population,
+            entry.segment_count.to_string(),
+            format!("{}", entry.area_km2),
+            entry.wikidata,
+            entry.wikipedia,
+            entry.addr_street,
+        ];
+        if options.with_metadata {
+            record.push(entry.osm_version);
+            record.push(entry.osm_timestamp);
+        }
+        if let Some(unit) = options.length_unit {
+            // Lengths are summed in km while merging and converted only here.
+            record.push(format!("{}", unit.convert_km(entry.length_km)));
+        }
+        if options.group_aliases {
+            record.push(entry.aliases.join(";"));
+        }
+        if let Some(len) = options.geohash_len {
+            record.push(geohash((entry.center_lon, entry.center_lat), len));
+        }
+        if let Some(countries) = &options.countries {
+            let code = countries.code_for((entry.center_lon, entry.center_lat));
+            record.push(code.unwrap_or_default().to_string());
+        }
+        if options.ascii_name {
+            record.push(deunicode::deunicode(&record[0]));
+        }
+        if options.include_proposed {
+            record.push(entry.status);
+        }
+        if options.with_surface {
+            record.push(entry.surface);
+            record.push(entry.smoothness);
+        }
+        if options.is_in_hierarchy {
+            record.push(entry.is_in_region);
+            record.push(entry.is_in_country);
+        }
+        if options.place_coords {
+            let (x, y) = match entry.city_place_coord {
+                Some(coord) => {
+                    let (x, y) = options.crs.project(coord);
+                    let format = |v| format_coord(v, center_precision, options.compact_coords);
+                    (format(x), format(y))
+                }
+                None => (String::new(), String::new()),
+            };
+            record.push(x);
+            record.push(y);
+        }
+        if options.suburb {
+            record.push(entry.suburb);
+        }
+        if options.with_level {
+            record.push(entry.level);
+        }
+        if options.city_confidence {
+            record.push(format!("{:.2}", entry.city_confidence));
+        }
+        if options.with_ele {
+            record.push(entry.ele_m);
+        }
+        if options.with_operator_brand {
+            record.push(entry.operator);
+            record.push(entry.brand);
+        }
+        if options.with_merged_from {
+            record.push(entry.merged_from.join(";"));
+        }
+        if options.use_destination {
+            record.push(entry.name_source);
+        }
+        if options.with_sinuosity {
+            record.push(sinuosity.map_or(String::new(), |value| format!("{value:.3}")));
+        }
+        if options.include_places {
+            record.push(place_type);
+        }
+        record.extend(entry.extra_tags);
+        if let Some(selection) = &selection {
+            record = select_fields(record, selection);
+        }
+        // Snapping makes exact duplicates likely; emit each record once.
+        if options.snap.is_some() && !written.insert(record.clone()) {
+            continue;
+        }
+        let geometry = RowGeometry {
+            center: (entry.center_lon, entry.center_lat),
+            lines: entry.geometry,
+        };
+        sink(record, geometry)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// What [`write_entries`] hands a sink besides the record: the (snapped)
+/// WGS84 center and the row's geometry lines, if they were kept.
+struct RowGeometry {
+    center: (f64, f64),
+    lines: Vec<Vec<(f64, f64)>>,
+}
+
+impl RowGeometry {
+    /// A LineString for one way, a MultiLineString for several, and the
+    /// center as a Point for nodes, places and relations.
+    fn to_geojson(&self) -> serde_json::Value {
+        let position = |(lon, lat): (f64, f64)| serde_json::json!([lon, lat]);
+        let line = |line: &Vec<(f64, f64)>| -> Vec<_> {
+            line.iter().copied().map(position).collect()
+        };
+        match self.lines.as_slice() {
+            [] => serde_json::json!({"type": "Point", "coordinates": position(self.center)}),
+            [single] => serde_json::json!({"type": "LineString", "coordinates": line(single)}),
+            lines => serde_json::json!({
+                "type": "MultiLineString",
+                "coordinates": lines.iter().map(line).collect::<Vec<_>>(),
+            }),
+        }
+    }
+}
+
+/// How often `Throughput` looks at the clock, in processed objects.
+const THROUGHPUT_CHECK_EVERY: usize = 1024;
+
+/// Periodic objects/sec lines on stderr for `--progress`; a no-op otherwise.
+struct Throughput {
+    label: &'static str,
+    interval: Option<Duration>,
+    total: Option<usize>,
+    processed: usize,
+    start: Instant,
+    last_report: Instant,
+}
+
+impl Throughput {
+    fn new(label: &'static str, options: &ExtractOptions, total: Option<usize>) -> Self {
+        let now = Instant::now();
+        Self {
+            label,
+            interval: options.progress,
+            total,
+            processed: 0,
+            start: now,
+            last_report: now,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.processed += 1;
+        let Some(interval) = self.interval else {
+            return;
+        };
+        if !self.processed.is_multiple_of(THROUGHPUT_CHECK_EVERY)
+            || self.last_report.elapsed() < interval
+        {
+            return;
+        }
+        self.last_report = Instant::now();
+        eprintln!(
+            "{}",
+            throughput_line(self.label, self.processed, self.total, self.start.elapsed())
+        );
+    }
+}
+
+/// The point at which `--timeout` gives up, checked between objects while
+/// reading and between merge groups.
+#[derive(Clone, Copy)]
+struct Deadline {
+    start: Instant,
+    limit: Option<Duration>,
+}
+
+impl Deadline {
+    fn start(limit: Option<Duration>) -> Self {
+        Self {
+            start: Instant::now(),
+            limit,
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        match self.limit {
+            Some(limit) if self.start.elapsed() >= limit => Err(ExtractError::Timeout(limit)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn throughput_line(
+    label: &str,
+    processed: usize,
+    total: Option<usize>,
+    elapsed: Duration,
+) -> String {
+    let rate = processed as f64 / elapsed.as_secs_f64().max(1e-9);
+    let mut line = format!("{label}: {processed} objects, {rate:.0}/s");
+    if let Some(total) = total {
+        let remaining = total.saturating_sub(processed) as f64 / rate.max(1e-9);
+        line.push_str(&format!(" of {total}, ETA {remaining:.0}s"));
+    }
+    line
+}
+
+fn collect_osm_xml_entries(
+    input_path: &Path,
+    options: &ExtractOptions,
+    deadline: Deadline,
+) -> Result<CollectedInput> {
+    let file = File::open(input_path)?;
+    let mut reader = Reader::from_reader(BufReader::new(file));
+    reader.trim_text(true);
+
+    let mut nodes: HashMap<i64, (f64, f64)> = HashMap::new();
+    let mut node_conflicts = 0;
+    let mut swapped_coords = 0;
+    let mut ways: Vec<WayData> = Vec::new();
+    let mut place_nodes: Vec<PlaceNode> = Vec::new();
+    let mut poi_nodes: Vec<NodeData> = Vec::new();
+    let mut current_node: Option<NodeData> = None;
+    let mut current_way: Option<WayData> = None;
+    let mut relations: Vec<RelationData> = Vec::new();
+    let mut current_relation: Option<RelationData> = None;
+    // Inside an osmChange <delete> block, whose elements are skipped.
+    let mut in_delete = false;
+    let mut buf = Vec::new();
+    // The XML size in objects is unknown up front, so only the rate is logged.
+    let mut throughput = Throughput::new("osm", options, None);
+
+    loop {
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(e) if e.local_name().as_ref() == b"delete" => in_delete = true,
+            Event::End(e) if e.local_name().as_ref() == b"delete" => in_delete = false,
+            _ => {}
+        }
+        if in_delete && !matches!(event, Event::Eof) {
+            buf.clear();
+            continue;
+        }
+        if let Event::Start(e) | Event::Empty(e) = &event {
+            if matches!(e.local_name().as_ref(), b"node" | b"way" | b"relation") {
+                throughput.tick();
+                deadline.check()?;
+            }
+        }
+        match event {
+            Event::Eof => break,
+            Event::Start(e) => {
+                let name = e.local_name().as_ref().to_vec();
+                match name.as_slice() {
+                    b"node" => {
+                        let id = get_attr_value(&e, b"id")?
+                            .and_then(|value| value.parse::<i64>().ok());
+                        let coord = read_node_coord(&e, &mut swapped_coords, options)?;
+                        if let (Some(id), Some(coord)) = (id, coord) {
+                            current_node = Some(NodeData {
+                                id: Some(id),
+                                coord: Some(coord),
+                                tags: Tags::new(),
+                                meta: read_element_meta(&e)?,
+                            });
+                        }
+                    }
+                    b"way" => {
+                        let id = get_attr_value(&e, b"id")?
+                            .and_then(|value| value.parse::<i64>().ok());
+                        current_way = Some(WayData {
+                            id,
+                            meta: read_element_meta(&e)?,
+                            ..WayData::default()
+                        });
+                    }
+                    b"relation" => {
+                        let id = get_attr_value(&e, b"id")?
+                            .and_then(|value| value.parse::<i64>().ok());
+                        current_relation = Some(RelationData {
+                            id,
+                            meta: read_element_meta(&e)?,
+                            ..RelationData::default()
+                        });
+                    }
+                    b"member" => {
+                        if let Some(relation) = current_relation.as_mut() {
+                            if let Some(member) = read_member(&e)? {
+                                relation.members.push(member);
+                            }
+                        }
+                    }
+                    b"nd" => {
+                        if let Some(way) = current_way.as_mut() {
+                            if let Some(reference) = get_attr_value(&e, b"ref")?
+                                .and_then(|value| value.parse::<i64>().ok())
+                            {
+                                way.node_refs.push(reference);
+                            }
+                        }
+                    }
+                    b"tag" => {
+                        if let Some(way) = current_way.as_mut() {
+                            let key = get_attr_value(&e, b"k")?;
+                            let value = get_attr_value(&e, b"v")?;
+                            if let (Some(key), Some(value)) = (key, value) {
+                                way.tags.insert(key.into(), value.into());
+                            }
+                        }
+                        if let Some(node) = current_node.as_mut() {
+                            let key = get_attr_value(&e, b"k")?;
+                            let value = get_attr_value(&e, b"v")?;
+                            if let (Some(key), Some(value)) = (key, value) {
+                                node.tags.insert(key.into(), value.into());
+                            }
+                        }
+                        if let Some(relation) = current_relation.as_mut() {
+                            let key = get_attr_value(&e, b"k")?;
+                            let value = get_attr_value(&e, b"v")?;
+                            if let (Some(key), Some(value)) = (key, value) {
+                                relation.tags.insert(key.into(), value.into());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Empty(e) => {
+                let name = e.local_name().as_ref().to_vec();
+                match name.as_slice() {
+                    b"node" => {
+                        let id = get_attr_value(&e, b"id")?
+                            .and_then(|value| value.parse::<i64>().ok());
+                        let coord = read_node_coord(&e, &mut swapped_coords, options)?;
+                        if let (Some(id), Some(coord)) = (id, coord) {
+                            insert_node_coord(&mut nodes, id, coord, &mut node_conflicts, options)?;
+                        }
+                    }
+                    b"way" => {
+                        let id = get_attr_value(&e, b"id")?
+                            .and_then(|value| value.parse::<i64>().ok());
+                        let way = WayData {
+                            id,
+                            meta: read_element_meta(&e)?,
+                            ..WayData::default()
+                        };
+                        ways.push(way);
+                    }
+                    b"member" => {
+                        if let Some(relation) = current_relation.as_mut() {
+                            if let Some(member) = read_member(&e)? {
+                                relation.members.push(member);
+                            }
+                        }
+                    }
+                    b"nd" => {
+                        if let Some(way) = current_way.as_mut() {
+                            if let Some(reference) = get_attr_value(&e, b"ref")?
+                                .and_then(|value| value.parse::<i64>().ok())
+                            {
+                                way.node_refs.push(reference);
+                            }
+                        }
+                    }
+                    b"tag" => {
+                        if let Some(way) = current_way.as_mut() {
+                            let key = get_attr_value(&e, b"k")?;
+                            let value = get_attr_value(&e, b"v")?;
+                            if let (Some(key), Some(value)) = (key, value) {
+                                way.tags.insert(key.into(), value.into());
+                            }
+                        }
+                        if let Some(node) = current_node.as_mut() {
+                            let key = get_attr_value(&e, b"k")?;
+                            let value = get_attr_value(&e, b"v")?;
+                            if let (Some(key), Some(value)) = (key, value) {
+                                node.tags.insert(key.into(), value.into());
+                            }
+                        }
+                        if let Some(relation) = current_relation.as_mut() {
+                            let key = get_attr_value(&e, b"k")?;
+                            let value = get_attr_value(&e, b"v")?;
+                            if let (Some(key), Some(value)) = (key, value) {
+                                relation.tags.insert(key.into(), value.into());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(e) => {
+                if e.local_name().as_ref() == b"node" {
+                    if let Some(node) = current_node.take() {
+                        if let (Some(id), Some(coord)) = (node.id, node.coord) {
+                            insert_node_coord(&mut nodes, id, coord, &mut node_conflicts, options)?;
+                            let place_node = place_node_from_tags(&node.tags, coord, options);
+                            if let Some(place_node) = place_node {
+                                place_nodes.push(place_node);
+                            }
+                            if is_poi(&node.tags, options) {
+                                poi_nodes.push(node);
+                            }
+                        }
+                    }
+                } else if e.local_name().as_ref() == b"way" {
+                    if let Some(way) = current_way.take() {
+                        ways.push(way);
+                    }
+                } else if e.local_name().as_ref() == b"relation" {
+                    if let Some(relation) = current_relation.take() {
+                        relations.push(relation);
+                    }
+                }
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+    if node_conflicts > 0 {
+        eprintln!(
+            "warning: {}: {node_conflicts} node ids repeated with a different coordinate; \
+             the last one was used",
+            input_path.display()
+        );
+    }
+    if swapped_coords > 0 {
+        eprintln!(
+            "warning: {}: {swapped_coords} nodes had lat and lon swapped; corrected",
+            input_path.display()
+        );
+    }
+
+    let ways_by_id: HashMap<i64, &WayData> = ways
+        .iter()
+        .filter_map(|way| way.id.map(|id| (id, way)))
+        .collect();
+    let mut membership = StreetMembership::new();
+    for relation in relations.iter().filter(|relation| is_associated_street(&relation.tags)) {
+        let members: Vec<(MemberType, i64, &str)> = relation
+            .members
+            .iter()
+            .map(|(member_type, id, role)| (*member_type, *id, role.as_str()))
+            .collect();
+        add_associated_street(&mut membership, &relation.tags, &members, |id| {
+            ways_by_id
+                .get(&id)
+                .and_then(|way| way.tags.get("name"))
+                .map(|name| name.to_string())
+        });
+    }
+    let mut features: Vec<PendingFeature> = Vec::new();
+    for node in poi_nodes {
+        if !passes_date_filter(&node.meta, options) {
+            continue;
+        }
+        let coord = match node.coord {
+            Some(coord) => coord,
+            None => continue,
+        };
+        let kind = match poi_kind(&node.tags, options) {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let names = collect_name_variants(&node.tags, options);
+        if names.is_empty() {
+            continue;
+        }
+        let entry = feature_entry(&node.tags, kind, coord, &node.meta, options);
+        let associated = node.id.and_then(|id| membership.get(&(MemberType::Node, id)));
+        let entry = StreetEntry {
+            addr_street: poi_addr_street(&node.tags, associated),
+            merged_from: element_ref('n', node.id),
+            ..entry
+        };
+        features.push(PendingFeature {
+            tags: node.tags,
+            names,
+            entry,
+            shape: FeatureShape::Node,
+        });
+    }
+    if options.include_poi_relations {
+        for relation in &relations {
+            if !is_multipolygon(&relation.tags) || !passes_date_filter(&relation.meta, options) {
+                continue;
+            }
+            let kind = match poi_kind(&relation.tags, options) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let names = collect_name_variants(&relation.tags, options);
+            if names.is_empty() {
+                continue;
+            }
+            let outer_ways = relation
+                .members
+                .iter()
+                .filter(|(member_type, _, role)| {
+                    *member_type == MemberType::Way && is_outer_role(role)
+                })
+                .filter_map(|(_, id, _)| ways_by_id.get(id).map(|way| way.node_refs.clone()))
+                .collect();
+            let Some(center) = multipolygon_center(outer_ways, |id| nodes.get(&id).copied())
+            else {
+                continue;
+            };
+            let entry = feature_entry(&relation.tags, kind, center, &relation.meta, options);
+            let entry = StreetEntry {
+                addr_street: poi_addr_street(&relation.tags, None),
+                merged_from: element_ref('r', relation.id),
+                ..entry
+            };
+            features.push(PendingFeature {
+                tags: relation.tags.clone(),
+                names,
+                entry,
+                shape: FeatureShape::Other,
+            });
+        }
+    }
+    let mut coords: Vec<(f64, f64)> = Vec::new();
+    let mut oversized_ways = 0;
+    for way in &ways {
+        if !passes_date_filter(&way.meta, options) {
+            continue;
+        }
+        let is_street = is_street(&way.tags, options);
+        let is_poi_way = is_poi(&way.tags, options);
+        if !is_street && !is_poi_way {
+            continue;
+        }
+        let kind = if is_street {
+            "street"
+        } else {
+            match poi_kind(&way.tags, options) {
+                Some(kind) => kind,
+                None => continue,
+            }
+        };
+
+        let names = collect_name_variants(&way.tags, options);
+        if names.is_empty() {
+            continue;
+        }
+
+        if way.node_refs.len() > options.max_nodes_per_way() {
+            oversized_ways += 1;
+            continue;
+        }
+        if !fill_way_coords(&way.node_refs, |id| nodes.get(id).copied(), &mut coords) {
+            continue;
+        }
+
+        let is_closed = is_ring(&way.node_refs, &coords);
+        let geometry = way_geometry(&coords, is_closed, is_street, &way.tags, options.poi_length);
+        let Some(mut geometry) = geometry else {
+            continue;
+        };
+        if options.drop_zero_length && geometry.is_collapsed_street(is_street) {
+            continue;
+        }
+        if geometry.is_below_min_area(options.min_area_m2) {
+            continue;
+        }
+        if options.center_on_geometry {
+            geometry.center = nearest_vertex(geometry.center, &coords);
+        }
+        let entry = feature_entry(&way.tags, kind, geometry.center, &way.meta, options);
+        let addr_street = if is_street {
+            String::new()
+        } else {
+            let associated = way.id.and_then(|id| membership.get(&(MemberType::Way, id)));
+            poi_addr_street(&way.tags, associated)
+        };
+        let shape = FeatureShape::for_way(&way.tags, is_street, is_closed, &coords, options);
+        features.push(PendingFeature {
+            tags: way.tags.clone(),
+            names,
+            shape,
+            entry: StreetEntry {
+                length_km: geometry.length_km,
+                area_km2: geometry.area_km2,
+                chord_km: geometry.chord_km,
+                addr_street,
+                node_count: coords.len(),
+                endpoint_nodes: way_endpoints(&way.node_refs),
+                merged_from: element_ref('w', way.id),
+                geometry: way_geometry_lines(&coords, options),
+                ..entry
+            },
+        });
+    }
+
+    warn_oversized_ways(input_path, oversized_ways, options);
+
+    let mut boundaries = Vec::new();
+    if collects_boundaries(options) {
+        let lookup = |id: i64| nodes.get(&id).copied();
+        for way in ways.iter().filter(|way| is_admin_boundary(&way.tags)) {
+            let outer_ways = vec![way.node_refs.clone()];
+            boundaries.extend(boundary_from_outer_ways(&way.tags, outer_ways, lookup));
+        }
+        for relation in relations.iter().filter(|relation| is_admin_boundary(&relation.tags)) {
+            let outer_ways = relation
+                .members
+                .iter()
+                .filter(|(member_type, _, role)| {
+                    *member_type == MemberType::Way && is_outer_role(role)
+                })
+                .filter_map(|(_, id, _)| ways_by_id.get(id).map(|way| way.node_refs.clone()))
+                .collect();
+            boundaries.extend(boundary_from_outer_ways(&relation.tags, outer_ways, lookup));
+        }
+    }
+
+    Ok(CollectedInput {
+        place_nodes,
+        features,
+        boundaries,
+    })
+}
+
+pub fn find_default_pbf(folder: &Path) -> Result<PathBuf> {
+    let mut pbfs = Vec::new();
+    for entry in folder.read_dir()? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("pbf") {
+            pbfs.push(path);
+        }
+    }
+    pbfs.sort();
+
+    match pbfs.len() {
+        0 => Err(ExtractError::bad_args("no .pbf files found in current directory")),
+        1 => Ok(pbfs.remove(0)),
+        _ => Err(ExtractError::bad_args("multiple .pbf files found; pass --input explicitly")),
+    }
+}
+
+/// Every `.pbf` and `.osm` file directly inside `folder`, sorted by path.
+pub fn input_dir_files(folder: &Path) -> Result<Vec<PathBuf>> {
+    let mut inputs = Vec::new();
+    for entry in folder.read_dir()? {
+        let path = entry?.path();
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        if path.is_file() && matches!(extension, Some("pbf" | "osm")) {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+    if inputs.is_empty() {
+        return Err(ExtractError::bad_args(format!(
+            "no .pbf or .osm files found in {}",
+            folder.display()
+        )));
+    }
+    Ok(inputs)
+}
+
+fn is_wanted_pbf_object(obj: &OsmObj, options: &ExtractOptions) -> bool {
+    if collects_boundaries(options) && is_admin_boundary(obj.tags()) {
+        return true;
+    }
+    match obj {
+        OsmObj::Way(w) => is_street(&w.tags, options) || is_poi(&w.tags, options),
+        OsmObj::Node(n) => is_place_node(&n.tags) || is_poi(&n.tags, options),
+        OsmObj::Relation(r) => {
+            is_associated_street(&r.tags)
+                || (options.include_poi_relations
+                    && is_multipolygon(&r.tags)
+                    && is_poi(&r.tags, options))
+        }
+    }
+}
+
+fn read_pbf_objects(
+    input_path: &Path,
+    options: &ExtractOptions,
+) -> Result<BTreeMap<OsmId, OsmObj>> {
+    let file = File::open(input_path)?;
+    if options.mmap {
+        // SAFETY: the mapping is only read while `file` is open. Truncating or
+        // rewriting the file concurrently is undefined behavior (SIGBUS on
+        // Unix), which is why --mmap is opt-in.
+        let map = unsafe { Mmap::map(&file)? };
+        let mut pbf = OsmPbfReader::new(Cursor::new(&map[..]));
+        return Ok(pbf.get_objs_and_deps(|obj| is_wanted_pbf_object(obj, options))?);
+    }
+    let mut pbf = OsmPbfReader::new(file);
+    Ok(pbf.get_objs_and_deps(|obj| is_wanted_pbf_object(obj, options))?)
+}
+
+fn collect_pbf_entries(
+    input_path: &Path,
+    options: &ExtractOptions,
+    deadline: Deadline,
+) -> Result<CollectedInput> {
+    let objs = read_pbf_objects(input_path, options)?;
+    let place_nodes = collect_pbf_place_nodes(&objs, options);
+    let mut membership = StreetMembership::new();
+    for obj in objs.values() {
+        let OsmObj::Relation(relation) = obj else {
+            continue;
+        };
+        if !is_associated_street(&relation.tags) {
+            continue;
+        }
+        let members: Vec<(MemberType, i64, &str)> = relation
+            .refs
+            .iter()
+            .map(|member| {
+                let (member_type, id) = match member.member {
+                    OsmId::Node(id) => (MemberType::Node, id.0),
+                    OsmId::Way(id) => (MemberType::Way, id.0),
+                    OsmId::Relation(id) => (MemberType::Relation, id.0),
+                };
+                (member_type, id, member.role.as_str())
+            })
+            .collect();
+        add_associated_street(&mut membership, &relation.tags, &members, |id| {
+            match objs.get(&OsmId::Way(WayId(id))) {
+                Some(OsmObj::Way(way)) => way.tags.get("name").map(|name| name.to_string()),
+                _ => None,
+            }
+        });
+    }
+
+    let mut features: Vec<PendingFeature> = Vec::new();
+    // PBF objects carry no timestamps, so the date filter keeps or drops all of them.
+    let meta = ElementMeta::default();
+    let keep_features = passes_date_filter(&meta, options);
+    let mut throughput = Throughput::new("pbf", options, Some(objs.len()));
+    let mut coords: Vec<(f64, f64)> = Vec::new();
+    let mut oversized_ways = 0;
+    for obj in objs.values() {
+        if !keep_features {
+            break;
+        }
+        throughput.tick();
+        deadline.check()?;
+        match obj {
+            OsmObj::Way(way) => {
+                let is_street = is_street(&way.tags, options);
+                let is_poi_way = is_poi(&way.tags, options);
+                if !is_street && !is_poi_way {
+                    continue;
+                }
+                let kind = if is_street {
+                    "street"
+                } else {
+                    match poi_kind(&way.tags, options) {
+                        Some(kind) => kind,
+                        None => continue,
+                    }
+                };
+
+                let names = collect_name_variants(&way.tags, options);
+                if names.is_empty() {
+                    continue;
+                }
+
+                let lookup = |id: &NodeId| match objs.get(&OsmId::Node(*id)) {
+                    Some(OsmObj::Node(node)) => Some((node.lon(), node.lat())),
+                    _ => None,
+                };
+                if way.nodes.len() > options.max_nodes_per_way() {
+                    oversized_ways += 1;
+                    continue;
+                }
+                if !fill_way_coords(&way.nodes, lookup, &mut coords) {
+                    continue;
+                }
+
+                let is_closed = is_ring(&way.nodes, &coords);
+                let geometry =
+                    way_geometry(&coords, is_closed, is_street, &way.tags, options.poi_length);
+                let Some(mut geometry) = geometry else {
+                    continue;
+                };
+                if options.drop_zero_length && geometry.is_collapsed_street(is_street) {
+                    continue;
+                }
+                if geometry.is_below_min_area(options.min_area_m2) {
+                    continue;
+                }
+                if options.center_on_geometry {
+                    geometry.center = nearest_vertex(geometry.center, &coords);
+                }
+                let entry = feature_entry(&way.tags, kind, geometry.center, &meta, options);
+                let addr_street = if is_street {
+                    String::new()
+                } else {
+                    poi_addr_street(&way.tags, membership.get(&(MemberType::Way, way.id.0)))
+                };
+                let shape =
+                    FeatureShape::for_way(&way.tags, is_street, is_closed, &coords, options);
+                features.push(PendingFeature {
+                    tags: way.tags.clone(),
+                    names,
+                    shape,
+                    entry: StreetEntry {
+                        length_km: geometry.length_km,
+                        area_km2: geometry.area_km2,
+                        chord_km: geometry.chord_km,
+                        addr_street,
+                        node_count: coords.len(),
+                        endpoint_nodes: way_endpoints(&way.nodes)
+                            .into_iter()
+                            .map(|id| id.0)
+                            .collect(),
+                        merged_from: element_ref('w', Some(way.id.0)),
+                        geometry: way_geometry_lines(&coords, options),
+                        ..entry
+                    },
+                });
+            }
+            OsmObj::Node(node) => {
+                let kind = match poi_kind(&node.tags, options) {
+                    Some(kind) => kind,
+                    None => continue,
+                };
+                let names = collect_name_variants(&node.tags, options);
+                if names.is_empty() {
+                    continue;
+                }
+                let center = (node.lon(), node.lat());
+                let entry = feature_entry(&node.tags, kind, center, &meta, options);
+                let entry = StreetEntry {
+                    addr_street: poi_addr_street(
+                        &node.tags,
+                        membership.get(&(MemberType::Node, node.id.0)),
+                    ),
+                    merged_from: element_ref('n', Some(node.id.0)),
+                    ..entry
+                };
+                features.push(PendingFeature {
+                    tags: node.tags.clone(),
+                    names,
+                    entry,
+                    shape: FeatureShape::Node,
+                });
+            }
+            OsmObj::Relation(relation) => {
+                if !options.include_poi_relations || !is_multipolygon(&relation.tags) {
+                    continue;
+                }
+                let kind = match poi_kind(&relation.tags, options) {
+                    Some(kind) => kind,
+                    None => continue,
+                };
+                let names = collect_name_variants(&relation.tags, options);
+                if names.is_empty() {
+                    continue;
+                }
+                let outer_ways = relation
+                    .refs
+                    .iter()
+                    .filter(|member| is_outer_role(&member.role))
+                    .filter_map(|member| match objs.get(&member.member) {
+                        Some(OsmObj::Way(way)) => {
+                            Some(way.nodes.iter().map(|node_id| node_id.0).collect())
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let center = multipolygon_center(outer_ways, |id| {
+                    match objs.get(&OsmId::Node(NodeId(id))) {
+                        Some(OsmObj::Node(node)) => Some((node.lon(), node.lat())),
+                        _ => None,
+                    }
+                });
+                let Some(center) = center else {
+                    continue;
+                };
+                let entry = feature_entry(&relation.tags, kind, center, &meta, options);
+                let entry = StreetEntry {
+                    addr_street: poi_addr_street(&relation.tags, None),
+                    merged_from: element_ref('r', Some(relation.id.0)),
+                    ..entry
+                };
+                features.push(PendingFeature {
+                    tags: relation.tags.clone(),
+                    names,
+                    entry,
+                    shape: FeatureShape::Other,
+                });
+            }
+        }
+    }
+
+    warn_oversized_ways(input_path, oversized_ways, options);
+
+    let mut boundaries = Vec::new();
+    if collects_boundaries(options) {
+        let lookup = |id: i64| match objs.get(&OsmId::Node(NodeId(id))) {
+            Some(OsmObj::Node(node)) => Some((node.lon(), node.lat())),
+            _ => None,
+        };
+        let way_nodes = |way: &osmpbfreader::Way| way.nodes.iter().map(|id| id.0).collect();
+        for obj in objs.values().filter(|obj| is_admin_boundary(obj.tags())) {
+            let outer_ways = match obj {
+                OsmObj::Way(way) => vec![way_nodes(way)],
+                OsmObj::Relation(relation) => relation
+                    .refs
+                    .iter()
+                    .filter(|member| is_outer_role(&member.role))
+                    .filter_map(|member| match objs.get(&member.member) {
+                        Some(OsmObj::Way(way)) => Some(way_nodes(way)),
+                        _ => None,
+                    })
+                    .collect(),
+                OsmObj::Node(_) => continue,
+            };
+            boundaries.extend(boundary_from_outer_ways(obj.tags(), outer_ways, lookup));
+        }
+    }
+
+    Ok(CollectedInput {
+        place_nodes,
+        features,
+        boundaries,
+    })
+}
+
+const METADATA_HEADERS: [&str; 2] = ["osm_version", "osm_timestamp"];
+
+/// Bumped whenever `COLUMNS` or the optional columns change order or meaning.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Columns every output starts with, in order. Optional columns (metadata,
+/// length, aliases, geohash, extra tags) follow; see [`output_columns`].
+pub const COLUMNS: &[&str] = &[
+    "streetname",
+    "name_lang",
+    "kind",
+    "center_lon",
+    "center_lat",
+    "city_place_node",
+    "city_place_type",
+    "city_place_city",
+    "city_resolved",
+    "city_population",
+    "segment_count",
+    "area_km2",
+    "wikidata",
+    "wikipedia",
+    "addr_street",
+];
+
+/// The schema version and column list for `options` as a JSON object. The
+/// columns are named as in the written header, after `header_map`.
+pub fn schema_json(options: &ExtractOptions) -> Result<String> {
+    Ok(serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "columns": output_header(options)?,
+    })
+    .to_string())
+}
+
+/// Parses a comma-separated `--header-map` list of `column=header` pairs.
+pub fn parse_header_map(value: &str) -> Result<Vec<(String, String)>> {
+    let mut renames = Vec::new();
+    for pair in value.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        match pair.split_once('=') {
+            Some((column, header)) if !column.trim().is_empty() && !header.trim().is_empty() => {
+                renames.push((column.trim().to_string(), header.trim().to_string()));
+            }
+            _ => {
+                return Err(ExtractError::bad_args(format!(
+                    "invalid --header-map entry (expected old=new): {pair}"
+                )))
+            }
+        }
+    }
+    Ok(renames)
+}
+
+/// Parses a comma-separated `--columns` list. Names are checked against the
+/// written columns by [`output_header`]; listing one twice is an error.
+pub fn parse_columns(value: &str) -> Result<Vec<String>> {
+    let mut columns: Vec<String> = Vec::new();
+    for column in value.split(',').map(str::trim).filter(|column| !column.is_empty()) {
+        if columns.iter().any(|existing| existing == column) {
+            return Err(ExtractError::bad_args(format!("--columns lists {column} twice")));
+        }
+        columns.push(column.to_string());
+    }
+    if columns.is_empty() {
+        return Err(ExtractError::bad_args("--columns requires at least one column"));
+    }
+    Ok(columns)
+}
+
+/// Parses a comma-separated `--extra-tags` list, rejecting keys that would
+/// clash with a built-in column.
+pub fn parse_extra_tags(value: &str) -> Result<Vec<String>> {
+    let mut keys: Vec<String> = Vec::new();
+    for key in value.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+        if COLUMNS.contains(&key) || METADATA_HEADERS.contains(&key) {
+            return Err(ExtractError::bad_args(format!(
+                "--extra-tags key clashes with a built-in column: {key}"
+            )));
+        }
+        if !keys.iter().any(|existing| existing == key) {
+            keys.push(key.to_string());
+        }
+    }
+    Ok(keys)
+}
+
+fn is_osm_xml(input_path: &Path) -> bool {
+    matches!(input_path.extension().and_then(|value| value.to_str()), Some("osm" | "osc"))
+}
+
+const CHECKPOINT_MAGIC: &str = "street-checkpoint";
+const CHECKPOINT_VERSION: &str = "16";
+/// Joins aliases in a checkpoint; unlike `;` it cannot occur in a name.
+const CHECKPOINT_ALIAS_SEPARATOR: &str = "\u{1f}";
+
+/// Identifies the inputs (path, size, modification time) and options a
+/// checkpoint was built for. Any change to either invalidates it, except
+/// to options that only act on the rows after the checkpoint: how they are
+/// merged, filtered and written.
+fn checkpoint_fingerprint(input_paths: &[PathBuf], options: &ExtractOptions) -> Result<String> {
+    let mut hasher = DefaultHasher::new();
+    for path in input_paths {
+        let metadata = std::fs::metadata(path)?;
+        path.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        metadata.modified().ok().hash(&mut hasher);
+    }
+    let row_options = ExtractOptions {
+        progress: None,
+        timeout: None,
+        checkpoint: None,
+        mmap: false,
+        boundaries_output: None,
+        places_output: None,
+        // Merging and the filters on merged rows.
+        merge_weight: MergeWeight::default(),
+        max_cluster_span_km: None,
+        merge_case_insensitive: false,
+        merge_accent_insensitive: false,
+        dedup_distance_km: None,
+        max_rows_per_name: None,
+        sample_rate: None,
+        // Output columns and encoding.
+        format: OutputFormat::default(),
+        columns: Vec::new(),
+        header_map: Vec::new(),
+        gzip: false,
+        crlf: false,
+        pretty: false,
+        schema_comment: false,
+        fail_on_empty: false,
+        snap: None,
+        length_unit: None,
+        compact_coords: false,
+        geohash_len: None,
+        crs: Crs::default(),
+        countries: None,
+        with_metadata: false,
+        ascii_name: false,
+        with_surface: false,
+        is_in_hierarchy: false,
+        place_coords: false,
+        with_level: false,
+        with_ele: false,
+        with_operator_brand: false,
+        with_merged_from: false,
+        with_sinuosity: false,
+        city_confidence: false,
+        ..options.clone()
+    };
+    format!("{row_options:?}").hash(&mut hasher);
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+impl StreetEntry {
+    /// `length_km` over `chord_km`: 1 for a straight street, more the more
+    /// it winds. `None` where there is no chord, as for closed ways.
+    pub fn sinuosity(&self) -> Option<f64> {
+        (self.chord_km > 0.0).then(|| self.length_km / self.chord_km)
+    }
+
+    /// The checkpoint's column order. Fields are stored as text that
+    /// [`StreetEntry::from_csv_record`] parses back to an equal entry; list
+    /// fields are joined with `;` (aliases with `CHECKPOINT_ALIAS_SEPARATOR`),
+    /// geometry lines with `;` between lines and ` ` between `lon,lat`
+    /// points, and `extra_tags` fill the trailing columns.
+    pub fn to_csv_record(&self) -> Vec<String> {
+        let mut record = vec![
+            self.name.clone(),
+            self.name_lang.clone(),
+            self.aliases.join(CHECKPOINT_ALIAS_SEPARATOR),
+            self.kind.clone(),
+            self.center_lon.to_string(),
+            self.center_lat.to_string(),
+            self.length_km.to_string(),
+            self.area_km2.to_string(),
+            self.city_place_node.clone(),
+            self.city_place_type.clone(),
+            self.city_place_city.clone(),
+            self.city_resolved.clone(),
+            self.city_population.clone(),
+            self.city_place_coord
+                .map(|(lon, lat)| format!("{lon};{lat}"))
+                .unwrap_or_default(),
+            self.city_confidence.to_string(),
+            self.suburb.clone(),
+            self.addr_street.clone(),
+            self.status.clone(),
+            self.ele_m.clone(),
+            self.level.clone(),
+            self.surface.clone(),
+            self.smoothness.clone(),
+            self.operator.clone(),
+            self.brand.clone(),
+            self.is_in_region.clone(),
+            self.is_in_country.clone(),
+            self.osm_version.clone(),
+            self.osm_timestamp.clone(),
+            self.segment_count.to_string(),
+            self.node_count.to_string(),
+            self.endpoint_nodes
+                .iter()
+                .map(|node| node.to_string())
+                .collect::<Vec<_>>()
+                .join(";"),
+            self.merged_from.join(";"),
+            self.name_source.clone(),
+            self.geometry
+                .iter()
+                .map(|line| {
+                    let points: Vec<String> =
+                        line.iter().map(|(lon, lat)| format!("{lon},{lat}")).collect();
+                    points.join(" ")
+                })
+                .collect::<Vec<_>>()
+                .join(";"),
+            self.chord_km.to_string(),
+        ];
+        // The wiki ids sit just before the extra tags.
+        record.push(self.wikidata.clone());
+        record.push(self.wikipedia.clone());
+        record.extend(self.extra_tags.iter().cloned());
+        record
+    }
+
+    /// Parses a record written by [`StreetEntry::to_csv_record`].
+    pub fn from_csv_record(record: &csv::StringRecord) -> Result<Self> {
+        const FIXED: usize = 37;
+        if record.len() < FIXED {
+            return Err(ExtractError::invalid_data(format!(
+                "checkpoint row has {} fields",
+                record.len()
+            )));
+        }
+        let text = |idx: usize| record[idx].to_string();
+        let aliases = record[2]
+            .split(CHECKPOINT_ALIAS_SEPARATOR)
+            .filter(|alias| !alias.is_empty())
+            .map(str::to_string)
+            .collect();
+        let city_place_coord = match record[13].split_once(';') {
+            Some((lon, lat)) => Some((lon.parse()?, lat.parse()?)),
+            None => None,
+        };
+        let mut geometry = Vec::new();
+        for line in record[33].split(';').filter(|line| !line.is_empty()) {
+            let mut points = Vec::new();
+            for point in line.split(' ') {
+                let (lon, lat) = point.split_once(',').ok_or_else(|| {
+                    ExtractError::invalid_data(format!("invalid checkpoint point: {point}"))
+                })?;
+                points.push((lon.parse()?, lat.parse()?));
+            }
+            geometry.push(points);
+        }
+        Ok(Self {
+            name: text(0),
+            name_lang: text(1),
+            aliases,
+            kind: text(3),
+            center_lon: record[4].parse()?,
+            center_lat: record[5].parse()?,
+            length_km: record[6].parse()?,
+            area_km2: record[7].parse()?,
+            city_place_node: text(8),
+            city_place_type: text(9),
+            city_place_city: text(10),
+            city_resolved: text(11),
+            city_population: text(12),
+            city_place_coord,
+            city_confidence: record[14].parse()?,
+            suburb: text(15),
+            addr_street: text(16),
+            status: text(17),
+            ele_m: text(18),
+            level: text(19),
+            surface: text(20),
+            smoothness: text(21),
+            operator: text(22),
+            brand: text(23),
+            is_in_region: text(24),
+            is_in_country: text(25),
+            osm_version: text(26),
+            osm_timestamp: text(27),
+            segment_count: record[28].parse()?,
+            node_count: record[29].parse()?,
+            endpoint_nodes: record[30]
+                .split(';')
+                .filter(|node| !node.is_empty())
+                .map(str::parse)
+                .collect::<std::result::Result<_, _>>()?,
+            merged_from: record[31]
+                .split(';')
+                .filter(|element| !element.is_empty())
+                .map(str::to_string)
+                .collect(),
+            name_source: text(32),
+            geometry,
+            chord_km: record[34].parse()?,
+            wikidata: text(FIXED - 2),
+            wikipedia: text(FIXED - 1),
+            extra_tags: record.iter().skip(FIXED).map(str::to_string).collect(),
+        })
+    }
+
+    /// Every field by name, in declaration order, with numbers and lists as
+    /// JSON numbers and arrays; the Python binding returns one such map per
+    /// row. The entry is destructured, so a new field cannot be left out.
+    pub fn to_json_map(&self) -> serde_json::Map<String, serde_json::Value> {
+        use serde_json::json;
+        let StreetEntry {
+            name,
+            name_lang,
+            aliases,
+            kind,
+            center_lon,
+            center_lat,
+            length_km,
+            area_km2,
+            city_place_node,
+            city_place_type,
+            city_place_city,
+            city_resolved,
+            city_population,
+            city_confidence,
+            city_place_coord,
+            suburb,
+            addr_street,
+            status,
+            ele_m,
+            level,
+            surface,
+            smoothness,
+            operator,
+            brand,
+            is_in_region,
+            is_in_country,
+            extra_tags,
+            osm_version,
+            osm_timestamp,
+            segment_count,
+            wikidata,
+            wikipedia,
+            node_count,
+            endpoint_nodes,
+            merged_from,
+            name_source,
+            geometry,
+            chord_km,
+        } = self;
+        let fields = [
+            ("name", json!(name)),
+            ("name_lang", json!(name_lang)),
+            ("aliases", json!(aliases)),
+            ("kind", json!(kind)),
+            ("center_lon", json!(center_lon)),
+            ("center_lat", json!(center_lat)),
+            ("length_km", json!(length_km)),
+            ("area_km2", json!(area_km2)),
+            ("city_place_node", json!(city_place_node)),
+            ("city_place_type", json!(city_place_type)),
+            ("city_place_city", json!(city_place_city)),
+            ("city_resolved", json!(city_resolved)),
+            ("city_population", json!(city_population)),
+            ("city_confidence", json!(city_confidence)),
+            ("city_place_coord", json!(city_place_coord.map(|(lon, lat)| [lon, lat]))),
+            ("suburb", json!(suburb)),
+            ("addr_street", json!(addr_street)),
+            ("status", json!(status)),
+            ("ele_m", json!(ele_m)),
+            ("level", json!(level)),
+            ("surface", json!(surface)),
+            ("smoothness", json!(smoothness)),
+            ("operator", json!(operator)),
+            ("brand", json!(brand)),
+            ("is_in_region", json!(is_in_region)),
+            ("is_in_country", json!(is_in_country)),
+            ("extra_tags", json!(extra_tags)),
+            ("osm_version", json!(osm_version)),
+            ("osm_timestamp", json!(osm_timestamp)),
+            ("segment_count", json!(segment_count)),
+            ("wikidata", json!(wikidata)),
+            ("wikipedia", json!(wikipedia)),
+            ("node_count", json!(node_count)),
+            ("endpoint_nodes", json!(endpoint_nodes)),
+            ("merged_from", json!(merged_from)),
+            ("name_source", json!(name_source)),
+            ("geometry", json!(geometry)),
+            ("chord_km", json!(chord_km)),
+        ];
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect()
+    }
+}
+
+/// Reads the rows saved at `path`, or `None` if there is no checkpoint or it
+/// was written for other inputs or options.
+fn load_checkpoint(path: &Path, fingerprint: &str) -> Result<Option<Vec<StreetEntry>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(path)?;
+    let mut records = reader.records();
+    let header = match records.next() {
+        Some(header) => header?,
+        None => return Ok(None),
+    };
+    if header.iter().collect::<Vec<_>>() != [CHECKPOINT_MAGIC, CHECKPOINT_VERSION, fingerprint] {
+        return Ok(None);
+    }
+    let mut entries = Vec::new();
+    for record in records {
+        entries.push(StreetEntry::from_csv_record(&record?)?);
+    }
+    Ok(Some(entries))
+}
+
+/// Writes the rows to a temporary file next to `path` and renames it into
+/// place, so an interrupted write never leaves a truncated checkpoint.
+fn save_checkpoint(path: &Path, fingerprint: &str, entries: &[StreetEntry]) -> Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let mut writer = WriterBuilder::new()
+        .flexible(true)
+        .from_path(&partial)?;
+    writer.write_record([CHECKPOINT_MAGIC, CHECKPOINT_VERSION, fingerprint])?;
+    for entry in entries {
+        writer.write_record(entry.to_csv_record())?;
+    }
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Extracts and merges all streets, POIs and places from a `.pbf` or `.osm`
+/// file.
+pub fn extract_entries(input_path: &Path, options: &ExtractOptions) -> Result<Vec<StreetEntry>> {
+    extract_entries_from(&[input_path.to_path_buf()], options)
+}
+
+/// Like [`extract_entries`] over several files, e.g. the tiles of a region.
+/// Places from every file feed one shared index, so a street near a tile
+/// edge can still be associated with a town in the neighboring tile.
+///
+/// With `options.checkpoint`, the un-merged rows are saved to that file once
+/// every input has been read and city-associated, which is the bulk of a
+/// large run. A later run with the same inputs and options starts from the
+/// saved rows and only merges, filters and writes; the options it may change
+/// are the ones `checkpoint_fingerprint` leaves out. A run interrupted before
+/// the checkpoint is saved starts over; a stale checkpoint is rebuilt.
+pub fn extract_entries_from(
+    input_paths: &[PathBuf],
+    options: &ExtractOptions,
+) -> Result<Vec<StreetEntry>> {
+    let deadline = Deadline::start(options.timeout);
+    let fingerprint = match &options.checkpoint {
+        Some(_) => Some(checkpoint_fingerprint(input_paths, options)?),
+        None => None,
+    };
+    // Boundaries and places are only found while reading, so a run that
+    // writes them never starts from a checkpoint.
+    let reads_inputs = options.boundaries_output.is_some() || options.places_output.is_some();
+    let saved = match (&options.checkpoint, &fingerprint) {
+        (Some(path), Some(fingerprint)) if !reads_inputs => load_checkpoint(path, fingerprint)?,
+        _ => None,
+    };
+    let entries = match saved {
+        Some(entries) => entries,
+        None => {
+            let mut inputs = Vec::with_capacity(input_paths.len());
+            for input_path in input_paths {
+                inputs.push(collect_input(input_path, options, deadline)?);
+            }
+            if let Some(path) = &options.boundaries_output {
+                write_boundaries(path, inputs.iter().flat_map(|input| &input.boundaries))?;
+            }
+            if let Some(path) = &options.places_output {
+                write_places(path, inputs.iter().flat_map(|input| &input.place_nodes))?;
+            }
+            let entries = resolve_features(inputs, options);
+            if let (Some(path), Some(fingerprint)) = (&options.checkpoint, &fingerprint) {
+                save_checkpoint(path, fingerprint, &entries)?;
+            }
+            entries
+        }
+    };
+    let mut merged = merge_entries_until(entries, options, deadline)?;
+    if let Some(distance_km) = options.dedup_distance_km {
+        drop_nearby_duplicates(&mut merged, distance_km);
+    }
+    merged.retain(|entry| passes_area_filters((entry.center_lon, entry.center_lat), options));
+    if let Some(max) = options.max_rows_per_name {
+        cap_rows_per_name(&mut merged, max);
+    }
+    if let Some(rate) = options.sample_rate {
+        merged.retain(|entry| {
+            let center = (entry.center_lon, entry.center_lat);
+            in_sample(&entry.name, &entry.kind, center, rate)
+        });
+    }
+    Ok(merged)
+}
+
+/// Whether the row with this name, kind and center is in the
+/// `sample_rate` subsample. They are hashed (the center to 7 decimals) with
+/// FNV-1a rather than `DefaultHasher`, so the same row is kept on every
+/// run, Rust version and overlapping extract.
+fn in_sample(name: &str, kind: &str, center: (f64, f64), rate: f64) -> bool {
+    let key = format!("{name}\u{1f}{kind}\u{1f}{:.7}\u{1f}{:.7}", center.0, center.1);
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    // The top 53 bits as a uniform value in [0, 1).
+    ((hash >> 11) as f64 / (1u64 << 53) as f64) < rate
+}
+
+/// Reads `.osm`/`.osc` files as XML and `.pbf` (or extension-less) files
+/// as PBF.
+fn collect_input(
+    input_path: &Path,
+    options: &ExtractOptions,
+    deadline: Deadline,
+) -> Result<CollectedInput> {
+    match input_path.extension().and_then(|value| value.to_str()) {
+        Some("osm" | "osc") => collect_osm_xml_entries(input_path, options, deadline),
+        Some("pbf") | None => collect_pbf_entries(input_path, options, deadline),
+        Some(_) => Err(ExtractError::UnsupportedFormat(input_path.display().to_string())),
+    }
+}
+
+/// Streams the rows of `input_path` one at a time, before merging.
+///
+/// The file is read on the first call to `next`, since ways and place
+/// association need every node and place. Rows are then built and
+/// city-annotated one feature at a time, so the caller never holds more than
+/// it keeps. A read error is yielded once and ends the stream.
+///
+/// The rows are **not merged**: a street split into several ways yields one
+/// row per way, and the area filters see each row's own center. Collect the
+/// rows and pass them to [`merge_entries`] to merge them.
+pub fn stream_entries<'a>(
+    input_path: &'a Path,
+    options: &'a ExtractOptions,
+) -> impl Iterator<Item = Result<StreetEntry>> + 'a {
+    let mut pending = Some(input_path);
+    let mut rows: Box<dyn Iterator<Item = StreetEntry> + 'a> = Box::new(std::iter::empty());
+    std::iter::from_fn(move || {
+        if let Some(path) = pending.take() {
+            match collect_input(path, options, Deadline::start(options.timeout)) {
+                Ok(input) => {
+                    rows = Box::new(resolved_rows(vec![input], options).filter(|entry| {
+                        passes_area_filters((entry.center_lon, entry.center_lat), options)
+                    }));
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        rows.next().map(Ok)
+    })
+}
+
+/// Inclusion filters (`bbox`, `clip`) are applied first; a center that
+/// passes them is still dropped if it falls in any excluded box.
+fn passes_area_filters(center: (f64, f64), options: &ExtractOptions) -> bool {
+    if options.bbox.is_some_and(|bbox| !bbox.contains(center)) {
+        return false;
+    }
+    if options.clip.as_ref().is_some_and(|clip| !clip.contains(center)) {
+        return false;
+    }
+    !options.exclude_bboxes.iter().any(|bbox| bbox.contains(center))
+}
+
+/// Like [`extract_entries`], but runs `hook` on every merged entry. The hook
+/// may rewrite the entry in place; entries for which it returns `false` are
+/// dropped.
+///
+/// ```
+/// use extract_street_polygons::{extract_entries_with, ExtractOptions};
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let input = dir.path().join("sample.osm");
+/// std::fs::write(
+///     &input,
+///     r#"<osm version="0.6">
+///   <node id="1" lat="0.0" lon="0.0" />
+///   <node id="2" lat="0.001" lon="0.0" />
+///   <node id="3" lat="0.0" lon="0.001"><tag k="place" v="town" /><tag k="name" v="Here" /></node>
+///   <node id="4" lat="0.0" lon="9.0" />
+///   <node id="5" lat="0.001" lon="9.0" />
+///   <way id="10"><nd ref="1" /><nd ref="2" /><tag k="highway" v="residential" /><tag k="name" v="Near Road" /></way>
+///   <way id="11"><nd ref="4" /><nd ref="5" /><tag k="highway" v="residential" /><tag k="name" v="Far Road" /></way>
+/// </osm>"#,
+/// )
+/// .unwrap();
+///
+/// let streets = extract_entries_with(&input, &ExtractOptions::default(), |entry| {
+///     entry.kind == "street" && entry.city_resolved == "Here"
+/// })
+/// .unwrap();
+/// let names: Vec<&str> = streets.iter().map(|entry| entry.name.as_str()).collect();
+/// assert_eq!(names, ["Near Road"]);
+/// ```
+pub fn extract_entries_with<F>(
+    input_path: &Path,
+    options: &ExtractOptions,
+    mut hook: F,
+) -> Result<Vec<StreetEntry>>
+where
+    F: FnMut(&mut StreetEntry) -> bool,
+{
+    let mut entries = extract_entries(input_path, options)?;
+    entries.retain_mut(|entry| hook(entry));
+    Ok(entries)
+}
+
+/// Extracts `input_path` and writes the merged rows as CSV to `output_path`.
+/// Returns the number of data rows written.
+pub fn extract_to_csv(
+    input_path: &Path,
+    output_path: &Path,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    extract_files_to_csv(&[input_path.to_path_buf()], output_path, options)
+}
+
+/// Extracts several inputs into a single CSV (or JSON, per
+/// `options.format`); see [`extract_entries_from`]. Returns the number of
+/// data rows written. With `options.fail_on_empty`, writing no rows is an
+/// error (the header-only file is still written).
+pub fn extract_files_to_csv(
+    input_paths: &[PathBuf],
+    output_path: &Path,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let gzip = options.gzip || output_path.extension().is_some_and(|ext| ext == "gz");
+    let written = if output_path == Path::new(STDOUT_PATH) {
+        write_maybe_gzipped(input_paths, std::io::stdout().lock(), gzip, options)?
+    } else {
+        if let Some(parent) = output_path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        write_maybe_gzipped(input_paths, File::create(output_path)?, gzip, options)?
+    };
+    if written == 0 && options.fail_on_empty {
+        return Err(ExtractError::invalid_data(format!(
+            "no rows written to {}",
+            output_path.display()
+        )));
+    }
+    Ok(written)
+}
+
+/// [`extract_files_to_writer`], through a gzip encoder if `gzip` is set.
+/// The encoder is finished before returning, so the gzip trailer is
+/// written whenever the extraction succeeds.
+fn write_maybe_gzipped<W: Write>(
+    input_paths: &[PathBuf],
+    out: W,
+    gzip: bool,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    if !gzip {
+        return extract_files_to_writer(input_paths, out, options);
+    }
+    let mut encoder = GzEncoder::new(BufWriter::new(out), Compression::default());
+    let written = extract_files_to_writer(input_paths, &mut encoder, options)?;
+    encoder.finish()?.flush()?;
+    Ok(written)
+}
+
+/// Tag frequencies among the features an extraction keeps, for tuning
+/// filters on an unfamiliar region. See [`tag_stats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TagStats {
+    /// Name keys (`NAME_KEYS` and their `:<lang>` variants) on kept features.
+    pub name_keys: BTreeMap<String, usize>,
+    pub place_types: BTreeMap<String, usize>,
+    /// `highway` values of kept streets.
+    pub highways: BTreeMap<String, usize>,
+    /// `kind` of kept POIs.
+    pub poi_kinds: BTreeMap<String, usize>,
+}
+
+impl TagStats {
+    /// A plain-text report, one section per tally, most frequent first.
+    pub fn report(&self) -> String {
+        let sections = [
+            ("name keys", &self.name_keys),
+            ("place types", &self.place_types),
+            ("highway classes", &self.highways),
+            ("poi categories", &self.poi_kinds),
+        ];
+        let mut report = String::new();
+        for (title, counts) in sections {
+            report.push_str(&format!("{title}:\n"));
+            let mut sorted: Vec<(&String, &usize)> = counts.iter().collect();
+            sorted.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+            for (value, count) in sorted {
+                report.push_str(&format!("  {count:>8}  {value}\n"));
+            }
+        }
+        report
+    }
+}
+
+/// Tallies [`TagStats`] over the places and features `input_paths` yield
+/// under `options`, including its area, name and sample filters, without
+/// resolving cities or writing rows.
+pub fn tag_stats(input_paths: &[PathBuf], options: &ExtractOptions) -> Result<TagStats> {
+    let mut stats = TagStats::default();
+    let bump = |counts: &mut BTreeMap<String, usize>, value: &str| {
+        *counts.entry(value.to_string()).or_default() += 1;
+    };
+    // Only elements that would yield a row under the area, name and sample
+    // filters count. Sampling sees each way's own center, not the center of
+    // the merged row.
+    let kept = |names: &[NameVariant], kind: &str, center: (f64, f64)| {
+        passes_area_filters(center, options)
+            && names.iter().any(|variant| {
+                name_passes_filters(&variant.name, options)
+                    && options
+                        .sample_rate
+                        .is_none_or(|rate| in_sample(&variant.name, kind, center, rate))
+            })
+    };
+    let deadline = Deadline::start(options.timeout);
+    for input_path in input_paths {
+        let input = collect_input(input_path, options, deadline)?;
+        for place in &input.place_nodes {
+            if kept(&place.names, place_row_kind(options), place.coord) {
+                bump(&mut stats.place_types, &place.place_type);
+            }
+        }
+        for feature in &input.features {
+            let center = (feature.entry.center_lon, feature.entry.center_lat);
+            if !kept(&feature.names, &feature.entry.kind, center) {
+                continue;
+            }
+            for key in feature.tags.keys().filter(|key| is_name_key(key)) {
+                bump(&mut stats.name_keys, key);
+            }
+            if is_street(&feature.tags, options) {
+                if let Some(highway) = feature.tags.get("highway") {
+                    bump(&mut stats.highways, highway);
+                }
+            } else if let Some(kind) = poi_kind(&feature.tags, options) {
+                bump(&mut stats.poi_kinds, kind);
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Output path that [`extract_files_to_csv`] reads as standard output.
+pub const STDOUT_PATH: &str = "-";
+
+/// Like [`extract_files_to_csv`], but writes to `out`. Only the rows go to
+/// `out`; nothing else is printed. Returns the number of rows written.
+pub fn extract_files_to_writer<W: Write>(
+    input_paths: &[PathBuf],
+    mut out: W,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let header = output_header(options)?;
+    let all_xml = input_paths.iter().all(|path| is_osm_xml(path));
+    let coord_precision = if all_xml { None } else { Some(7) };
+    let written = match options.format {
+        OutputFormat::Csv => {
+            let (terminator, line_end) = if options.crlf {
+                (Terminator::CRLF, "\r\n")
+            } else {
+                (Terminator::Any(b'\n'), "\n")
+            };
+            if options.schema_comment {
+                write!(out, "# schema_version={SCHEMA_VERSION}{line_end}")?;
+            }
+            let mut writer = WriterBuilder::new().terminator(terminator).from_writer(out);
+            writer.write_record(&header)?;
+
+            let entries = extract_entries_from(input_paths, options)?;
+            let written = write_entries(entries, options, coord_precision, |record, _| {
+                Ok(writer.write_record(&record)?)
+            })?;
+            writer.flush()?;
+            written
+        }
+        OutputFormat::Json => {
+            // A comment line would make the file invalid JSON, so
+            // `schema_comment` only applies to CSV.
+            let entries = extract_entries_from(input_paths, options)?;
+            let mut rows = Vec::with_capacity(entries.len());
+            let written = write_entries(entries, options, coord_precision, |record, _| {
+                let values = record.into_iter().map(serde_json::Value::String);
+                rows.push(serde_json::Value::Object(header.iter().cloned().zip(values).collect()));
+                Ok(())
+            })?;
+            let mut out = BufWriter::new(out);
+            if options.pretty {
+                serde_json::to_writer_pretty(&mut out, &rows)?;
+            } else {
+                serde_json::to_writer(&mut out, &rows)?;
+            }
+            writeln!(out)?;
+            out.flush()?;
+            written
+        }
+        OutputFormat::Jsonl => {
+            let entries = extract_entries_from(input_paths, options)?;
+            let mut out = BufWriter::new(out);
+            let written = write_entries(entries, options, coord_precision, |record, geometry| {
+                let values = record.into_iter().map(serde_json::Value::String);
+                let properties: serde_json::Map<_, _> =
+                    header.iter().cloned().zip(values).collect();
+                let feature = serde_json::json!({
+                    "type": "Feature",
+                    "geometry": geometry.to_geojson(),
+                    "properties": properties,
+                });
+                serde_json::to_writer(&mut out, &feature)?;
+                Ok(writeln!(out)?)
+            })?;
+            out.flush()?;
+            written
+        }
+    };
+    Ok(written)
+}
+
+/// Shape of the synthetic extract written by [`write_synthetic_osm`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SyntheticOsm {
+    /// Way nodes, spread evenly over the ways.
+    pub nodes: usize,
+    pub ways: usize,
+    /// Distinct street names; fewer names than ways means more merging.
+    pub names: usize,
+}
+
+/// Writes a reproducible extract for benchmarks: `ways` named residential
+/// streets on a 0.001° grid, with a town every 100 ways. `path` gets PBF
+/// if it ends in `.pbf` (which needs the `pbf-writer` feature) and OSM XML
+/// otherwise.
+pub fn write_synthetic_osm(path: &Path, shape: SyntheticOsm) -> Result<()> {
+    if shape.ways == 0 || shape.nodes < 2 * shape.ways || shape.names == 0 {
+        return Err(ExtractError::bad_args(
+            "synthetic extracts need ways, names and at least 2 nodes per way",
+        ));
+    }
+    let pbf = path.extension().is_some_and(|ext| ext == "pbf");
+    if pbf && !cfg!(feature = "pbf-writer") {
+        return Err(ExtractError::bad_args(
+            "writing a synthetic .pbf needs the `pbf-writer` feature",
+        ));
+    }
+    let per_way = shape.nodes / shape.ways;
+    let columns = (shape.ways as f64).sqrt().ceil() as usize;
+    let coord = |way: usize, step: usize| {
+        let lon = 13.0 + (way % columns) as f64 * 0.001 + step as f64 * 0.0001;
+        let lat = 52.0 + (way / columns) as f64 * 0.001;
+        (lon, lat)
+    };
+    let mut nodes = Vec::with_capacity(shape.ways * per_way + shape.ways / 100 + 1);
+    for way in 0..shape.ways {
+        for step in 0..per_way {
+            let id = way * per_way + step + 1;
+            nodes.push(SyntheticElement::node(id, coord(way, step), Vec::new()));
+        }
+    }
+    for (town, way) in (0..shape.ways).step_by(100).enumerate() {
+        let id = shape.ways * per_way + town + 1;
+        let tags = vec![("place", "town".to_string()), ("name", format!("Town {town}"))];
+        nodes.push(SyntheticElement::node(id, coord(way, 0), tags));
+    }
+    let ways: Vec<SyntheticElement> = (0..shape.ways)
+        .map(|way| SyntheticElement {
+            id: way + 1,
+            coord: (0.0, 0.0),
+            refs: (0..per_way).map(|step| way * per_way + step + 1).collect(),
+            tags: vec![
+                ("highway", "residential".to_string()),
+                ("name", format!("Street {}", way % shape.names)),
+            ],
+        })
+        .collect();
+
+    let mut out = BufWriter::new(File::create(path)?);
+    if pbf {
+        #[cfg(feature = "pbf-writer")]
+        synthetic_pbf::write_synthetic_pbf(&mut out, &nodes, &ways)?;
+    } else {
+        write_synthetic_xml(&mut out, &nodes, &ways)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// A node (`coord`) or way (`refs`) of [`write_synthetic_osm`].
+pub(crate) struct SyntheticElement {
+    pub(crate) id: usize,
+    pub(crate) coord: (f64, f64),
+    pub(crate) refs: Vec<usize>,
+    pub(crate) tags: Vec<(&'static str, String)>,
+}
+
+impl SyntheticElement {
+    fn node(id: usize, coord: (f64, f64), tags: Vec<(&'static str, String)>) -> Self {
+        Self {
+            id,
+            coord,
+            refs: Vec::new(),
+            tags,
+        }
+    }
+}
+
+fn write_synthetic_xml(
+    out: &mut impl Write,
+    nodes: &[SyntheticElement],
+    ways: &[SyntheticElement],
+) -> Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<osm version="0.6" generator="extract_street_polygons gen-bench">"#)?;
+    let write_tags = |out: &mut dyn Write, tags: &[(&str, String)]| -> Result<()> {
+        for (key, value) in tags {
+            writeln!(out, r#"    <tag k="{key}" v="{value}" />"#)?;
+        }
+        Ok(())
+    };
+    for node in nodes {
+        let (id, (lon, lat)) = (node.id, node.coord);
+        if node.tags.is_empty() {
+            writeln!(out, r#"  <node id="{id}" lat="{lat:.7}" lon="{lon:.7}" />"#)?;
+            continue;
+        }
+        writeln!(out, r#"  <node id="{id}" lat="{lat:.7}" lon="{lon:.7}">"#)?;
+        write_tags(out, &node.tags)?;
+        writeln!(out, "  </node>")?;
+    }
+    for way in ways {
+        writeln!(out, r#"  <way id="{}">"#, way.id)?;
+        for node in &way.refs {
+            writeln!(out, r#"    <nd ref="{node}" />"#)?;
+        }
+        write_tags(out, &way.tags)?;
+        writeln!(out, "  </way>")?;
+    }
+    writeln!(out, "</osm>")?;
+    Ok(())
+}
+
+/// Default distance a row's center must shift before `diff` reports it as
+/// moved.
+pub const DEFAULT_MOVE_THRESHOLD_KM: f64 = 0.05;
+
+/// Counts of the changes [`diff_outputs`] found.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiffSummary {
+    pub added: usize,
+    pub removed: usize,
+    pub moved: usize,
+}
+
+/// The identity and center of one row of an earlier CSV output.
+struct OutputRow {
+    key: [String; 3],
+    center: (f64, f64),
+}
+
+/// Reads the `streetname`, `kind`, `city_resolved` and center columns of a
+/// CSV written by this tool, skipping a `# schema_version` line. Centers
+/// written with `--crs 3857` are projected back to degrees.
+fn read_output_rows(path: &Path) -> Result<Vec<OutputRow>> {
+    let mut reader = ReaderBuilder::new().comment(Some(b'#')).from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let position = |name: &str| headers.iter().position(|header| header == name);
+    let missing = |name: &str| {
+        ExtractError::invalid_data(format!(
+            "{} has no {name} column; outputs written with --header-map or --columns \
+             cannot be diffed",
+            path.display()
+        ))
+    };
+    let column = |name: &str| position(name).ok_or_else(|| missing(name));
+    let key_columns = [column("streetname")?, column("kind")?, column("city_resolved")?];
+    let centers = [Crs::Wgs84, Crs::WebMercator].into_iter().find_map(|crs| {
+        let [x, y] = crs.center_headers();
+        Some((crs, position(x)?, position(y)?))
+    });
+    let (crs, x, y) = centers.ok_or_else(|| missing("center_lon/center_lat or x/y"))?;
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        rows.push(OutputRow {
+            key: key_columns.map(|idx| record[idx].to_string()),
+            center: crs.unproject((record[x].parse()?, record[y].parse()?)),
+        });
+    }
+    Ok(rows)
+}
+
+/// Compares two outputs of this tool and writes one CSV row per change.
+///
+/// Rows are keyed on name, kind and resolved city. Each old row is paired
+/// with the nearest unpaired new row of the same key. It is `removed` if
+/// there is none and `moved` if the centers are more than
+/// `move_threshold_km` apart. New rows left unpaired are `added`.
+/// Unchanged rows are not written.
+pub fn diff_outputs(
+    old_path: &Path,
+    new_path: &Path,
+    output_path: &Path,
+    move_threshold_km: f64,
+) -> Result<DiffSummary> {
+    let old_rows = read_output_rows(old_path)?;
+    let new_rows = read_output_rows(new_path)?;
+    let mut by_key: HashMap<&[String; 3], Vec<usize>> = HashMap::new();
+    for (idx, row) in new_rows.iter().enumerate() {
+        by_key.entry(&row.key).or_default().push(idx);
+    }
+
+    let mut writer = Writer::from_path(output_path)?;
+    writer.write_record([
+        "change",
+        "streetname",
+        "kind",
+        "city_resolved",
+        "old_center_lon",
+        "old_center_lat",
+        "new_center_lon",
+        "new_center_lat",
+        "distance_km",
+    ])?;
+    let mut summary = DiffSummary::default();
+    let mut paired = vec![false; new_rows.len()];
+    for old in &old_rows {
+        let nearest = by_key.get(&old.key).and_then(|candidates| {
+            candidates
+                .iter()
+                .filter(|&&idx| !paired[idx])
+                .map(|&idx| (idx, haversine_km(old.center, new_rows[idx].center)))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+        });
+        let (change, new_center, distance) = match nearest {
+            None => ("removed", None, None),
+            Some((idx, distance)) => {
+                paired[idx] = true;
+                if distance <= move_threshold_km {
+                    continue;
+                }
+                ("moved", Some(new_rows[idx].center), Some(distance))
+            }
+        };
+        if change == "removed" {
+            summary.removed += 1;
+        } else {
+            summary.moved += 1;
+        }
+        write_change(&mut writer, change, &old.key, Some(old.center), new_center, distance)?;
+    }
+    for (new, _) in new_rows.iter().zip(&paired).filter(|(_, paired)| !**paired) {
+        summary.added += 1;
+        write_change(&mut writer, "added", &new.key, None, Some(new.center), None)?;
+    }
+    writer.flush()?;
+    Ok(summary)
+}
+
+fn write_change(
+    writer: &mut Writer<File>,
+    change: &str,
+    key: &[String; 3],
+    old_center: Option<(f64, f64)>,
+    new_center: Option<(f64, f64)>,
+    distance_km: Option<f64>,
+) -> Result<()> {
+    let coord = |center: Option<(f64, f64)>, pick: fn((f64, f64)) -> f64| {
+        center.map(|center| pick(center).to_string()).unwrap_or_default()
+    };
+    writer.write_record([
+        change.to_string(),
+        key[0].clone(),
+        key[1].clone(),
+        key[2].clone(),
+        coord(old_center, |c| c.0),
+        coord(old_center, |c| c.1),
+        coord(new_center, |c| c.0),
+        coord(new_center, |c| c.1),
+        distance_km.map(|km| format!("{km:.3}")).unwrap_or_default(),
+    ])?;
+    Ok(())
+}
+
+fn parse_population(value: &str) -> Option<u64> {
+    let digits: String = value.chars().filter(|ch| ch.is_ascii_digit()).collect();
+    if digits.is_empty() {
+        return None;
+    }
+    digits.parse::<u64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::ReaderBuilder;
+    use tempfile::tempdir;
+
+    const OSM_SAMPLE: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="1.0" />
+  <node id="3" lat="1.0" lon="1.0" />
+  <node id="4" lat="1.0" lon="0.0" />
+  <node id="5" lat="2.0" lon="0.0" />
+  <node id="6" lat="2.0" lon="1.0" />
+  <node id="7" lat="0.6" lon="0.6">
+    <tag k="place" v="town" />
+    <tag k="name" v="Placetown" />
+    <tag k="name:fr" v="Ville Place" />
+    <tag k="population" v="12345" />
+  </node>
+  <node id="100" lat="-1.0" lon="-1.0" />
+  <node id="101" lat="-1.0" lon="3.0" />
+  <node id="102" lat="3.0" lon="3.0" />
+  <node id="103" lat="3.0" lon="-1.0" />
+  <node id="200" lat="-5.0" lon="-5.0" />
+  <node id="201" lat="-5.0" lon="5.0" />
+  <node id="202" lat="5.0" lon="5.0" />
+  <node id="203" lat="5.0" lon="-5.0" />
+  <way id="10">
+    <nd ref="1" />
+    <nd ref="2" />
+    <nd ref="3" />
+    <nd ref="4" />
+    <nd ref="1" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Main Street" />
+    <tag k="alt_name" v="Old Main" />
+    <tag k="is_in" v="Placetown, Testland" />
+    <tag k="surface" v="asphalt" />
+  </way>
+  <way id="11">
+    <nd ref="4" />
+    <nd ref="5" />
+    <nd ref="6" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Open Way" />
+  </way>
+  <way id="300">
+    <nd ref="100" />
+    <nd ref="101" />
+    <nd ref="102" />
+    <nd ref="103" />
+    <nd ref="100" />
+    <tag k="boundary" v="administrative" />
+    <tag k="admin_level" v="8" />
+    <tag k="name" v="Testville" />
+  </way>
+  <way id="301">
+    <nd ref="200" />
+    <nd ref="201" />
+    <nd ref="202" />
+    <nd ref="203" />
+    <nd ref="200" />
+    <tag k="boundary" v="administrative" />
+    <tag k="admin_level" v="2" />
+    <tag k="name" v="Testland" />
+  </way>
+</osm>
+"#;
+
+    const OSM_MULTI_NAME: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="2.0" />
+  <node id="3" lat="2.0" lon="2.0" />
+  <node id="4" lat="2.0" lon="0.0" />
+  <way id="20">
+    <nd ref="1" />
+    <nd ref="2" />
+    <nd ref="3" />
+    <nd ref="4" />
+    <nd ref="1" />
+    <tag k="highway" v="primary" />
+    <tag k="name" v="First;Second" />
+    <tag k="name:de" v="Erste Straße" />
+  </way>
+</osm>
+"#;
+
+    const OSM_LARGER_PLACE: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.2" />
+  <node id="10" lat="0.0" lon="0.1">
+    <tag k="place" v="hamlet" />
+    <tag k="name" v="Tinyham" />
+  </node>
+  <node id="11" lat="0.0" lon="0.5">
+    <tag k="place" v="town" />
+    <tag k="name" v="Bigtown" />
+  </node>
+  <way id="20">
+    <nd ref="1" />
+    <nd ref="2" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Hamlet Road" />
+  </way>
+</osm>
+"#;
+
+    const OSM_MERGE_NEARBY: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.001" lon="0.0" />
+  <node id="3" lat="0.002" lon="0.0" />
+  <node id="10" lat="0.001" lon="0.001">
+    <tag k="place" v="city" />
+    <tag k="name" v="Testville" />
+    <tag k="population" v="100000" />
+  </node>
+  <way id="40">
+    <nd ref="1" />
+    <nd ref="2" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Dave Burns Drive" />
+  </way>
+  <way id="41">
+    <nd ref="2" />
+    <nd ref="3" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Dave Burns Drive" />
+  </way>
+</osm>
+"#;
+
+    const OSM_MERGE_DIFFERENT_CITY: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.001" lon="0.0" />
+  <node id="3" lat="0.008" lon="0.0" />
+  <node id="4" lat="0.009" lon="0.0" />
+  <node id="10" lat="0.001" lon="0.001">
+    <tag k="place" v="city" />
+    <tag k="name" v="Alpha City" />
+    <tag k="population" v="50000" />
+  </node>
+  <node id="11" lat="0.0085" lon="0.001">
+    <tag k="place" v="city" />
+    <tag k="name" v="Beta City" />
+    <tag k="population" v="75000" />
+  </node>
+  <way id="40">
+    <nd ref="1" />
+    <nd ref="2" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Dave Burns Drive" />
+  </way>
+  <way id="41">
+    <nd ref="3" />
+    <nd ref="4" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Dave Burns Drive" />
+  </way>
+</osm>
+"#;
+
+    const OSM_ASSOCIATED_STREET: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="10.0" lon="10.0" />
+  <node id="2" lat="10.0" lon="10.01" />
+  <node id="3" lat="10.001" lon="10.002">
+    <tag k="name" v="Harbor Museum" />
+    <tag k="tourism" v="museum" />
+  </node>
+  <node id="4" lat="10.001" lon="10.004">
+    <tag k="name" v="Dock Library" />
+    <tag k="amenity" v="library" />
+    <tag k="addr:street" v="Pier Road" />
+  </node>
+  <way id="10">
+    <nd ref="1" />
+    <nd ref="2" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Quay Street" />
+  </way>
+  <relation id="50">
+    <member type="way" ref="10" role="street" />
+    <member type="node" ref="3" role="house" />
+    <member type="node" ref="4" role="house" />
+    <tag k="type" v="associatedStreet" />
+  </relation>
+</osm>
+"#;
+
+    const OSM_POI: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="48.8584" lon="2.2945">
+    <tag k="name" v="Eiffel Tower" />
+    <tag k="tourism" v="attraction" />
+    <tag k="wikipedia" v="en:Eiffel_Tower" />
+    <tag k="wikidata" v="Q243" />
+  </node>
+  <node id="2" lat="48.0" lon="2.0">
+    <tag k="name" v="Local Statue" />
+    <tag k="tourism" v="attraction" />
+  </node>
+  <node id="3" lat="40.0" lon="-73.0">
+    <tag k="name" v="Central Station" />
+    <tag k="railway" v="station" />
+  </node>
+  <node id="4" lat="41.0" lon="-74.0">
+    <tag k="name" v="Main Bus Stop" />
+    <tag k="highway" v="bus_stop" />
+  </node>
+  <node id="5" lat="42.0" lon="-75.0">
+    <tag k="name" v="City Airport" />
+    <tag k="aeroway" v="aerodrome" />
+  </node>
+  <node id="6" lat="43.0" lon="-76.0">
+    <tag k="name" v="Harbor Ferry Terminal" />
+    <tag k="amenity" v="ferry_terminal" />
+  </node>
+  <node id="7" lat="44.0" lon="-77.0">
+    <tag k="name" v="State University" />
+    <tag k="amenity" v="university" />
+  </node>
+  <node id="8" lat="45.0" lon="-78.0">
+    <tag k="name" v="City Museum" />
+    <tag k="tourism" v="museum" />
+  </node>
+  <node id="9" lat="46.0" lon="-79.0">
+    <tag k="name" v="Central Library" />
+    <tag k="amenity" v="library" />
+  </node>
+</osm>
+"#;
+
+    const OSM_METADATA: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.001" lon="0.0" />
+  <node id="3" lat="0.002" lon="0.0" />
+  <node id="4" lat="1.0" lon="1.0" version="7" timestamp="2021-03-04T05:06:07Z">
+    <tag k="name" v="Central Station" />
+    <tag k="railway" v="station" />
+  </node>
+  <node id="10" lat="0.001" lon="0.001">
+    <tag k="place" v="city" />
+    <tag k="name" v="Testville" />
+  </node>
+  <way id="40" version="3" timestamp="2019-01-01T00:00:00Z">
+    <nd ref="1" />
+    <nd ref="2" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Dave Burns Drive" />
+  </way>
+  <way id="41" version="12" timestamp="2023-06-15T12:00:00Z">
+    <nd ref="2" />
+    <nd ref="3" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Dave Burns Drive" />
+  </way>
+</osm>
+"#;
+
+    const OSM_POI_RELATION: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="10.0" lon="10.0" />
+  <node id="2" lat="10.0" lon="10.2" />
+  <node id="3" lat="10.2" lon="10.2" />
+  <node id="4" lat="10.2" lon="10.0" />
+  <way id="50">
+    <nd ref="1" />
+    <nd ref="2" />
+    <nd ref="3" />
+  </way>
+  <way id="51">
+    <nd ref="1" />
+    <nd ref="4" />
+    <nd ref="3" />
+  </way>
+  <relation id="60">
+    <member type="way" ref="50" role="outer" />
+    <member type="way" ref="51" role="outer" />
+    <tag k="type" v="multipolygon" />
+    <tag k="tourism" v="attraction" />
+    <tag k="name" v="Castle Grounds" />
+    <tag k="wikidata" v="Q123" />
+  </relation>
+</osm>
+"#;
+
+    const OSM_NEARBY_POIS: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="45.02" lon="7.63">
+    <tag k="name" v="Corner Stop" />
+    <tag k="highway" v="bus_stop" />
+  </node>
+  <node id="2" lat="45.04" lon="7.61">
+    <tag k="name" v="Corner Stop" />
+    <tag k="highway" v="bus_stop" />
+  </node>
+</osm>
+"#;
+
+    const OSM_ONE_KM: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0089932" lon="0.0" />
+  <way id="10">
+    <nd ref="1" />
+    <nd ref="2" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Kilometer Lane" />
+  </way>
+</osm>
+"#;
+
+    const OSM_NONAME: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.01" />
+  <node id="3" lat="0.0" lon="0.02" />
+  <way id="10">
+    <nd ref="1" />
+    <nd ref="2" />
+    <tag k="highway" v="service" />
+    <tag k="name" v="Stale Name" />
+    <tag k="noname" v="yes" />
+  </way>
+  <way id="11">
+    <nd ref="2" />
+    <nd ref="3" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Real Street" />
+  </way>
+</osm>
+"#;
+
+    const OSM_PEDESTRIAN_SQUARE: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.000898315" />
+  <node id="3" lat="0.000898315" lon="0.000898315" />
+  <node id="4" lat="0.000898315" lon="0.0" />
+  <way id="10">
+    <nd ref="1" />
+    <nd ref="2" />
+    <nd ref="3" />
+    <nd ref="4" />
+    <nd ref="1" />
+    <tag k="highway" v="pedestrian" />
+    <tag k="area" v="yes" />
+    <tag k="name" v="Market Square" />
+  </way>
+  <way id="11">
+    <nd ref="1" />
+    <nd ref="2" />
+    <nd ref="3" />
+    <nd ref="4" />
+    <nd ref="1" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Loop Road" />
+  </way>
+</osm>
+"#;
+
+    fn extract_rows(osm: &str, options: &ExtractOptions) -> Vec<Vec<String>> {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, osm).unwrap();
+
+        extract_to_csv(&osm_path, &out_path, options).unwrap();
+
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(&out_path)
+            .unwrap();
+        reader
+            .records()
+            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
+            .collect()
+    }
+
+    fn column(rows: &[Vec<String>], header: &str) -> usize {
+        rows[0]
+            .iter()
+            .position(|value| value == header)
+            .unwrap_or_else(|| panic!("missing column {header}"))
+    }
+
+    #[test]
+    fn split_and_collect_names() {
+        let mut tags = Tags::new();
+        tags.insert("name".into(), "Main St;Second St".into());
+        tags.insert("alt_name".into(), "Alt".into());
+        tags.insert("name:de".into(), "Hauptstraße".into());
+        tags.insert("foo".into(), "bar".into());
+
+        let variants = collect_name_variants(&tags, &ExtractOptions::default());
+        let results: Vec<(String, String)> = variants
+            .into_iter()
+            .map(|variant| (variant.name, variant.lang))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("Main St".to_string(), "".to_string()),
+                ("Second St".to_string(), "".to_string()),
+                ("Alt".to_string(), "".to_string()),
+                ("Hauptstraße".to_string(), "de".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn polygon_centroid_square() {
+        let coords = vec![(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0), (0.0, 0.0)];
+        let (cx, cy) = polygon_centroid(&coords).unwrap();
+        assert!((cx - 1.0).abs() < 1e-9);
+        assert!((cy - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn polygon_centroid_ignores_winding_and_closure() {
+        let ccw = vec![(10.0, 50.0), (10.002, 50.0), (10.002, 50.001), (10.0, 50.001)];
+        let cw: Vec<(f64, f64)> = ccw.iter().rev().copied().collect();
+        let mut closed_cw = cw.clone();
+        closed_cw.push(cw[0]);
+
+        let expected = (10.001, 50.0005);
+        for ring in [&ccw, &cw, &closed_cw] {
+            let (cx, cy) = polygon_centroid(ring).unwrap();
+            assert!((cx - expected.0).abs() < 1e-9, "{ring:?} -> {cx}");
+            assert!((cy - expected.1).abs() < 1e-9, "{ring:?} -> {cy}");
+        }
+        assert!((ring_area(&ccw) + ring_area(&cw)).abs() < 1e-15);
+        assert!((ring_area_km2(&ccw) - ring_area_km2(&cw)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn polygon_centroid_sliver_stays_on_the_ring() {
+        // Nearly collinear in either winding: falls back to the vertex mean.
+        let sliver = vec![(0.0, 0.0), (1.0, 1e-13), (2.0, 0.0), (0.0, 0.0)];
+        let reversed: Vec<(f64, f64)> = sliver.iter().rev().copied().collect();
+        for ring in [&sliver, &reversed] {
+            let (cx, cy) = polygon_centroid(ring).unwrap();
+            assert!((cx - 1.0).abs() < 1e-9);
+            assert!(cy.abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn line_midpoint_basic() {
+        let coords = vec![(0.0, 0.0), (4.0, 0.0)];
+        let (mx, my) = line_midpoint(&coords).unwrap();
+        assert!((mx - 2.0).abs() < 1e-9);
+        assert!(my.abs() < 1e-9);
+    }
+
+    #[test]
+    fn place_index_picks_nearest_and_filters() {
+        let places = vec![
+            PlaceNode::new(
+                vec![NameVariant {
+                    name: "Near".to_string(),
+                    lang: "".to_string(),
+                }],
+                "town".to_string(),
+                (0.0, 0.0),
+                None,
+            ),
+            PlaceNode::new(
+                vec![NameVariant {
+                    name: "Far".to_string(),
+                    lang: "".to_string(),
+                }],
+                "hamlet".to_string(),
+                (5.0, 5.0),
+                None,
+            ),
+        ];
+        let index = PlaceIndex::new(places, 1.0);
+
+        let nearest = index
+            .nearest((0.1, 0.1), PlaceFilter::ANY)
+            .unwrap();
+        assert_eq!(nearest.name, "Near");
+
+        let filtered = index
+            .nearest((0.1, 0.1), PlaceFilter::at_least(PlaceRank::Town))
+            .map(|place| place.name.clone());
+        assert_eq!(filtered.as_deref(), Some("Near"));
+    }
+
+    fn grid_places(origin: (f64, f64), step: f64, side: usize) -> Vec<PlaceNode> {
+        let mut places = Vec::new();
+        for i in 0..side {
+            for j in 0..side {
+                places.push(PlaceNode::new(
+                    vec![NameVariant {
+                        name: format!("Place {i}/{j}"),
+                        lang: "".to_string(),
+                    }],
+                    "village".to_string(),
+                    (origin.0 + i as f64 * step, origin.1 + j as f64 * step),
+                    None,
+                ));
+            }
+        }
+        places
+    }
+
+    #[test]
+    fn place_index_auto_cell_size_follows_density() {
+        let dense = grid_places((13.0, 52.0), 0.005, 40);
+        let sparse = grid_places((0.0, 0.0), 0.5, 40);
+        let dense_size = PlaceIndex::auto_cell_size(&dense);
+        let sparse_size = PlaceIndex::auto_cell_size(&sparse);
+        assert!((dense_size - MIN_PLACE_CELL_DEG).abs() < 1e-9);
+        assert!(sparse_size > 1.0 && sparse_size < MAX_PLACE_CELL_DEG);
+        assert_eq!(PlaceIndex::auto_cell_size(&[]), DEFAULT_PLACE_CELL_DEG);
+        assert!(PlaceCellSize::parse("0.00001").is_err());
+        assert!(PlaceCellSize::parse("90").is_err());
+        assert_eq!(PlaceCellSize::parse("0.5").unwrap(), PlaceCellSize::Fixed(0.5));
+
+        let fixed = PlaceIndex::with_cell_size(dense.clone(), PlaceCellSize::default());
+        let auto = PlaceIndex::with_cell_size(dense, PlaceCellSize::Auto);
+        for query in [(13.01, 52.01), (13.1, 52.17), (12.5, 51.9)] {
+            let any = PlaceFilter::at_least(PlaceRank::Locality);
+            let expected = fixed.nearest(query, any).map(|p| p.coord);
+            let actual = auto.nearest(query, any).map(|p| p.coord);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn extract_to_csv_from_osm() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("sample.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+
+        extract_to_csv(&osm_path, &out_path, &ExtractOptions::default()).unwrap();
+
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(&out_path)
+            .unwrap();
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
+            .collect();
+
+        let expected_headers: Vec<String> =
+            COLUMNS.iter().map(|header| (*header).to_string()).collect();
+        assert_eq!(rows[0], expected_headers);
+        let mut names: Vec<String> =
+            rows[1..].iter().map(|row| row[0].to_string()).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "Main Street",
+                "Old Main",
+                "Open Way",
+                "Placetown",
+                "Ville Place",
+            ]
+        );
+
+        let open_row = rows
+            .iter()
+            .skip(1)
+            .find(|row| row[0] == "Open Way")
+            .unwrap();
+        let idx = |name: &str| {
+            COLUMNS
+                .iter()
+                .position(|header| header == &name)
+                .unwrap()
+        };
+        assert_eq!(open_row[idx("name_lang")], "");
+        assert_eq!(open_row[idx("kind")], "street");
+        assert_eq!(open_row[idx("center_lon")], "0");
+        assert_eq!(open_row[idx("center_lat")], "2");
+        assert_eq!(open_row[idx("city_place_node")], "");
+        assert_eq!(open_row[idx("city_place_type")], "");
+        assert_eq!(open_row[idx("city_place_city")], "");
+        assert_eq!(open_row[idx("city_resolved")], "");
+        assert_eq!(open_row[idx("city_population")], "");
+
+        let main_row = rows
+            .iter()
+            .skip(1)
+            .find(|row| row[0] == "Main Street")
+            .unwrap();
+        assert_eq!(main_row[idx("name_lang")], "");
+        assert_eq!(main_row[idx("kind")], "street");
+        assert_eq!(main_row[idx("city_place_node")], "Placetown");
+        assert_eq!(main_row[idx("city_place_type")], "town");
+        assert_eq!(main_row[idx("city_place_city")], "Placetown");
+        assert_eq!(main_row[idx("city_resolved")], "Placetown");
+        assert_eq!(main_row[idx("city_population")], "12345");
+
+        let translated_row = rows
+            .iter()
+            .skip(1)
+            .find(|row| row[0] == "Ville Place")
+            .unwrap();
+        assert_eq!(translated_row[idx("name_lang")], "fr");
+    }
+
+    #[test]
+    fn extract_to_csv_splits_multi_names() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("multi.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, OSM_MULTI_NAME).unwrap();
+
+        extract_to_csv(&osm_path, &out_path, &ExtractOptions::default()).unwrap();
+
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(&out_path)
+            .unwrap();
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
+            .collect();
+
+        let names: Vec<&str> = rows[1..].iter().map(|row| row[0].as_str()).collect();
+        assert_eq!(names, vec!["First", "Second", "Erste Straße"]);
+    }
+
+    #[test]
+    fn extract_to_csv_promotes_town_or_city() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("larger_place.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, OSM_LARGER_PLACE).unwrap();
+
+        extract_to_csv(&osm_path, &out_path, &ExtractOptions::default()).unwrap();
+
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(&out_path)
+            .unwrap();
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
+            .collect();
+
+        let hamlet_row = rows
+            .iter()
+            .skip(1)
+            .find(|row| row[0] == "Hamlet Road")
+            .unwrap();
+        assert_eq!(hamlet_row[2], "street");
+        assert_eq!(hamlet_row[5], "Tinyham");
+        assert_eq!(hamlet_row[6], "hamlet");
+        assert_eq!(hamlet_row[7], "Bigtown");
+        assert_eq!(hamlet_row[8], "Bigtown");
+    }
+
+    #[test]
+    fn extract_to_csv_merges_nearby_segments() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("merge.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, OSM_MERGE_NEARBY).unwrap();
+
+        extract_to_csv(&osm_path, &out_path, &ExtractOptions::default()).unwrap();
+
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(&out_path)
+            .unwrap();
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
+            .collect();
+
+        let data_rows: Vec<&Vec<String>> = rows
+            .iter()
+            .skip(1)
+            .filter(|row| row[2] != "city")
+            .collect();
+        assert_eq!(data_rows.len(), 1);
+        assert_eq!(data_rows[0][0], "Dave Burns Drive");
+
+        let lat: f64 = data_rows[0][4].parse().unwrap();
+        assert!((lat - 0.001).abs() < 1e-9);
+        let segment_count = COLUMNS
+            .iter()
+            .position(|header| *header == "segment_count")
+            .unwrap();
+        assert_eq!(data_rows[0][segment_count], "2");
+        let city_row = rows.iter().find(|row| row[2] == "city").unwrap();
+        assert_eq!(city_row[segment_count], "1");
+    }
+
+    #[test]
+    fn extract_to_csv_keeps_same_name_separate_for_different_cities() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("merge_city.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, OSM_MERGE_DIFFERENT_CITY).unwrap();
+
+        extract_to_csv(&osm_path, &out_path, &ExtractOptions::default()).unwrap();
+
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(&out_path)
+            .unwrap();
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
+            .collect();
+
+        let data_rows: Vec<&Vec<String>> = rows
+            .iter()
+            .skip(1)
+            .filter(|row| row[2] != "city")
+            .collect();
+        assert_eq!(data_rows.len(), 2);
+        let mut cities: Vec<&str> = data_rows.iter().map(|row| row[8].as_str()).collect();
+        cities.sort();
+        assert_eq!(cities, vec!["Alpha City", "Beta City"]);
+    }
+
+    #[test]
+    fn extract_to_csv_includes_poi_and_filters_minor_sights() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("poi.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, OSM_POI).unwrap();
+
+        extract_to_csv(&osm_path, &out_path, &ExtractOptions::default()).unwrap();
+
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(&out_path)
+            .unwrap();
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
+            .collect();
+        let mut names: Vec<String> = rows.iter().skip(1).map(|row| row[0].to_string()).collect();
+        names.sort();
+        let expected: Vec<String> = vec![
+            "Central Library",
+            "Central Station",
+            "City Airport",
+            "City Museum",
+            "Eiffel Tower",
+            "Harbor Ferry Terminal",
+            "Main Bus Stop",
+            "State University",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(names, expected);
+
+        let mut kinds: Vec<String> = rows.iter().skip(1).map(|row| row[2].to_string()).collect();
+        kinds.sort();
+        let expected_kinds: Vec<String> = vec![
+            "airport",
+            "bus_stop",
+            "civic_building",
+            "ferry_terminal",
+            "museum",
+            "sight",
+            "train_station",
+            "university",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(kinds, expected_kinds);
+    }
+
+    #[test]
+    fn pois_carry_wikidata_and_wikipedia() {
+        let rows = extract_rows(OSM_POI, &ExtractOptions::default());
+        let (wikidata, wikipedia) = (column(&rows, "wikidata"), column(&rows, "wikipedia"));
+        let eiffel = rows.iter().find(|row| row[0] == "Eiffel Tower").unwrap();
+        assert_eq!(eiffel[wikidata], "Q243");
+        assert_eq!(eiffel[wikipedia], "en:Eiffel_Tower");
+        let station = rows.iter().find(|row| row[0] == "Central Station").unwrap();
+        assert_eq!((station[wikidata].as_str(), station[wikipedia].as_str()), ("", ""));
+
+        let rows = extract_rows(OSM_SAMPLE, &ExtractOptions::default());
+        assert!(rows[1..].iter().all(|row| row[wikidata].is_empty() && row[wikipedia].is_empty()));
+    }
+
+    #[test]
+    fn extract_to_csv_emits_metadata_columns() {
+        let rows = extract_rows(OSM_METADATA, &ExtractOptions::default());
+        assert_eq!(rows[0].len(), COLUMNS.len());
+
+        let options = ExtractOptions {
+            with_metadata: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_METADATA, &options);
+        let version = column(&rows, "osm_version");
+        let timestamp = column(&rows, "osm_timestamp");
+
+        let station = rows.iter().find(|row| row[0] == "Central Station").unwrap();
+        assert_eq!(station[version], "7");
+        assert_eq!(station[timestamp], "2021-03-04T05:06:07Z");
+
+        let street = rows.iter().find(|row| row[0] == "Dave Burns Drive").unwrap();
+        assert_eq!(street[version], "12");
+        assert_eq!(street[timestamp], "2023-06-15T12:00:00Z");
+
+        let city = rows.iter().find(|row| row[0] == "Testville").unwrap();
+        assert_eq!(city[version], "");
+        assert_eq!(city[timestamp], "");
+    }
+
+    #[test]
+    fn assemble_rings_joins_reversed_segments() {
+        let rings = assemble_rings(vec![vec![1, 2, 3], vec![1, 4, 3], vec![7, 8]]);
+        assert_eq!(rings, vec![vec![1, 4, 3, 2, 1]]);
+    }
+
+    #[test]
+    fn extract_to_csv_includes_poi_relations_when_enabled() {
+        let rows = extract_rows(OSM_POI_RELATION, &ExtractOptions::default());
+        assert_eq!(rows.len(), 1);
+
+        let options = ExtractOptions {
+            include_poi_relations: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_POI_RELATION, &options);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][0], "Castle Grounds");
+        assert_eq!(rows[1][2], "sight");
+        let lon: f64 = rows[1][3].parse().unwrap();
+        let lat: f64 = rows[1][4].parse().unwrap();
+        assert!((lon - 10.1).abs() < 1e-9);
+        assert!((lat - 10.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn snap_precision_rounds_and_collapses_duplicates() {
+        let rows = extract_rows(OSM_NEARBY_POIS, &ExtractOptions::default());
+        assert_eq!(rows.len(), 3);
+
+        let options = ExtractOptions {
+            snap: Some(CoordSnap::Decimals(1)),
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_NEARBY_POIS, &options);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][0], "Corner Stop");
+        assert_eq!(rows[1][3], "7.6");
+        assert_eq!(rows[1][4], "45");
+    }
+
+    #[test]
+    fn snap_meters_uses_latitude_scaled_grid() {
+        let (lon, lat) = CoordSnap::Meters(1000.0).apply((10.004, 60.004));
+        let lat_step = 1000.0 / METERS_PER_DEGREE_LAT;
+        assert!(((lat / lat_step).round() * lat_step - lat).abs() < 1e-12);
+        assert!((lat - 60.004).abs() <= lat_step / 2.0);
+        let lon_step = lat_step / lat.to_radians().cos();
+        assert!((lon - 10.004).abs() <= lon_step / 2.0);
+        assert!(lon_step > 1.9 * lat_step);
+    }
+
+    #[test]
+    fn length_unit_converts_emitted_length() {
+        let rows = extract_rows(OSM_ONE_KM, &ExtractOptions::default());
+        assert!(!rows[0].iter().any(|header| header.starts_with("length_")));
+
+        for (unit, header, expected) in [
+            (LengthUnit::Kilometers, "length_km", 1.0),
+            (LengthUnit::Miles, "length_mi", 0.621),
+            (LengthUnit::Meters, "length_m", 1000.0),
+        ] {
+            let options = ExtractOptions {
+                length_unit: Some(unit),
+                ..ExtractOptions::default()
+            };
+            let rows = extract_rows(OSM_ONE_KM, &options);
+            let length: f64 = rows[1][column(&rows, header)].parse().unwrap();
+            assert!((length - expected).abs() < expected * 1e-3, "{header}: {length}");
+        }
+    }
+
+    fn chained_segments_osm(count: usize, step_deg: f64) -> String {
+        let mut osm = String::from("<osm version=\"0.6\">\n");
+        osm.push_str(
+            "  <node id=\"1000\" lat=\"0.0\" lon=\"0.001\">\
+             <tag k=\"place\" v=\"city\" /><tag k=\"name\" v=\"Longton\" /></node>\n",
+        );
+        for i in 0..=count {
+            let lat = i as f64 * step_deg;
+            osm.push_str(&format!("  <node id=\"{}\" lat=\"{lat}\" lon=\"0.0\" />\n", i + 1));
+        }
+        for i in 0..count {
+            osm.push_str(&format!(
+                "  <way id=\"{}\"><nd ref=\"{}\" /><nd ref=\"{}\" />\
+                 <tag k=\"highway\" v=\"primary\" /><tag k=\"name\" v=\"Long Road\" /></way>\n",
+                100 + i,
+                i + 1,
+                i + 2
+            ));
+        }
+        osm.push_str("</osm>\n");
+        osm
+    }
+
+    #[test]
+    fn max_cluster_span_limits_chain_merging() {
+        let osm = chained_segments_osm(10, 0.008);
+        let street_rows = |options: &ExtractOptions| {
+            extract_rows(&osm, options)
+                .into_iter()
+                .skip(1)
+                .filter(|row| row[0] == "Long Road")
+                .count()
+        };
+        assert_eq!(street_rows(&ExtractOptions::default()), 1);
+
+        let options = ExtractOptions {
+            max_cluster_span_km: Some(3.0),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(street_rows(&options), 3);
+    }
+
+    #[test]
+    fn after_date_filters_on_timestamps() {
+        let street_names = |options: &ExtractOptions| {
+            let mut names: Vec<String> = extract_rows(OSM_METADATA, options)
+                .into_iter()
+                .skip(1)
+                .filter(|row| row[2] != "city")
+                .map(|row| row[0].clone())
+                .collect();
+            names.sort();
+            names
+        };
+        let options = ExtractOptions {
+            after_date: parse_date("2022-01-01"),
+            ..ExtractOptions::default()
+        };
+        // Way 41 (2023) survives, way 40 (2019) and the 2021 station do not.
+        assert_eq!(street_names(&options), vec!["Dave Burns Drive"]);
+        let rows = extract_rows(OSM_METADATA, &options);
+        let street = rows.iter().find(|row| row[0] == "Dave Burns Drive").unwrap();
+        let lat: f64 = street[4].parse().unwrap();
+        assert!((lat - 0.0015).abs() < 1e-9);
+
+        let options = ExtractOptions {
+            after_date: parse_date("2021-03-04"),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(
+            street_names(&options),
+            vec!["Central Station", "Dave Burns Drive"]
+        );
+
+        let undated = extract_rows(OSM_ONE_KM, &options);
+        assert_eq!(undated.len(), 2);
+        let options = ExtractOptions {
+            drop_undated: true,
+            ..options
+        };
+        assert_eq!(extract_rows(OSM_ONE_KM, &options).len(), 1);
+    }
+
+    #[test]
+    fn parse_date_validates_format() {
+        assert_eq!(parse_date("2024-02-29"), Some((2024, 2, 29)));
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-1-01"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn highway_areas_report_area_instead_of_length() {
+        let options = ExtractOptions {
+            length_unit: Some(LengthUnit::Kilometers),
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_PEDESTRIAN_SQUARE, &options);
+        let area = column(&rows, "area_km2");
+        let length = column(&rows, "length_km");
+
+        let square = rows.iter().find(|row| row[0] == "Market Square").unwrap();
+        let square_area: f64 = square[area].parse().unwrap();
+        assert!((square_area - 0.01).abs() < 1e-4, "{square_area}");
+        assert_eq!(square[length], "0");
+
+        let lane = rows.iter().find(|row| row[0] == "Loop Road").unwrap();
+        assert_eq!(lane[area], "0");
+        let lane_length: f64 = lane[length].parse().unwrap();
+        assert!((lane_length - 0.4).abs() < 1e-3, "{lane_length}");
+    }
+
+    #[test]
+    fn noname_suppresses_street_unless_kept() {
+        let names = |options: &ExtractOptions| {
+            let mut names: Vec<String> = extract_rows(OSM_NONAME, options)
+                .into_iter()
+                .skip(1)
+                .map(|row| row[0].clone())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&ExtractOptions::default()), vec!["Real Street"]);
+
+        let options = ExtractOptions {
+            keep_noname: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(names(&options), vec!["Real Street", "Stale Name"]);
+    }
+
+    #[test]
+    fn mmap_reads_empty_pbf_like_buffered_reader() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("empty.osm.pbf");
+        std::fs::write(&input, b"").unwrap();
+
+        let buffered = extract_entries(&input, &ExtractOptions::default()).unwrap();
+        let options = ExtractOptions {
+            mmap: true,
+            ..ExtractOptions::default()
+        };
+        let mapped = extract_entries(&input, &options).unwrap();
+        assert!(buffered.is_empty());
+        assert_eq!(mapped, buffered);
+    }
+
+    #[test]
+    fn group_aliases_keeps_one_row_per_element() {
+        let options = ExtractOptions {
+            group_aliases: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_MULTI_NAME, &options);
+        assert_eq!(rows.len(), 2);
+        let aliases = column(&rows, "aliases");
+        assert_eq!(rows[1][0], "First");
+        assert_eq!(rows[1][column(&rows, "name_lang")], "");
+        assert_eq!(rows[1][aliases], "Second;Erste Straße");
+    }
+
+    #[test]
+    fn clip_polygon_keeps_entries_inside() {
+        let clip = ClipPolygon::from_geojson(
+            r#"{"type": "Feature", "properties": {}, "geometry": {"type": "Polygon",
+                "coordinates": [[[0.5, -0.5], [2.0, -0.5], [2.0, 1.5], [0.5, 1.5], [0.5, -0.5]]]}}"#,
+        )
+        .unwrap();
+        let options = ExtractOptions {
+            clip: Some(clip),
+            ..ExtractOptions::default()
+        };
+        let names: Vec<String> = extract_rows(OSM_SAMPLE, &options)
+            .into_iter()
+            .skip(1)
+            .map(|row| row[0].clone())
+            .collect();
+        assert!(names.contains(&"Main Street".to_string()), "{names:?}");
+        assert!(!names.contains(&"Open Way".to_string()), "{names:?}");
+    }
+
+    #[test]
+    fn clip_polygon_respects_holes_and_rejects_points() {
+        let clip = ClipPolygon::from_geojson(
+            r#"{"type": "MultiPolygon", "coordinates": [[
+                [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]],
+                [[4, 4], [6, 4], [6, 6], [4, 6], [4, 4]]
+            ]]}"#,
+        )
+        .unwrap();
+        assert!(clip.contains((1.0, 1.0)));
+        assert!(!clip.contains((5.0, 5.0)));
+        assert!(!clip.contains((11.0, 1.0)));
+        assert!(ClipPolygon::from_geojson(r#"{"type": "Point", "coordinates": [0, 0]}"#).is_err());
+    }
+
+    #[test]
+    fn tolerant_numbers_accept_decimal_commas() {
+        let osm = r#"<osm version="0.6">
+  <node id="1" lat="1,5" lon="0.0" />
+  <node id="2" lat="1.5" lon="2e-2" />
+  <way id="10">
+    <nd ref="1" />
+    <nd ref="2" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Comma Lane" />
+  </way>
+</osm>"#;
+        assert_eq!(extract_rows(osm, &ExtractOptions::default()).len(), 1);
+
+        let options = ExtractOptions {
+            tolerant_numbers: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][column(&rows, "center_lon")], "0.01");
+        assert_eq!(rows[1][column(&rows, "center_lat")], "1.5");
+        assert_eq!(parse_coordinate(" 4,25 ", true), Some(4.25));
+        assert_eq!(parse_coordinate("1,000,5", true), None);
+    }
+
+    #[test]
+    fn exclude_bbox_drops_entries_after_inclusion() {
+        let names = |options: &ExtractOptions| -> Vec<String> {
+            extract_rows(OSM_SAMPLE, options)
+                .into_iter()
+                .skip(1)
+                .map(|row| row[0].clone())
+                .collect()
+        };
+        let options = ExtractOptions {
+            exclude_bboxes: vec![BoundingBox::parse("-0.5,1.5,1.5,2.5").unwrap()],
+            ..ExtractOptions::default()
+        };
+        let kept = names(&options);
+        assert!(kept.contains(&"Main Street".to_string()), "{kept:?}");
+        assert!(!kept.contains(&"Open Way".to_string()), "{kept:?}");
+
+        let options = ExtractOptions {
+            bbox: Some(BoundingBox::parse("-1,-1,3,3").unwrap()),
+            exclude_bboxes: vec![BoundingBox::parse("0,0,1,1").unwrap()],
+            ..ExtractOptions::default()
+        };
+        assert_eq!(names(&options), vec!["Open Way"]);
+        assert!(BoundingBox::parse("1,0,0,1").is_err());
+    }
+
+    #[test]
+    fn associated_street_fills_addr_street_for_pois() {
+        let rows = extract_rows(OSM_ASSOCIATED_STREET, &ExtractOptions::default());
+        let addr_street = column(&rows, "addr_street");
+        let value = |name: &str| {
+            rows.iter()
+                .find(|row| row[0] == name)
+                .map(|row| row[addr_street].clone())
+                .unwrap()
+        };
+        assert_eq!(value("Harbor Museum"), "Quay Street");
+        assert_eq!(value("Dock Library"), "Pier Road");
+        assert_eq!(value("Quay Street"), "");
+    }
+
+    #[test]
+    fn compact_coords_trim_trailing_zeros() {
+        assert_eq!(format_coord(0.0, Some(7), false), "0.0000000");
+        assert_eq!(format_coord(0.0, Some(7), true), "0");
+        assert_eq!(format_coord(2.2945, Some(7), true), "2.2945");
+        assert_eq!(format_coord(-73.0, Some(7), true), "-73");
+        assert_eq!(format_coord(-0.00000001, Some(7), true), "0");
+        assert_eq!(format_coord(120.0, Some(0), true), "120");
+        assert_eq!(format_coord(1.5, None, true), "1.5");
+    }
+
+    #[test]
+    fn spatial_grid_neighbors_cover_radius() {
+        let mut grid = SpatialGrid::new(0.01);
+        grid.insert((13.0, 52.0), "origin");
+        grid.insert((13.012, 52.0), "east");
+        grid.insert((13.0, 52.03), "north");
+        grid.insert((14.0, 52.0), "far");
+
+        let mut near: Vec<&str> = grid.neighbors((13.0, 52.0), 1.0).copied().collect();
+        near.sort();
+        assert_eq!(near, vec!["east", "origin"]);
+
+        let mut wider: Vec<&str> = grid.neighbors((13.0, 52.0), 5.0).copied().collect();
+        wider.sort();
+        assert_eq!(wider, vec!["east", "north", "origin"]);
+    }
+
+    #[test]
+    fn extra_tags_add_columns_from_element_tags() {
+        let options = ExtractOptions {
+            extra_tags: parse_extra_tags("surface, maxspeed").unwrap(),
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_SAMPLE, &options);
+        let surface = column(&rows, "surface");
+        let maxspeed = column(&rows, "maxspeed");
+        assert_eq!(surface, rows[0].len() - 2);
+        let main = rows.iter().find(|row| row[0] == "Main Street").unwrap();
+        assert_eq!(main[surface], "asphalt");
+        assert_eq!(main[maxspeed], "");
+        let open = rows.iter().find(|row| row[0] == "Open Way").unwrap();
+        assert_eq!(open[surface], "");
+
+        assert!(parse_extra_tags("kind").is_err());
+    }
+
+    #[test]
+    fn multi_value_tags_classify_by_first_token() {
+        assert_eq!(split_multi_value("residential;service"), ("residential", vec!["service"]));
+        assert_eq!(split_multi_value(" ; services ;rest_area"), ("services", vec!["rest_area"]));
+        assert_eq!(split_multi_value("primary"), ("primary", vec![]));
+        assert_eq!(split_multi_value(";"), ("", vec![]));
+
+        let mut tags = Tags::new();
+        tags.insert("highway".into(), "bus_stop;platform".into());
+        assert!(has_tag_value(&tags, "highway", &["bus_stop"]));
+        assert!(!has_tag_value(&tags, "highway", &["platform"]));
+
+        tags.insert("name".into(), "Odd Road".into());
+        tags.insert("highway".into(), ";".into());
+        assert!(!is_street(&tags, &ExtractOptions::default()));
+    }
+
+    #[test]
+    fn throughput_line_reports_rate_and_eta() {
+        assert_eq!(
+            throughput_line("pbf", 5000, Some(10000), Duration::from_secs(5)),
+            "pbf: 5000 objects, 1000/s of 10000, ETA 5s"
+        );
+        assert_eq!(
+            throughput_line("osm", 300, None, Duration::from_millis(1500)),
+            "osm: 300 objects, 200/s"
+        );
+    }
+
+    #[test]
+    fn input_dir_shares_places_across_files() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("a_places.osm"),
+            r#"<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.01"><tag k="place" v="town" /><tag k="name" v="Edgeton" /></node>
+</osm>"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("b_streets.osm"),
+            r#"<osm version="0.6">
+  <node id="10" lat="0.0" lon="0.0" />
+  <node id="11" lat="0.001" lon="0.0" />
+  <way id="20"><nd ref="10" /><nd ref="11" /><tag k="highway" v="residential" /><tag k="name" v="Border Road" /></way>
+</osm>"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "not an extract").unwrap();
+
+        let inputs = input_dir_files(dir.path()).unwrap();
+        assert_eq!(inputs.len(), 2);
+        let out_path = dir.path().join("out.csv");
+        extract_files_to_csv(&inputs, &out_path, &ExtractOptions::default()).unwrap();
+
+        let mut reader = ReaderBuilder::new().from_path(&out_path).unwrap();
+        let headers = reader.headers().unwrap().clone();
+        let resolved = headers.iter().position(|header| header == "city_resolved").unwrap();
+        let rows: Vec<csv::StringRecord> = reader.records().map(|row| row.unwrap()).collect();
+        let street = rows.iter().find(|row| &row[0] == "Border Road").unwrap();
+        assert_eq!(&street[resolved], "Edgeton");
+        assert!(rows.iter().any(|row| &row[0] == "Edgeton"));
+
+        assert!(input_dir_files(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn geohash_matches_reference_values() {
+        assert_eq!(geohash((10.40744, 57.64911), 11), "u4pruydqqvj");
+        assert_eq!(geohash((2.2945, 48.8584), 6), "u09tun");
+        assert_eq!(geohash((-0.0001, -0.0001), 1), "7");
+
+        let options = ExtractOptions {
+            geohash_len: Some(5),
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_POI, &options);
+        let geohash = column(&rows, "geohash");
+        let eiffel = rows.iter().find(|row| row[0] == "Eiffel Tower").unwrap();
+        assert_eq!(eiffel[geohash], "u09tu");
+    }
+
+    #[test]
+    fn hamlet_radius_prefers_farther_town() {
+        let osm = r#"<osm version="0.6">
+  <node id="1" lat="-0.0005" lon="0.0" />
+  <node id="2" lat="0.0005" lon="0.0" />
+  <node id="3" lat="0.0" lon="0.09"><tag k="place" v="hamlet" /><tag k="name" v="Tiny" /></node>
+  <node id="4" lat="0.0" lon="-0.108"><tag k="place" v="town" /><tag k="name" v="Bigger" /></node>
+  <way id="10"><nd ref="1" /><nd ref="2" /><tag k="highway" v="residential" /><tag k="name" v="Coast Road" /></way>
+</osm>"#;
+        let place_node = |options: &ExtractOptions| {
+            let rows = extract_rows(osm, options);
+            let idx = column(&rows, "city_place_node");
+            rows.iter().find(|row| row[0] == "Coast Road").unwrap()[idx].clone()
+        };
+        assert_eq!(place_node(&ExtractOptions::default()), "Tiny");
+
+        let options = ExtractOptions {
+            place_radii: PlaceRadii {
+                hamlet_km: 5.0,
+                ..PlaceRadii::default()
+            },
+            ..ExtractOptions::default()
+        };
+        assert_eq!(place_node(&options), "Bigger");
+    }
+
+    #[test]
+    fn csv_header_matches_columns_and_schema() {
+        let rows = extract_rows(OSM_SAMPLE, &ExtractOptions::default());
+        assert_eq!(rows[0], COLUMNS);
+
+        let options = ExtractOptions {
+            with_metadata: true,
+            geohash_len: Some(4),
+            ..ExtractOptions::default()
+        };
+        let schema: serde_json::Value =
+            serde_json::from_str(&schema_json(&options).unwrap()).unwrap();
+        assert_eq!(schema["schema_version"], SCHEMA_VERSION);
+        let columns: Vec<&str> = schema["columns"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|column| column.as_str().unwrap())
+            .collect();
+        assert_eq!(columns, output_columns(&options));
+        assert_eq!(columns[..COLUMNS.len()], *COLUMNS);
+        assert_eq!(columns.last(), Some(&"geohash"));
+
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("sample.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let options = ExtractOptions {
+            schema_comment: true,
+            ..ExtractOptions::default()
+        };
+        extract_to_csv(&osm_path, &out_path, &options).unwrap();
+        let written = std::fs::read_to_string(&out_path).unwrap();
+        let mut lines = written.lines();
+        assert_eq!(lines.next(), Some("# schema_version=1"));
+        assert_eq!(lines.next(), Some(COLUMNS.join(",").as_str()));
+    }
+
+    #[test]
+    fn fill_way_coords_reuses_scratch_and_stops_early() {
+        let nodes: HashMap<i64, (f64, f64)> =
+            HashMap::from([(1, (0.0, 0.0)), (2, (1.0, 0.0)), (3, (1.0, 1.0))]);
+        let lookup = |id: &i64| nodes.get(id).copied();
+        let mut coords = Vec::new();
+
+        assert!(fill_way_coords(&[1, 2, 3], lookup, &mut coords));
+        assert_eq!(coords, vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)]);
+        let buffer = coords.as_ptr();
+
+        let lookups = std::cell::Cell::new(0);
+        let counting = |id: &i64| {
+            lookups.set(lookups.get() + 1);
+            nodes.get(id).copied()
+        };
+        assert!(!fill_way_coords(&[1, 9, 2, 3], counting, &mut coords));
+        assert_eq!(lookups.get(), 2);
+
+        assert!(fill_way_coords(&[3, 2], lookup, &mut coords));
+        assert_eq!(coords, vec![(1.0, 1.0), (1.0, 0.0)]);
+        assert_eq!(coords.as_ptr(), buffer);
+    }
+
+    #[test]
+    fn name_filters_include_then_exclude() {
+        let names = |options: &ExtractOptions| -> Vec<String> {
+            extract_rows(OSM_SAMPLE, options)
+                .into_iter()
+                .skip(1)
+                .map(|row| row[0].clone())
+                .collect()
+        };
+        let options = ExtractOptions {
+            name_include: Some(Regex::new(".*Street$").unwrap()),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(names(&options), vec!["Main Street".to_string()]);
+
+        let options = ExtractOptions {
+            name_exclude: Some(Regex::new("^Open").unwrap()),
+            ..ExtractOptions::default()
+        };
+        let kept = names(&options);
+        assert!(kept.contains(&"Main Street".to_string()), "{kept:?}");
+        assert!(kept.contains(&"Old Main".to_string()), "{kept:?}");
+        assert!(!kept.contains(&"Open Way".to_string()), "{kept:?}");
+
+        let options = ExtractOptions {
+            name_include: Some(Regex::new("Main").unwrap()),
+            name_exclude: Some(Regex::new("^Old ").unwrap()),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(names(&options), vec!["Main Street".to_string()]);
+    }
+
+    #[test]
+    fn closed_roundabout_keeps_line_geometry() {
+        let ring = [(0.0, 0.0), (0.01, 0.0), (0.01, 0.01), (0.0, 0.01), (0.0, 0.0)];
+        let mut tags = Tags::new();
+        tags.insert("highway".into(), "primary".into());
+        tags.insert("junction".into(), "roundabout".into());
+
+        let geometry = way_geometry(&ring, true, true, &tags, PoiLength::Zero).unwrap();
+        assert_eq!(geometry.center, line_midpoint(&ring).unwrap());
+        assert!((geometry.length_km - path_length_km(&ring)).abs() < 1e-9);
+        assert!(geometry.length_km > 4.0, "{}", geometry.length_km);
+        assert_eq!(geometry.area_km2, 0.0);
+
+        tags.insert("area".into(), "yes".into());
+        let geometry = way_geometry(&ring, true, true, &tags, PoiLength::Zero).unwrap();
+        assert_eq!(geometry.center, polygon_centroid(&ring).unwrap());
+        assert_eq!(geometry.length_km, 0.0);
+        assert!(geometry.area_km2 > 0.0);
+    }
+
+    #[test]
+    fn stream_entries_yields_unmerged_rows_lazily() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let options = ExtractOptions::default();
+
+        let mut stream = stream_entries(&osm_path, &options);
+        let first = stream.next().unwrap().unwrap();
+        assert_eq!(first.kind, "city");
+        let rest: Vec<StreetEntry> = stream.map(|entry| entry.unwrap()).collect();
+        let mut streamed = vec![first];
+        streamed.extend(rest);
+
+        let merged = extract_entries(&osm_path, &options).unwrap();
+        assert!(streamed.len() >= merged.len());
+        assert_eq!(merge_entries(streamed, &options), merged);
+
+        let missing = dir.path().join("missing.osm");
+        let mut stream = stream_entries(&missing, &options);
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn header_map_renames_header_only() {
+        let options = ExtractOptions {
+            header_map: parse_header_map("streetname=name, center_lon=lon").unwrap(),
+            ..ExtractOptions::default()
+        };
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        extract_to_csv(&osm_path, &out_path, &options).unwrap();
+        let csv = std::fs::read_to_string(&out_path).unwrap();
+        let mut expected = COLUMNS.to_vec();
+        expected[0] = "name";
+        expected[3] = "lon";
+        assert_eq!(csv.lines().next(), Some(expected.join(",").as_str()));
+
+        let rows = extract_rows(OSM_SAMPLE, &options);
+        let plain = extract_rows(OSM_SAMPLE, &ExtractOptions::default());
+        assert_eq!(rows[1..], plain[1..]);
+
+        assert!(parse_header_map("streetname").is_err());
+        let unknown = ExtractOptions {
+            header_map: parse_header_map("street=name").unwrap(),
+            ..ExtractOptions::default()
+        };
+        assert!(output_header(&unknown).is_err());
+        let duplicate = ExtractOptions {
+            header_map: parse_header_map("streetname=kind").unwrap(),
+            ..ExtractOptions::default()
+        };
+        assert!(output_header(&duplicate).is_err());
+    }
+
+    #[test]
+    fn osm_change_and_prefixed_elements_are_extracted() {
+        const OSM_CHANGE: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osmChange version="0.6" xmlns:osm="https://www.openstreetmap.org/osm">
+  <create>
+    <osm:node osm:id="1" lat="0.0" lon="0.0" />
+    <osm:node osm:id="2" lat="0.0" lon="0.01" />
+    <node id="3" lat="0.0" lon="0.005">
+      <tag k="place" v="village" />
+      <tag k="name" v="Changedorf" />
+    </node>
+  </create>
+  <modify>
+    <osm:way osm:id="10">
+      <osm:nd ref="1" />
+      <osm:nd ref="2" />
+      <osm:tag k="highway" v="residential" />
+      <osm:tag k="name" v="Created Road" />
+    </osm:way>
+  </modify>
+  <delete>
+    <node id="4" lat="0.0" lon="0.02" />
+    <way id="11">
+      <nd ref="2" />
+      <nd ref="4" />
+      <tag k="highway" v="residential" />
+      <tag k="name" v="Deleted Road" />
+    </way>
+  </delete>
+</osmChange>
+"#;
+        let rows = extract_rows(OSM_CHANGE, &ExtractOptions::default());
+        let city = column(&rows, "city_resolved");
+        let names: Vec<(&str, &str)> = rows[1..]
+            .iter()
+            .map(|row| (row[0].as_str(), row[city].as_str()))
+            .collect();
+        assert_eq!(names, [("Changedorf", "Changedorf"), ("Created Road", "Changedorf")]);
+    }
+
+    #[test]
+    fn merge_weight_changes_cluster_center() {
+        // A long street segment next to a short one of the same name.
+        const OSM_ASYMMETRIC: &str = r#"<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.001" />
+  <node id="3" lat="0.0" lon="0.002" />
+  <node id="4" lat="0.0" lon="0.008" />
+  <node id="5" lat="0.0" lon="0.0082" />
+  <node id="6" lat="0.001" lon="0.005">
+    <tag k="place" v="village" /><tag k="name" v="Weighton" />
+  </node>
+  <way id="10">
+    <nd ref="1" /><nd ref="2" /><nd ref="3" /><nd ref="4" />
+    <tag k="highway" v="residential" /><tag k="name" v="Split Street" />
+  </way>
+  <way id="11">
+    <nd ref="4" /><nd ref="5" />
+    <tag k="highway" v="residential" /><tag k="name" v="Split Street" />
+  </way>
+</osm>
+"#;
+        let center = |merge_weight: MergeWeight| -> f64 {
+            let options = ExtractOptions {
+                merge_weight,
+                ..ExtractOptions::default()
+            };
+            let rows = extract_rows(OSM_ASYMMETRIC, &options);
+            let streets: Vec<&Vec<String>> =
+                rows.iter().filter(|row| row[0] == "Split Street").collect();
+            assert_eq!(streets.len(), 1);
+            assert_eq!(streets[0][column(&rows, "segment_count")], "2");
+            streets[0][column(&rows, "center_lon")].parse().unwrap()
+        };
+        // Midpoints: 0.004 for the long way (4 nodes), 0.0081 for the short one (2 nodes).
+        let length = center(MergeWeight::Length);
+        let equal = center(MergeWeight::Equal);
+        let count = center(MergeWeight::Count);
+        assert!((equal - 0.00605).abs() < 1e-9, "{equal}");
+        assert!((count - (4.0 * 0.004 + 2.0 * 0.0081) / 6.0).abs() < 1e-9, "{count}");
+        assert!(length < count && count < equal, "{length} {count} {equal}");
+        assert!((length - (0.008 * 0.004 + 0.0002 * 0.0081) / 0.0082).abs() < 1e-6, "{length}");
+        assert!(MergeWeight::parse("nodes").is_err());
+    }
+
+    #[test]
+    fn checkpoint_resume_matches_a_fresh_run() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let checkpoint = dir.path().join("run.checkpoint");
+        let run = |options: &ExtractOptions, name: &str| -> String {
+            let out_path = dir.path().join(name);
+            extract_to_csv(&osm_path, &out_path, options).unwrap();
+            std::fs::read_to_string(out_path).unwrap()
+        };
+        let options = ExtractOptions {
+            extra_tags: vec!["surface".to_string()],
+            group_aliases: true,
+            checkpoint: Some(checkpoint.clone()),
+            ..ExtractOptions::default()
+        };
+        let fresh = run(
+            &ExtractOptions {
+                checkpoint: None,
+                ..options.clone()
+            },
+            "fresh.csv",
+        );
+
+        assert_eq!(run(&options, "first.csv"), fresh);
+        assert!(checkpoint.exists());
+        // Resuming skips reading: an edit to the saved rows shows up.
+        let saved = std::fs::read_to_string(&checkpoint).unwrap();
+        std::fs::write(&checkpoint, saved.replace("Open Way", "Resumed Way")).unwrap();
+        let resumed = run(&options, "resumed.csv");
+        assert_eq!(resumed, fresh.replace("Open Way", "Resumed Way"));
+
+        // Other options invalidate the checkpoint, which is rebuilt.
+        let other = ExtractOptions {
+            keep_noname: true,
+            ..options.clone()
+        };
+        assert_eq!(run(&other, "other.csv"), fresh);
+        assert!(!std::fs::read_to_string(&checkpoint).unwrap().contains("Resumed Way"));
+    }
+
+    #[test]
+    fn country_code_from_supplied_polygons() {
+        let countries = CountryPolygons::from_geojson(
+            r#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "properties": {"ISO_A2": "-99"},
+                 "geometry": {"type": "Polygon",
+                    "coordinates": [[[-9, -9], [9, -9], [9, 9], [-9, 9], [-9, -9]]]}},
+                {"type": "Feature", "properties": {"iso_a2": "tl"},
+                 "geometry": {"type": "Polygon",
+                    "coordinates": [[[-0.5, -0.5], [1.5, -0.5], [1.5, 1.5], [-0.5, 1.5],
+                                     [-0.5, -0.5]]]}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(countries.code_for((0.5, 0.5)), Some("TL"));
+        assert_eq!(countries.code_for((0.0, 2.0)), None);
+
+        let options = ExtractOptions {
+            countries: Some(countries),
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_SAMPLE, &options);
+        let code = column(&rows, "country_code");
+        let code_of = |name: &str| {
+            let row = rows.iter().find(|row| row[0] == name).unwrap();
+            row[code].clone()
+        };
+        assert_eq!(code_of("Main Street"), "TL");
+        assert_eq!(code_of("Open Way"), "");
+        assert!(CountryPolygons::from_geojson(r#"{"type": "FeatureCollection", "features": []}"#)
+            .is_err());
+    }
+
+    #[test]
+    fn merge_joins_halves_sharing_an_end_node() {
+        // Two ~3 km halves: their centers are further apart than
+        // MERGE_DISTANCE_KM, but way 11 starts where way 10 ends.
+        let osm = |second_start: &str| {
+            format!(
+                r#"<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.027" />
+  <node id="3" lat="0.0" lon="0.054" />
+  <node id="4" lat="0.0" lon="0.027" />
+  <node id="5" lat="0.001" lon="0.027">
+    <tag k="place" v="village" /><tag k="name" v="Halfway" />
+  </node>
+  <way id="10">
+    <nd ref="1" /><nd ref="2" />
+    <tag k="highway" v="primary" /><tag k="name" v="Long Road" />
+  </way>
+  <way id="11">
+    <nd ref="{second_start}" /><nd ref="3" />
+    <tag k="highway" v="primary" /><tag k="name" v="Long Road" />
+  </way>
+</osm>
+"#
+            )
+        };
+        let streets = |osm: &str| -> Vec<Vec<String>> {
+            extract_rows(osm, &ExtractOptions::default())
+                .into_iter()
+                .filter(|row| row[0] == "Long Road")
+                .collect()
+        };
+        let shared = streets(&osm("2"));
+        assert_eq!(shared.len(), 1, "{shared:?}");
+        assert_eq!(shared[0][COLUMNS.iter().position(|c| *c == "segment_count").unwrap()], "2");
+        // Same geometry without a shared node id stays split.
+        assert_eq!(streets(&osm("4")).len(), 2);
+    }
+
+    #[test]
+    fn no_split_names_keeps_semicolons() {
+        let osm = OSM_SAMPLE.replace(r#"v="Open Way""#, r#"v="A; B Street""#);
+        let names = |options: &ExtractOptions| -> Vec<String> {
+            extract_rows(&osm, options)
+                .into_iter()
+                .skip(1)
+                .map(|row| row[0].clone())
+                .filter(|name| name.contains('A') || name.contains('B'))
+                .collect()
+        };
+        assert_eq!(names(&ExtractOptions::default()), ["A", "B Street"]);
+        let options = ExtractOptions {
+            no_split_names: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(names(&options), ["A; B Street"]);
+    }
+
+    #[test]
+    fn int_name_and_name_key_precedence() {
+        let osm = OSM_SAMPLE.replace(
+            r#"<tag k="name" v="Open Way" />"#,
+            r#"<tag k="int_name" v="Open Way" /><tag k="nat_name:de" v="Offener Weg" />"#,
+        );
+        let rows = extract_rows(&osm, &ExtractOptions::default());
+        let lang = column(&rows, "name_lang");
+        assert!(rows.iter().any(|row| row[0] == "Open Way" && row[lang].is_empty()));
+        assert!(rows.iter().any(|row| row[0] == "Offener Weg" && row[lang] == "de"));
+
+        let mut tags = Tags::new();
+        tags.insert("name".into(), "Hauptstraße".into());
+        tags.insert("int_name".into(), "Main Street".into());
+        let primary = |options: &ExtractOptions| {
+            collect_name_variants(&tags, options)[0].name.clone()
+        };
+        assert_eq!(primary(&ExtractOptions::default()), "Hauptstraße");
+        let options = ExtractOptions {
+            name_keys: parse_name_keys("int_name, name").unwrap(),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(primary(&options), "Main Street");
+        assert!(parse_name_keys("name,brand").is_err());
+    }
+
+    #[test]
+    fn pretty_json_output_parses() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        let out_path = dir.path().join("out.json");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let options = ExtractOptions {
+            format: OutputFormat::Json,
+            pretty: true,
+            ..ExtractOptions::default()
+        };
+        extract_to_csv(&osm_path, &out_path, &options).unwrap();
+
+        let text = std::fs::read_to_string(&out_path).unwrap();
+        assert!(text.starts_with("[\n  {\n    \"streetname\""), "{text}");
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let rows = value.as_array().unwrap();
+        let csv_rows = extract_rows(OSM_SAMPLE, &ExtractOptions::default());
+        assert_eq!(rows.len(), csv_rows.len() - 1);
+        let main = rows.iter().find(|row| row["streetname"] == "Main Street").unwrap();
+        assert_eq!(main["kind"], "street");
+        assert_eq!(main["city_resolved"], "Placetown");
+        assert_eq!(main.as_object().unwrap().len(), COLUMNS.len());
+    }
+
+    #[test]
+    fn poi_length_for_closed_tourism_way() {
+        const OSM_ATTRACTION: &str = r#"<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.01" />
+  <node id="3" lat="0.01" lon="0.01" />
+  <node id="4" lat="0.01" lon="0.0" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" /><nd ref="3" /><nd ref="4" /><nd ref="1" />
+    <tag k="tourism" v="museum" /><tag k="name" v="Fenced Garden" />
+  </way>
+</osm>
+"#;
+        let length = |poi_length: PoiLength| -> f64 {
+            let options = ExtractOptions {
+                poi_length,
+                length_unit: Some(LengthUnit::Kilometers),
+                ..ExtractOptions::default()
+            };
+            let rows = extract_rows(OSM_ATTRACTION, &options);
+            let row = rows.iter().find(|row| row[0] == "Fenced Garden").unwrap();
+            row[column(&rows, "length_km")].parse().unwrap()
+        };
+        let side_km = haversine_km((0.0, 0.0), (0.01, 0.0));
+        assert_eq!(length(PoiLength::Zero), 0.0);
+        assert!((length(PoiLength::Perimeter) - 4.0 * side_km).abs() < 1e-3);
+        assert!((length(PoiLength::Extent) - side_km * 2f64.sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn fail_on_empty_errors_when_filters_drop_everything() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let empty_bbox = Some(BoundingBox::parse("50,50,51,51").unwrap());
+
+        let options = ExtractOptions {
+            bbox: empty_bbox,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(extract_to_csv(&osm_path, &out_path, &options).unwrap(), 0);
+
+        let options = ExtractOptions {
+            bbox: empty_bbox,
+            fail_on_empty: true,
+            ..ExtractOptions::default()
+        };
+        let err = extract_to_csv(&osm_path, &out_path, &options).unwrap_err();
+        assert!(err.to_string().contains("no rows written"), "{err}");
+        let csv = std::fs::read_to_string(&out_path).unwrap();
+        assert_eq!(csv.lines().count(), 1);
+
+        let options = ExtractOptions {
+            fail_on_empty: true,
+            ..ExtractOptions::default()
+        };
+        let written = extract_to_csv(&osm_path, &out_path, &options).unwrap();
+        assert_eq!(written, extract_rows(OSM_SAMPLE, &options).len() - 1);
+    }
+
+    #[test]
+    fn ascii_name_column_folds_names() {
+        let options = ExtractOptions {
+            ascii_name: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_MULTI_NAME, &options);
+        let ascii = column(&rows, "name_ascii");
+        let row = rows.iter().find(|row| row[0] == "Erste Straße").unwrap();
+        assert_eq!(row[ascii], "Erste Strasse");
+        assert_eq!(deunicode::deunicode("Café Müller"), "Cafe Muller");
+        let plain = rows.iter().find(|row| row[0] == "First").unwrap();
+        assert_eq!(plain[ascii], "First");
+    }
+
+    #[test]
+    fn diff_outputs_reports_moved_added_and_removed() {
+        let dir = tempdir().unwrap();
+        let old_path = dir.path().join("old.csv");
+        let new_path = dir.path().join("new.csv");
+        let changes_path = dir.path().join("changes.csv");
+        let header = COLUMNS.join(",");
+        let row = |name: &str, lon: f64| {
+            format!("{name},,street,{lon},0,,,,Town,,1,0,,,")
+        };
+        std::fs::write(
+            &old_path,
+            [header.clone(), row("Stay Road", 0.0), row("Move Road", 0.1), row("Gone Road", 0.2)]
+                .join("\n"),
+        )
+        .unwrap();
+        std::fs::write(
+            &new_path,
+            [
+                format!("# schema_version={SCHEMA_VERSION}"),
+                header,
+                row("Stay Road", 0.0001),
+                row("Move Road", 0.11),
+                row("New Road", 0.3),
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+
+        let summary =
+            diff_outputs(&old_path, &new_path, &changes_path, DEFAULT_MOVE_THRESHOLD_KM).unwrap();
+        assert_eq!(
+            summary,
+            DiffSummary {
+                added: 1,
+                removed: 1,
+                moved: 1,
+            }
+        );
+        let mut reader = ReaderBuilder::new().from_path(&changes_path).unwrap();
+        let changes: Vec<(String, String)> = reader
+            .records()
+            .map(|record| {
+                let record = record.unwrap();
+                (record[0].to_string(), record[1].to_string())
+            })
+            .collect();
+        let expected = [("moved", "Move Road"), ("removed", "Gone Road"), ("added", "New Road")];
+        assert_eq!(
+            changes,
+            expected.map(|(change, name)| (change.to_string(), name.to_string()))
+        );
+    }
+
+    fn place_match_places() -> Vec<PlaceNode> {
+        let mut places = grid_places((13.0, 52.0), 0.02, 12);
+        for (idx, coord) in [(13.05, 52.05), (13.17, 52.11)].into_iter().enumerate() {
+            places.push(PlaceNode::new(
+                vec![NameVariant {
+                    name: format!("Town {idx}"),
+                    lang: "".to_string(),
+                }],
+                "town".to_string(),
+                coord,
+                None,
+            ));
+        }
+        places
+    }
+
+    #[test]
+    fn place_match_cache_agrees_with_uncached_lookup() {
+        let index = PlaceIndex::new(place_match_places(), 0.1);
+        let mut queries = Vec::new();
+        for i in 0..70 {
+            for j in 0..70 {
+                queries.push((12.98 + i as f64 * 0.0037, 51.98 + j as f64 * 0.0037));
+            }
+        }
+        for &query in &queries {
+            assert_eq!(index.place_match(query), index.place_match_within(query, 0.0).0);
+        }
+        let cells = index.match_cache.cells.read().unwrap();
+        let cached = cells.values().filter(|cell| cell.is_some()).count();
+        assert!(cached > cells.len() / 2, "{cached} of {} cells cached", cells.len());
+    }
+
+    const OSM_LIFECYCLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.01" />
+  <node id="3" lat="0.1" lon="0.0" />
+  <node id="4" lat="0.1" lon="0.01" />
+  <node id="5" lat="0.2" lon="0.0" />
+  <node id="6" lat="0.2" lon="0.01" />
+  <node id="7" lat="0.3" lon="0.0" />
+  <node id="8" lat="0.3" lon="0.01" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Open Road" />
+  </way>
+  <way id="11">
+    <nd ref="3" /><nd ref="4" />
+    <tag k="highway" v="proposed" />
+    <tag k="proposed:highway" v="residential" />
+    <tag k="name" v="Future Road" />
+  </way>
+  <way id="12">
+    <nd ref="5" /><nd ref="6" />
+    <tag k="highway" v="construction" />
+    <tag k="name" v="Building Road" />
+  </way>
+  <way id="13">
+    <nd ref="7" /><nd ref="8" />
+    <tag k="highway" v="abandoned" />
+    <tag k="name" v="Old Road" />
+  </way>
+</osm>
+"#;
+
+    #[test]
+    fn include_proposed_keeps_lifecycle_roads_with_status() {
+        let names = |rows: &[Vec<String>]| -> Vec<String> {
+            rows.iter().skip(1).map(|row| row[0].clone()).collect()
+        };
+        let rows = extract_rows(OSM_LIFECYCLE, &ExtractOptions::default());
+        assert_eq!(names(&rows), ["Open Road"]);
+        assert!(!rows[0].contains(&"status".to_string()));
+
+        let options = ExtractOptions {
+            include_proposed: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_LIFECYCLE, &options);
+        let status = column(&rows, "status");
+        let statuses: Vec<(String, String)> = rows
+            .iter()
+            .skip(1)
+            .map(|row| (row[0].clone(), row[status].clone()))
+            .collect();
+        let expected = [
+            ("Open Road", "active"),
+            ("Future Road", "proposed"),
+            ("Building Road", "construction"),
+        ];
+        assert_eq!(statuses, expected.map(|(name, status)| (name.to_string(), status.to_string())));
+    }
+
+    #[test]
+    fn with_surface_adds_street_surface_columns() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.01" />
+  <node id="3" lat="0.1" lon="0.0" />
+  <node id="4" lat="0.1" lon="0.01" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Smooth Road" />
+    <tag k="surface" v="asphalt" />
+    <tag k="smoothness" v="excellent" />
+  </way>
+  <way id="11">
+    <nd ref="3" /><nd ref="4" />
+    <tag k="highway" v="track" />
+    <tag k="name" v="Field Track" />
+  </way>
+</osm>
+"#;
+        let options = ExtractOptions {
+            with_surface: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        let (surface, smoothness) = (column(&rows, "surface"), column(&rows, "smoothness"));
+        let smooth = rows.iter().find(|row| row[0] == "Smooth Road").unwrap();
+        assert_eq!(smooth[surface], "asphalt");
+        assert_eq!(smooth[smoothness], "excellent");
+        let track = rows.iter().find(|row| row[0] == "Field Track").unwrap();
+        assert_eq!(track[surface], "");
+        assert_eq!(track[smoothness], "");
+    }
+
+    #[test]
+    fn web_mercator_projects_known_points() {
+        let close = |(x, y): (f64, f64), expected: (f64, f64)| {
+            assert!((x - expected.0).abs() < 0.01 && (y - expected.1).abs() < 0.01, "{x}, {y}");
+        };
+        close(Crs::WebMercator.project((0.0, 0.0)), (0.0, 0.0));
+        close(Crs::WebMercator.project((13.4, 52.5)), (1_491_681.18, 6_891_041.72));
+        close(Crs::WebMercator.project((180.0, 90.0)), (20_037_508.34, 20_037_508.34));
+        assert_eq!(Crs::parse("EPSG:3857").unwrap(), Crs::WebMercator);
+        assert!(Crs::parse("900913").is_err());
+
+        let options = ExtractOptions {
+            crs: Crs::WebMercator,
+            ..ExtractOptions::default()
+        };
+        let header = output_columns(&options);
+        assert_eq!(&header[3..5], ["x", "y"]);
+    }
+
+    #[test]
+    fn is_in_hierarchy_fills_region_and_country() {
+        let options = ExtractOptions {
+            is_in_hierarchy: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_SAMPLE, &options);
+        let (region, country) = (column(&rows, "is_in_region"), column(&rows, "is_in_country"));
+        let main = rows.iter().find(|row| row[0] == "Main Street").unwrap();
+        assert_eq!(main[region], "Testland");
+        assert_eq!(main[country], "Testland");
+
+        let mut tags = Tags::new();
+        tags.insert("is_in".into(), "Placetown, County, Testland, Europe".into());
+        assert_eq!(is_in_region_country(&tags), ("County".to_string(), "Testland".to_string()));
+        tags.insert("is_in:country".into(), "Otherland".into());
+        assert_eq!(is_in_region_country(&tags).1, "Otherland");
+    }
+
+    const OSM_POI_IN_BUILDING: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.01" />
+  <node id="3" lat="0.01" lon="0.01" />
+  <node id="4" lat="0.01" lon="0.0" />
+  <node id="5" lat="0.005" lon="0.005">
+    <tag k="amenity" v="library" />
+    <tag k="name" v="City Library" />
+  </node>
+  <node id="6" lat="0.1" lon="0.0" />
+  <node id="7" lat="0.1" lon="0.01" />
+  <node id="8" lat="0.11" lon="0.01" />
+  <node id="9" lat="0.11" lon="0.0" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" /><nd ref="3" /><nd ref="4" /><nd ref="1" />
+    <tag k="building" v="public" />
+    <tag k="name" v="Civic Hall" />
+  </way>
+  <way id="11">
+    <nd ref="6" /><nd ref="7" /><nd ref="8" /><nd ref="9" /><nd ref="6" />
+    <tag k="building" v="public" />
+    <tag k="name" v="Empty Hall" />
+  </way>
+</osm>
+"#;
+
+    #[test]
+    fn dedup_poi_in_building_drops_the_containing_way() {
+        let names = |options: &ExtractOptions| -> Vec<String> {
+            let mut names: Vec<String> = extract_rows(OSM_POI_IN_BUILDING, options)
+                .into_iter()
+                .skip(1)
+                .map(|row| row[0].clone())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(&ExtractOptions::default()),
+            ["City Library", "Civic Hall", "Empty Hall"]
+        );
+        let options = ExtractOptions {
+            dedup_poi_in_building: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(names(&options), ["City Library", "Empty Hall"]);
+    }
+
+    #[test]
+    fn synthetic_osm_extracts_every_way_merged_by_name() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("bench.osm");
+        let shape = SyntheticOsm {
+            nodes: 40,
+            ways: 10,
+            names: 3,
+        };
+        write_synthetic_osm(&path, shape).unwrap();
+        let entries = extract_entries(&path, &ExtractOptions::default()).unwrap();
+        // The ways sharing one of the 3 names lie close together and merge.
+        let streets: Vec<&StreetEntry> =
+            entries.iter().filter(|entry| entry.kind == "street").collect();
+        assert_eq!(streets.len(), 3);
+        assert_eq!(streets.iter().map(|entry| entry.segment_count).sum::<usize>(), 10);
+        assert_eq!(entries.iter().filter(|entry| entry.kind == "city").count(), 1);
+        assert!(write_synthetic_osm(&path, SyntheticOsm { nodes: 5, ..shape }).is_err());
+    }
+
+    #[test]
+    fn disused_pois_are_dropped_unless_included() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0">
+    <tag k="disused:railway" v="station" />
+    <tag k="name" v="Old Halt" />
+  </node>
+  <node id="2" lat="0.1" lon="0.0">
+    <tag k="railway" v="station" />
+    <tag k="disused" v="yes" />
+    <tag k="name" v="Closed Station" />
+  </node>
+  <node id="3" lat="0.2" lon="0.0">
+    <tag k="railway" v="station" />
+    <tag k="name" v="Central Station" />
+  </node>
+</osm>
+"#;
+        let stations = |options: &ExtractOptions| -> Vec<String> {
+            let rows = extract_rows(osm, options);
+            let kind = column(&rows, "kind");
+            rows.iter()
+                .skip(1)
+                .filter(|row| row[kind] == "train_station")
+                .map(|row| row[0].clone())
+                .collect()
+        };
+        assert_eq!(stations(&ExtractOptions::default()), ["Central Station"]);
+        let options = ExtractOptions {
+            include_disused: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(stations(&options), ["Old Halt", "Closed Station", "Central Station"]);
+    }
+
+    #[test]
+    fn place_coords_point_at_the_matched_place() {
+        let options = ExtractOptions {
+            place_coords: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_SAMPLE, &options);
+        let (place, lon, lat) = (
+            column(&rows, "city_place_node"),
+            column(&rows, "place_node_lon"),
+            column(&rows, "place_node_lat"),
+        );
+        let main = rows.iter().find(|row| row[0] == "Main Street").unwrap();
+        assert_eq!(main[place], "Placetown");
+        assert_eq!((main[lon].as_str(), main[lat].as_str()), ("0.6", "0.6"));
+    }
+
+    #[test]
+    fn extract_errors_are_typed() {
+        let dir = tempdir().unwrap();
+        let options = ExtractOptions::default();
+        let missing = extract_entries(&dir.path().join("missing.osm"), &options);
+        assert!(matches!(missing, Err(ExtractError::Io(_))));
+
+        let notes = dir.path().join("notes.txt");
+        std::fs::write(&notes, "not an extract").unwrap();
+        let unsupported = extract_entries(&notes, &options);
+        assert!(matches!(unsupported, Err(ExtractError::UnsupportedFormat(_))));
+
+        assert!(matches!(Crs::parse("4269"), Err(ExtractError::BadArgs(_))));
+    }
+
+    #[test]
+    fn extract_files_to_writer_streams_the_csv() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("sample.osm");
+        let out_path = dir.path().join("out.csv");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let options = ExtractOptions::default();
+
+        let mut stdout = Vec::new();
+        let inputs = [osm_path.clone()];
+        let written = extract_files_to_writer(&inputs, &mut stdout, &options).unwrap();
+        assert_eq!(extract_to_csv(&osm_path, &out_path, &options).unwrap(), written);
+        assert_eq!(String::from_utf8(stdout).unwrap(), std::fs::read_to_string(&out_path).unwrap());
+    }
+
+    #[test]
+    fn listed_inputs_share_places_in_any_order() {
+        let dir = tempdir().unwrap();
+        let streets = dir.path().join("streets.osm");
+        let places = dir.path().join("nested").join("places.osc");
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        std::fs::write(
+            &streets,
+            r#"<osm version="0.6">
+  <node id="10" lat="0.0" lon="0.0" />
+  <node id="11" lat="0.001" lon="0.0" />
+  <way id="20"><nd ref="10" /><nd ref="11" /><tag k="highway" v="residential" /><tag k="name" v="Border Road" /></way>
+</osm>"#,
+        )
+        .unwrap();
+        std::fs::write(
+            &places,
+            r#"<osmChange version="0.6"><create>
+  <node id="1" lat="0.0" lon="0.01"><tag k="place" v="town" /><tag k="name" v="Edgeton" /></node>
+</create></osmChange>"#,
+        )
+        .unwrap();
+
+        let entries = extract_entries_from(&[streets, places], &ExtractOptions::default()).unwrap();
+        let street = entries.iter().find(|entry| entry.name == "Border Road").unwrap();
+        assert_eq!(street.city_resolved, "Edgeton");
+    }
+
+    #[test]
+    fn suburb_column_prefers_addr_suburb_over_place_nodes() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.001" />
+  <node id="3" lat="0.01" lon="0.0" />
+  <node id="4" lat="0.01" lon="0.001" />
+  <node id="5" lat="0.005" lon="0.0"><tag k="place" v="city" /><tag k="name" v="Bigcity" /></node>
+  <node id="6" lat="0.01" lon="0.002">
+    <tag k="place" v="suburb" /><tag k="name" v="Northend" />
+  </node>
+  <way id="10">
+    <nd ref="1" /><nd ref="2" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Tagged Lane" />
+    <tag k="addr:suburb" v="Southside" />
+  </way>
+  <way id="11">
+    <nd ref="3" /><nd ref="4" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Untagged Lane" />
+  </way>
+</osm>
+"#;
+        let options = ExtractOptions {
+            suburb: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        let (suburb, resolved) = (column(&rows, "suburb"), column(&rows, "city_resolved"));
+        let tagged = rows.iter().find(|row| row[0] == "Tagged Lane").unwrap();
+        assert_eq!(tagged[suburb], "Southside");
+        assert_eq!(tagged[resolved], "Bigcity");
+        let untagged = rows.iter().find(|row| row[0] == "Untagged Lane").unwrap();
+        assert_eq!(untagged[suburb], "Northend");
+    }
+
+    #[test]
+    fn tag_stats_counts_name_keys_of_kept_features() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+
+        let stats = tag_stats(&[osm_path], &ExtractOptions::default()).unwrap();
+        fn counts(map: &BTreeMap<String, usize>) -> Vec<(&str, usize)> {
+            map.iter().map(|(key, count)| (key.as_str(), *count)).collect()
+        }
+        // Both streets carry `name`; only Main Street has an `alt_name`. The
+        // boundaries are not kept by default, so they are not counted.
+        assert_eq!(counts(&stats.name_keys), [("alt_name", 1), ("name", 2)]);
+        assert_eq!(counts(&stats.place_types), [("town", 1)]);
+        assert_eq!(counts(&stats.highways), [("residential", 2)]);
+        assert!(stats.poi_kinds.is_empty());
+        assert!(stats.report().starts_with("name keys:\n         2  name\n         1  alt_name\n"));
+    }
+
+    #[test]
+    fn pois_on_different_levels_are_not_merged() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.6" lon="0.6"><tag k="place" v="town" /><tag k="name" v="Malltown" /></node>
+  <node id="2" lat="0.601" lon="0.601">
+    <tag k="tourism" v="museum" /><tag k="name" v="Toy Museum" /><tag k="level" v="0" />
+  </node>
+  <node id="3" lat="0.601" lon="0.601">
+    <tag k="tourism" v="museum" /><tag k="name" v="Toy Museum" /><tag k="level" v="2" />
+  </node>
+  <node id="4" lat="0.601" lon="0.601">
+    <tag k="tourism" v="museum" /><tag k="name" v="Toy Museum" /><tag k="layer" v="2" />
+  </node>
+</osm>
+"#;
+        let options = ExtractOptions {
+            with_level: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        let level = column(&rows, "level");
+        let mut levels: Vec<&str> = rows
+            .iter()
+            .filter(|row| row[0] == "Toy Museum")
+            .map(|row| row[level].as_str())
+            .collect();
+        levels.sort_unstable();
+        assert_eq!(levels, ["0", "2"]);
+    }
+
+    #[test]
+    fn sample_rate_keeps_a_stable_fraction() {
+        let rows_at = |rate: f64| {
+            let options = ExtractOptions {
+                sample_rate: Some(rate),
+                ..ExtractOptions::default()
+            };
+            extract_rows(OSM_SAMPLE, &options)
+        };
+        let all = extract_rows(OSM_SAMPLE, &ExtractOptions::default());
+        assert!(all.len() > 1);
+        assert_eq!(rows_at(0.0).len(), 1);
+        assert_eq!(rows_at(1.0), all);
+
+        let names: Vec<String> = (0..200).map(|i| format!("Street {i}")).collect();
+        let sampled = |rate: f64| {
+            names
+                .iter()
+                .filter(|name| in_sample(name, "street", (13.4, 52.5), rate))
+                .count()
+        };
+        let half = sampled(0.5);
+        assert!((70..=130).contains(&half), "{half}");
+        assert_eq!(sampled(0.5), half);
+        assert!(sampled(0.25) <= half);
+    }
+
+    #[test]
+    fn names_drop_invisible_characters_and_extra_spaces() {
+        assert_eq!(normalize_name("Rue\u{a0}de\u{200b} la  Paix\u{7f}"), "Rue de la Paix");
+        assert_eq!(normalize_name("\u{feff}\u{200b}"), "");
+        assert_eq!(normalize_name("می\u{200c}خانه"), "می\u{200c}خانه");
+
+        let mut tags = Tags::new();
+        tags.insert("name".into(), "Main\u{a0}Street\u{200b}".into());
+        tags.insert("alt_name".into(), "Main Street".into());
+        let names = collect_name_variants(&tags, &ExtractOptions::default());
+        let names: Vec<&str> = names.iter().map(|variant| variant.name.as_str()).collect();
+        assert_eq!(names, ["Main Street"]);
+
+        let options = ExtractOptions {
+            raw_names: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(collect_name_variants(&tags, &options).len(), 2);
+    }
+
+    #[test]
+    fn addr_city_scores_higher_than_a_far_place() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0"><tag k="place" v="town" /><tag k="name" v="Neartown" /></node>
+  <node id="2" lat="0.001" lon="0.0" />
+  <node id="3" lat="0.001" lon="0.001" />
+  <node id="4" lat="0.3" lon="0.0" />
+  <node id="5" lat="0.3" lon="0.001" />
+  <way id="10">
+    <nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Tagged Road" />
+    <tag k="addr:city" v="Neartown" />
+  </way>
+  <way id="11">
+    <nd ref="4" /><nd ref="5" />
+    <tag k="highway" v="residential" />
+    <tag k="name" v="Far Road" />
+  </way>
+</osm>
+"#;
+        let options = ExtractOptions {
+            city_confidence: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        let confidence = column(&rows, "city_confidence");
+        let resolved = column(&rows, "city_resolved");
+        let row = |name: &str| rows.iter().find(|row| row[0] == name).unwrap();
+        let score = |name: &str| row(name)[confidence].parse::<f64>().unwrap();
+
+        assert_eq!(score("Tagged Road"), 1.0);
+        // About 33 km from the town: 0.4 * (1 - 33 / 75).
+        assert_eq!(row("Far Road")[resolved], "Neartown");
+        assert!((score("Far Road") - 0.22).abs() < 0.011, "{}", score("Far Road"));
+        assert_eq!(score("Neartown"), 1.0);
+    }
+
+    #[test]
+    fn named_buildings_are_extracted_only_under_the_flag() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.001" />
+  <node id="3" lat="0.001" lon="0.001" />
+  <node id="4" lat="0.001" lon="0.0" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" /><nd ref="3" /><nd ref="4" /><nd ref="1" />
+    <tag k="building" v="office" />
+    <tag k="name" v="Tall Tower" />
+  </way>
+  <way id="11">
+    <nd ref="1" /><nd ref="2" /><nd ref="3" /><nd ref="4" /><nd ref="1" />
+    <tag k="building" v="yes" />
+    <tag k="name" v="Famous Hall" />
+    <tag k="wikidata" v="Q1" />
+  </way>
+</osm>
+"#;
+        let buildings = |options: &ExtractOptions| {
+            let rows = extract_rows(osm, options);
+            let kind = column(&rows, "kind");
+            let mut names: Vec<String> = rows
+                .iter()
+                .filter(|row| row[kind] == "building")
+                .map(|row| row[0].clone())
+                .collect();
+            names.sort();
+            names
+        };
+        assert!(buildings(&ExtractOptions::default()).is_empty());
+        let options = ExtractOptions {
+            include_named_buildings: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(buildings(&options), ["Famous Hall", "Tall Tower"]);
+        let options = ExtractOptions {
+            named_buildings_need_wiki: true,
+            ..options
+        };
+        assert_eq!(buildings(&options), ["Famous Hall"]);
+    }
+
+    #[test]
+    fn nearly_closed_ways_are_treated_as_rings() {
+        // The last node is a separate node about 1 m from the first.
+        let coords = [(0.0, 0.0), (0.001, 0.0), (0.001, 0.001), (0.0, 0.001), (0.00001, 0.0)];
+        let refs = [1, 2, 3, 4, 5];
+        assert!(is_ring(&refs, &coords));
+        assert!(is_ring(&[1, 2, 1], &coords[..3]));
+        let open = [(0.0, 0.0), (0.001, 0.0), (0.001, 0.001), (0.0, 0.001), (0.0, 0.0005)];
+        assert!(!is_ring(&refs, &open));
+        assert!(!is_ring(&refs[..3], &[(0.0, 0.0), (0.001, 0.0), (0.0, 0.0)]));
+
+        let mut tags = Tags::new();
+        tags.insert("highway".into(), "pedestrian".into());
+        tags.insert("area".into(), "yes".into());
+        let is_closed = is_ring(&refs, &coords);
+        let plaza = way_geometry(&coords, is_closed, true, &tags, PoiLength::default()).unwrap();
+        assert!(plaza.area_km2 > 0.01, "{}", plaza.area_km2);
+        assert_eq!(plaza.length_km, 0.0);
+        assert!((plaza.center.0 - 0.0005).abs() < 1e-5 && (plaza.center.1 - 0.0005).abs() < 1e-5);
+    }
+
+    #[test]
+    fn dedup_distance_drops_duplicates_left_by_merging() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.6" lon="0.6"><tag k="place" v="town" /><tag k="name" v="Placetown" /></node>
+  <node id="2" lat="0.61" lon="0.6" />
+  <node id="3" lat="0.61" lon="0.602" />
+  <node id="4" lat="0.61" lon="0.63" />
+  <node id="5" lat="0.61" lon="0.631" />
+  <node id="6" lat="0.61" lon="0.7" />
+  <node id="7" lat="0.61" lon="0.701" />
+  <way id="10">
+    <nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="Long Road" />
+  </way>
+  <way id="11">
+    <nd ref="4" /><nd ref="5" />
+    <tag k="highway" v="residential" /><tag k="name" v="Long Road" />
+  </way>
+  <way id="12">
+    <nd ref="6" /><nd ref="7" />
+    <tag k="highway" v="residential" /><tag k="name" v="Long Road" />
+  </way>
+</osm>
+"#;
+        let long_roads = |options: &ExtractOptions| {
+            let rows = extract_rows(osm, options);
+            let lon = column(&rows, "center_lon");
+            let mut lons: Vec<f64> = rows
+                .iter()
+                .filter(|row| row[0] == "Long Road")
+                .map(|row| row[lon].parse().unwrap())
+                .collect();
+            lons.sort_by(f64::total_cmp);
+            lons
+        };
+        // About 3 km apart, so merging leaves three rows.
+        assert_eq!(long_roads(&ExtractOptions::default()).len(), 3);
+        let options = ExtractOptions {
+            dedup_distance_km: Some(5.0),
+            ..ExtractOptions::default()
+        };
+        // The longer western segment wins; the road 11 km east stays.
+        let kept = long_roads(&options);
+        assert_eq!(kept.len(), 2);
+        assert!((kept[0] - 0.601).abs() < 1e-6 && (kept[1] - 0.7005).abs() < 1e-6, "{kept:?}");
+    }
+
+    #[test]
+    fn rename_map_merges_spellings_into_one_row() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.6" lon="0.6"><tag k="place" v="town" /><tag k="name" v="Placetown" /></node>
+  <node id="2" lat="0.61" lon="0.6" />
+  <node id="3" lat="0.61" lon="0.601" />
+  <node id="4" lat="0.61" lon="0.602" />
+  <way id="10">
+    <nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="St. Marks Rd" />
+  </way>
+  <way id="11">
+    <nd ref="3" /><nd ref="4" />
+    <tag k="highway" v="residential" /><tag k="name" v="Saint Mark's Road" />
+  </way>
+</osm>
+"#;
+        let map = RenameMap::from_csv(
+            "from,to\n# OSM spelling, canonical\nSt. Marks Rd,St Mark's Road\n\
+             Saint Mark's Road,St Mark's Road\n",
+        )
+        .unwrap();
+        let options = ExtractOptions {
+            rename_map: Some(map),
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        let roads: Vec<&Vec<String>> = rows.iter().filter(|row| row[0].contains("Mark")).collect();
+        assert_eq!(roads.len(), 1);
+        assert_eq!(roads[0][0], "St Mark's Road");
+        assert_eq!(roads[0][column(&rows, "city_resolved")], "Placetown");
+        assert_eq!(options.rename_map.unwrap().rename("Other".into()), "Other");
+
+        assert!(RenameMap::from_csv("only-one-column\n").is_err());
+    }
+
+    #[test]
+    fn ele_is_parsed_to_meters() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0">
+    <tag k="railway" v="station" /><tag k="name" v="Low Station" /><tag k="ele" v="324" />
+  </node>
+  <node id="2" lat="0.5" lon="0.5">
+    <tag k="railway" v="station" /><tag k="name" v="High Station" /><tag k="ele" v="324 m" />
+  </node>
+  <node id="3" lat="1.0" lon="1.0">
+    <tag k="railway" v="station" /><tag k="name" v="No Station" />
+  </node>
+</osm>
+"#;
+        let options = ExtractOptions {
+            with_ele: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        let ele = column(&rows, "ele_m");
+        let ele_of = |name: &str| rows.iter().find(|row| row[0] == name).unwrap()[ele].clone();
+        assert_eq!(ele_of("Low Station"), "324");
+        assert_eq!(ele_of("High Station"), "324");
+        assert_eq!(ele_of("No Station"), "");
+
+        assert_eq!(parse_ele_m("1000 ft"), Some(304.8));
+        assert_eq!(parse_ele_m(" 12.34m"), Some(12.3));
+        assert_eq!(parse_ele_m("~300"), None);
+    }
+
+    #[test]
+    fn max_rows_per_name_keeps_the_longest() {
+        // Five "Track" clusters far apart, 1 to 5 steps long, and one other road.
+        let mut osm = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.6" lon="0.6"><tag k="place" v="town" /><tag k="name" v="Placetown" /></node>
+"#,
+        );
+        for track in 1..=5 {
+            let lat = 0.6 + track as f64 * 0.05;
+            let (start, end) = (track * 10, track * 10 + 1);
+            let lon = 0.6 + track as f64 * 0.001;
+            osm.push_str(&format!(
+                r#"  <node id="{start}" lat="{lat}" lon="0.6" />
+  <node id="{end}" lat="{lat}" lon="{lon}" />
+  <way id="{track}00"><nd ref="{start}" /><nd ref="{end}" />
+    <tag k="highway" v="track" /><tag k="name" v="Track" /></way>
+"#
+            ));
+        }
+        osm.push_str(
+            r#"  <node id="90" lat="0.61" lon="0.6" /><node id="91" lat="0.61" lon="0.601" />
+  <way id="900"><nd ref="90" /><nd ref="91" />
+    <tag k="highway" v="residential" /><tag k="name" v="Real Road" /></way>
+</osm>
+"#,
+        );
+        let options = ExtractOptions {
+            max_rows_per_name: Some(2),
+            length_unit: Some(LengthUnit::Meters),
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(&osm, &options);
+        let length = column(&rows, "length_m");
+        let mut tracks: Vec<f64> = rows
+            .iter()
+            .filter(|row| row[0] == "Track")
+            .map(|row| row[length].parse().unwrap())
+            .collect();
+        tracks.sort_by(f64::total_cmp);
+        assert_eq!(tracks.len(), 2);
+        assert!(tracks[0] > 400.0 && tracks[1] > 500.0, "{tracks:?}");
+        assert_eq!(rows.iter().filter(|row| row[0] == "Real Road").count(), 1);
+    }
+
+    #[test]
+    fn parallel_place_collection_matches_a_serial_scan() {
+        let mut objs = BTreeMap::new();
+        for id in 0..2_000i64 {
+            let mut tags = Tags::new();
+            if id % 7 == 0 {
+                tags.insert("place".into(), ["town", "village", "suburb"][id as usize % 3].into());
+                tags.insert("name".into(), format!("Place {id}").into());
+            } else if id % 11 == 0 {
+                tags.insert("name".into(), format!("Not a place {id}").into());
+            }
+            let node = osmpbfreader::Node {
+                id: NodeId(id),
+                tags,
+                decimicro_lat: (id * 1_000) as i32,
+                decimicro_lon: (id * 2_000) as i32,
+            };
+            objs.insert(OsmId::Node(node.id), OsmObj::Node(node));
+        }
+        let options = ExtractOptions::default();
+        let summary = |places: &[PlaceNode]| {
+            let mut summary: Vec<(String, String, (f64, f64))> = places
+                .iter()
+                .map(|place| (place.name.clone(), place.place_type.clone(), place.coord))
+                .collect();
+            summary.sort_by(|a, b| a.0.cmp(&b.0));
+            summary
+        };
+        let serial: Vec<PlaceNode> = objs
+            .values()
+            .filter_map(|obj| match obj {
+                OsmObj::Node(node) => {
+                    place_node_from_tags(&node.tags, (node.lon(), node.lat()), &options)
+                }
+                _ => None,
+            })
+            .collect();
+        let parallel = collect_pbf_place_nodes(&objs, &options);
+        assert_eq!(serial.len(), 286);
+        assert_eq!(summary(&parallel), summary(&serial));
+    }
+
+    #[test]
+    fn bbox_from_input_clips_to_the_reference_extent() {
+        let dir = tempdir().unwrap();
+        let reference = dir.path().join("reference.osm");
+        std::fs::write(&reference, OSM_SAMPLE).unwrap();
+        let options = ExtractOptions::default();
+        let bbox = BoundingBox::from_input(&reference, &options).unwrap();
+        // Every node counts, including the corners of the Testland boundary.
+        assert_eq!(bbox, BoundingBox::parse("-5,-5,5,5").unwrap());
+
+        let far = dir.path().join("far.osm");
+        std::fs::write(
+            &far,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1000" lat="10.0" lon="10.0" />
+  <node id="1001" lat="10.0" lon="10.001" />
+  <way id="1002"><nd ref="1000" /><nd ref="1001" />
+    <tag k="highway" v="residential" /><tag k="name" v="Far Street" /></way>
+</osm>
+"#,
+        )
+        .unwrap();
+        let clipped = ExtractOptions {
+            bbox: Some(bbox),
+            ..ExtractOptions::default()
+        };
+        let entries = extract_entries_from(&[reference.clone(), far.clone()], &clipped).unwrap();
+        let names: HashSet<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert!(names.contains("Main Street") && !names.contains("Far Street"));
+
+        let empty = dir.path().join("empty.osm");
+        std::fs::write(&empty, "<osm version=\"0.6\"></osm>").unwrap();
+        assert!(BoundingBox::from_input(&empty, &options).is_err());
+    }
+
+    #[test]
+    fn include_natural_extracts_peaks_and_islands() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="1.0" lon="1.0">
+    <tag k="natural" v="peak" /><tag k="name" v="High Peak" /><tag k="ele" v="1200" />
+  </node>
+  <node id="2" lat="0.0" lon="0.0" />
+  <node id="3" lat="0.0" lon="0.02" />
+  <node id="4" lat="0.02" lon="0.02" />
+  <node id="5" lat="0.02" lon="0.0" />
+  <way id="10">
+    <nd ref="2" /><nd ref="3" /><nd ref="4" /><nd ref="5" /><nd ref="2" />
+    <tag k="place" v="island" /><tag k="name" v="Round Island" />
+  </way>
+</osm>
+"#;
+        let natural = |options: &ExtractOptions| {
+            let rows = extract_rows(osm, options);
+            let (kind, lon, lat) =
+                (column(&rows, "kind"), column(&rows, "center_lon"), column(&rows, "center_lat"));
+            let mut found: Vec<(String, f64, f64)> = rows
+                .iter()
+                .filter(|row| row[kind] == "natural")
+                .map(|row| (row[0].clone(), row[lon].parse().unwrap(), row[lat].parse().unwrap()))
+                .collect();
+            found.sort_by(|a, b| a.0.cmp(&b.0));
+            found
+        };
+        assert!(natural(&ExtractOptions::default()).is_empty());
+        let options = ExtractOptions {
+            include_natural: true,
+            ..ExtractOptions::default()
+        };
+        let found = natural(&options);
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].0.as_str(), found[0].1, found[0].2), ("High Peak", 1.0, 1.0));
+        // The island's center is its polygon centroid.
+        assert_eq!(found[1].0, "Round Island");
+        assert!((found[1].1 - 0.01).abs() < 1e-9 && (found[1].2 - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn name_lang_reports_the_language_variant() {
+        let rows = extract_rows(OSM_MULTI_NAME, &ExtractOptions::default());
+        let lang = column(&rows, "name_lang");
+        let langs: Vec<(&str, &str)> =
+            rows[1..].iter().map(|row| (row[0].as_str(), row[lang].as_str())).collect();
+        assert_eq!(langs, [("First", ""), ("Second", ""), ("Erste Straße", "de")]);
+    }
+
+    #[test]
+    fn nearest_place_is_found_across_the_antimeridian() {
+        let town = |name: &str, lon: f64| {
+            PlaceNode::new(
+                vec![NameVariant {
+                    name: name.to_string(),
+                    lang: "".to_string(),
+                }],
+                "town".to_string(),
+                (lon, -17.0),
+                None,
+            )
+        };
+        for cell_size_deg in [0.1, 1.0, 5.0] {
+            let places = vec![town("East", 179.9), town("Inland", 175.0)];
+            let index = PlaceIndex::new(places, cell_size_deg);
+            let place = index.nearest((-179.95, -17.0), PlaceFilter::ANY);
+            let place = place.map(|p| p.name.as_str());
+            assert_eq!(place, Some("East"), "cell size {cell_size_deg}");
+
+            let index = PlaceIndex::new(vec![town("West", -179.9)], cell_size_deg);
+            let place = index.nearest((179.95, -17.0), PlaceFilter::ANY);
+            let place = place.map(|p| p.name.as_str());
+            assert_eq!(place, Some("West"), "cell size {cell_size_deg}");
+        }
+        let (a, b) = ((179.9f64, -17.0f64), (-179.95f64, -17.0f64));
+        let cos = b.1.to_radians().cos();
+        let km = equirectangular_km(
+            a.0.to_radians(),
+            a.1.to_radians(),
+            cos,
+            b.0.to_radians(),
+            b.1.to_radians(),
+            cos,
+        );
+        assert!((km - haversine_km(a, b)).abs() < 0.01, "{km}");
+        assert!(km < 20.0);
+    }
+
+    #[test]
+    fn boundaries_output_lists_admin_boundaries() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        let boundaries_path = dir.path().join("boundaries.csv");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let options = ExtractOptions {
+            boundaries_output: Some(boundaries_path.clone()),
+            ..ExtractOptions::default()
+        };
+        extract_entries(&osm_path, &options).unwrap();
+
+        let mut reader = ReaderBuilder::new().from_path(&boundaries_path).unwrap();
+        let mut rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|row| row.unwrap().iter().map(str::to_string).collect())
+            .collect();
+        rows.sort();
+        let summary: Vec<(&str, &str)> =
+            rows.iter().map(|row| (row[0].as_str(), row[1].as_str())).collect();
+        assert_eq!(summary, [("Testland", "2"), ("Testville", "8")]);
+        // Testville is the square from -1 to 3 in both axes.
+        let testville = &rows[1];
+        assert_eq!((testville[2].as_str(), testville[3].as_str()), ("1", "1"));
+        let area: f64 = testville[4].parse().unwrap();
+        assert!((area - 16.0 * 111.19 * 111.19).abs() < 2_000.0, "{area}");
+    }
+
+    #[test]
+    fn min_place_rank_ignores_smaller_places() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0"><tag k="place" v="hamlet" /><tag k="name" v="Tinyham" /></node>
+  <node id="2" lat="0.001" lon="0.0" />
+  <node id="3" lat="0.001" lon="0.001" />
+  <way id="10">
+    <nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="Farm Lane" />
+  </way>
+</osm>
+"#;
+        let lane = |options: &ExtractOptions| {
+            let rows = extract_rows(osm, options);
+            let node = column(&rows, "city_place_node");
+            let resolved = column(&rows, "city_resolved");
+            let row = rows.iter().find(|row| row[0] == "Farm Lane").unwrap();
+            (row[node].clone(), row[resolved].clone())
+        };
+        assert_eq!(lane(&ExtractOptions::default()), ("Tinyham".into(), "Tinyham".into()));
+        let options = ExtractOptions {
+            min_place_rank: PlaceRank::parse("town").unwrap(),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(lane(&options), (String::new(), String::new()));
+
+        assert!(PlaceRank::City > PlaceRank::Town && PlaceRank::Suburb > PlaceRank::Locality);
+        assert!(PlaceRank::parse("metropolis").is_err());
+    }
+
+    #[test]
+    fn center_on_geometry_snaps_to_a_vertex() {
+        // A right-angled bend: the midpoint lies between the corner and the
+        // far end of the first leg, off the two outer vertices.
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.01" />
+  <node id="3" lat="0.004" lon="0.01" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="Bend Road" />
+  </way>
+</osm>
+"#;
+        let center = |options: &ExtractOptions| {
+            let rows = extract_rows(osm, options);
+            let (lon, lat) = (column(&rows, "center_lon"), column(&rows, "center_lat"));
+            (rows[1][lon].parse::<f64>().unwrap(), rows[1][lat].parse::<f64>().unwrap())
+        };
+        let (lon, lat) = center(&ExtractOptions::default());
+        assert!((lon - 0.007).abs() < 1e-6 && lat.abs() < 1e-6);
+        let options = ExtractOptions {
+            center_on_geometry: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(center(&options), (0.01, 0.0));
+    }
+
+    #[test]
+    fn duplicate_node_ids_warn_or_fail_under_strict() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.002" />
+  <node id="2" lat="0.0" lon="0.002" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" />
+    <tag k="highway" v="residential" /><tag k="name" v="Old Road" />
+  </way>
+  <node id="1" lat="0.0" lon="1.0" />
+  <node id="3" lat="0.0" lon="1.002" />
+  <way id="11">
+    <nd ref="1" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="New Road" />
+  </way>
+</osm>
+"#;
+        let mut nodes = HashMap::new();
+        let mut conflicts = 0;
+        let options = ExtractOptions::default();
+        for coord in [(0.0, 0.0), (0.0, 0.0), (1.0, 0.0)] {
+            insert_node_coord(&mut nodes, 1, coord, &mut conflicts, &options).unwrap();
+        }
+        assert_eq!((conflicts, nodes[&1]), (1, (1.0, 0.0)));
+
+        let rows = extract_rows(osm, &options);
+        assert_eq!(rows.len(), 3);
+
+        let dir = tempfile::tempdir().unwrap();
+        let osm_path = dir.path().join("dup.osm");
+        std::fs::write(&osm_path, osm).unwrap();
+        let strict = ExtractOptions {
+            strict: true,
+            ..ExtractOptions::default()
+        };
+        let err = extract_files_to_csv(&[osm_path], &dir.path().join("out.csv"), &strict);
+        assert!(err.unwrap_err().to_string().contains("node 1 appears twice"));
+    }
+
+    #[test]
+    fn crlf_ends_every_csv_line_with_crlf() {
+        let dir = tempfile::tempdir().unwrap();
+        let osm_paths = [dir.path().join("sample.osm")];
+        std::fs::write(&osm_paths[0], OSM_SAMPLE).unwrap();
+        let output = |crlf| {
+            let options = ExtractOptions {
+                crlf,
+                schema_comment: true,
+                ..ExtractOptions::default()
+            };
+            let mut out = Vec::new();
+            extract_files_to_writer(&osm_paths, &mut out, &options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(!output(false).contains('\r'));
+        let crlf = output(true);
+        assert!(crlf.contains("\r\n"));
+        assert_eq!(crlf.matches("\r\n").count(), crlf.matches('\n').count());
+        assert_eq!(crlf.replace("\r\n", "\n"), output(false));
+    }
+
+    #[test]
+    fn with_operator_brand_adds_poi_columns() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0">
+    <tag k="railway" v="station" /><tag k="public_transport" v="station" />
+    <tag k="name" v="Hauptbahnhof" /><tag k="operator" v="Deutsche Bahn" />
+  </node>
+  <node id="2" lat="0.0" lon="0.001" />
+  <node id="3" lat="0.0" lon="0.002" />
+  <way id="10">
+    <nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="Bahnhofstraße" />
+    <tag k="operator" v="City" />
+  </way>
+</osm>
+"#;
+        let options = ExtractOptions {
+            with_operator_brand: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        let (operator, brand) = (column(&rows, "operator"), column(&rows, "brand"));
+        let station = rows.iter().find(|row| row[0] == "Hauptbahnhof").unwrap();
+        assert_eq!((station[operator].as_str(), station[brand].as_str()), ("Deutsche Bahn", ""));
+        let street = rows.iter().find(|row| row[0] == "Bahnhofstraße").unwrap();
+        assert_eq!(street[operator], "");
+    }
+
+    #[test]
+    fn drop_zero_length_skips_collapsed_streets() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.0" />
+  <node id="3" lat="0.0" lon="0.002" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" />
+    <tag k="highway" v="residential" /><tag k="name" v="Ghost Lane" />
+  </way>
+  <way id="11">
+    <nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="Real Lane" />
+  </way>
+</osm>
+"#;
+        let names = |options: &ExtractOptions| {
+            let mut names: Vec<String> =
+                extract_rows(osm, options).into_iter().skip(1).map(|row| row[0].clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&ExtractOptions::default()), ["Ghost Lane", "Real Lane"]);
+        let options = ExtractOptions {
+            drop_zero_length: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(names(&options), ["Real Lane"]);
+    }
+
+    #[test]
+    fn places_output_lists_place_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        let osm_path = dir.path().join("sample.osm");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let places_path = dir.path().join("places.csv");
+        let options = ExtractOptions {
+            places_output: Some(places_path.clone()),
+            ..ExtractOptions::default()
+        };
+        extract_files_to_csv(&[osm_path], &dir.path().join("out.csv"), &options).unwrap();
+
+        let mut reader = ReaderBuilder::new().from_path(&places_path).unwrap();
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec!["name", "place_type", "lon", "lat", "is_city_town", "population"]
+        );
+        let rows: Vec<csv::StringRecord> = reader.records().map(|row| row.unwrap()).collect();
+        let town = rows.iter().find(|row| &row[0] == "Placetown").unwrap();
+        assert_eq!((&town[1], &town[2], &town[3], &town[4]), ("town", "0.6", "0.6", "true"));
+    }
+
+    #[test]
+    fn prefer_places_in_boundary_beats_a_nearer_place_outside() {
+        // The street's boundary holds Inham 1.5 km east; Outham is 1 km
+        // west, across the border.
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="-0.008"><tag k="place" v="hamlet" /><tag k="name" v="Outham" /></node>
+  <node id="2" lat="0.0" lon="0.0145"><tag k="place" v="town" /><tag k="name" v="Inham" /></node>
+  <node id="3" lat="0.0" lon="0.0" />
+  <node id="4" lat="0.0" lon="0.002" />
+  <way id="10">
+    <nd ref="3" /><nd ref="4" />
+    <tag k="highway" v="residential" /><tag k="name" v="Border Road" />
+  </way>
+  <node id="20" lat="-0.01" lon="-0.001" />
+  <node id="21" lat="-0.01" lon="0.03" />
+  <node id="22" lat="0.01" lon="0.03" />
+  <node id="23" lat="0.01" lon="-0.001" />
+  <way id="30">
+    <nd ref="20" /><nd ref="21" /><nd ref="22" /><nd ref="23" /><nd ref="20" />
+    <tag k="boundary" v="administrative" /><tag k="admin_level" v="8" />
+    <tag k="name" v="Inham" />
+  </way>
+</osm>
+"#;
+        let place = |options: &ExtractOptions| {
+            let rows = extract_rows(osm, options);
+            let node = column(&rows, "city_place_node");
+            rows.iter().find(|row| row[0] == "Border Road").unwrap()[node].clone()
+        };
+        assert_eq!(place(&ExtractOptions::default()), "Outham");
+        let options = ExtractOptions {
+            prefer_places_in_boundary: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(place(&options), "Inham");
+    }
+
+    #[test]
+    fn gz_output_path_writes_gzipped_csv() {
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        let osm_paths = [dir.path().join("sample.osm")];
+        std::fs::write(&osm_paths[0], OSM_SAMPLE).unwrap();
+        let plain_path = dir.path().join("out.csv");
+        let gz_path = dir.path().join("out.csv.gz");
+        let options = ExtractOptions::default();
+        let written = extract_files_to_csv(&osm_paths, &gz_path, &options).unwrap();
+        assert_eq!(extract_files_to_csv(&osm_paths, &plain_path, &options).unwrap(), written);
+
+        let mut decoded = String::new();
+        let file = File::open(&gz_path).unwrap();
+        flate2::read::GzDecoder::new(file).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, std::fs::read_to_string(&plain_path).unwrap());
+    }
+
+    #[test]
+    fn with_merged_from_lists_the_merged_way_ids() {
+        let options = ExtractOptions {
+            with_merged_from: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_MERGE_NEARBY, &options);
+        let merged_from = column(&rows, "merged_from");
+        let drive = rows.iter().find(|row| row[0] == "Dave Burns Drive").unwrap();
+        let mut ids: Vec<&str> = drive[merged_from].split(';').collect();
+        ids.sort();
+        assert_eq!(ids, ["w40", "w41"]);
+        let city = rows.iter().find(|row| row[0] == "Testville").unwrap();
+        assert_eq!(city[merged_from], "");
+    }
+
+    #[test]
+    fn use_destination_names_unnamed_links() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.002" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" />
+    <tag k="highway" v="motorway_link" /><tag k="destination" v="Berlin;Hamburg" />
+  </way>
+  <way id="11">
+    <nd ref="1" /><nd ref="2" />
+    <tag k="highway" v="residential" /><tag k="name" v="Main Street" />
+    <tag k="destination" v="Elsewhere" />
+  </way>
+</osm>
+"#;
+        let rows = extract_rows(osm, &ExtractOptions::default());
+        assert!(rows.iter().all(|row| !row[0].contains("Berlin")));
+
+        let options = ExtractOptions {
+            use_destination: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        let source = column(&rows, "name_source");
+        let link = rows.iter().find(|row| row[0] == "Berlin / Hamburg").unwrap();
+        assert_eq!(link[source], "destination");
+        let main = rows.iter().find(|row| row[0] == "Main Street").unwrap();
+        assert_eq!(main[source], "");
+        assert!(rows.iter().all(|row| row[0] != "Elsewhere"));
+    }
+
+    #[test]
+    fn min_area_m2_drops_slivers_but_keeps_small_squares() {
+        // A triangular plaza of about 100 m², and a four-node sliver whose
+        // middle node is 1 mm off the line between its ends.
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.00018" />
+  <node id="3" lat="0.00009" lon="0.0" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" /><nd ref="3" /><nd ref="1" />
+    <tag k="highway" v="pedestrian" /><tag k="area" v="yes" /><tag k="name" v="Small Plaza" />
+  </way>
+  <node id="4" lat="0.01" lon="0.0" />
+  <node id="5" lat="0.01" lon="0.001" />
+  <node id="6" lat="0.01000001" lon="0.002" />
+  <way id="11">
+    <nd ref="4" /><nd ref="5" /><nd ref="6" /><nd ref="4" />
+    <tag k="highway" v="pedestrian" /><tag k="area" v="yes" /><tag k="name" v="Sliver Plaza" />
+  </way>
+</osm>
+"#;
+        let names = |options: &ExtractOptions| {
+            let mut names: Vec<String> =
+                extract_rows(osm, options).into_iter().skip(1).map(|row| row[0].clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&ExtractOptions::default()), ["Sliver Plaza", "Small Plaza"]);
+        let options = ExtractOptions {
+            min_area_m2: Some(10.0),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(names(&options), ["Small Plaza"]);
+    }
+
+    #[test]
+    fn max_nodes_per_way_skips_longer_ways() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.001" />
+  <node id="3" lat="0.0" lon="0.002" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="Long Road" />
+  </way>
+  <way id="11">
+    <nd ref="1" /><nd ref="2" />
+    <tag k="highway" v="residential" /><tag k="name" v="Short Road" />
+  </way>
+</osm>
+"#;
+        let names = |options: &ExtractOptions| {
+            let mut names: Vec<String> =
+                extract_rows(osm, options).into_iter().skip(1).map(|row| row[0].clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&ExtractOptions::default()), ["Long Road", "Short Road"]);
+        let options = ExtractOptions {
+            max_nodes_per_way: Some(2),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(names(&options), ["Short Road"]);
+    }
+
+    #[test]
+    fn street_entry_csv_record_roundtrips() {
+        let options = ExtractOptions {
+            group_aliases: true,
+            extra_tags: vec!["surface".to_string()],
+            // Keeps the way geometries.
+            format: OutputFormat::Jsonl,
+            ..ExtractOptions::default()
+        };
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("sample.osm");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let entries = extract_entries(&osm_path, &options).unwrap();
+        assert!(!entries.is_empty());
+        for entry in entries {
+            let record = csv::StringRecord::from(entry.to_csv_record());
+            assert_eq!(StreetEntry::from_csv_record(&record).unwrap(), entry);
+        }
+        let short = csv::StringRecord::from(vec!["Main Street"]);
+        assert!(StreetEntry::from_csv_record(&short).is_err());
+    }
+
+    #[test]
+    fn only_with_wiki_keeps_pois_with_a_wiki_tag() {
+        let options = ExtractOptions {
+            only_with_wiki: true,
+            ..ExtractOptions::default()
+        };
+        let names: Vec<String> =
+            extract_rows(OSM_POI, &options).into_iter().skip(1).map(|row| row[0].clone()).collect();
+        assert_eq!(names, ["Eiffel Tower"]);
+    }
+
+    #[test]
+    fn place_nodes_are_rows_with_their_place_type() {
+        let rows = extract_rows(OSM_SAMPLE, &ExtractOptions::default());
+        let place_type = column(&rows, "city_place_type");
+        let town = rows.iter().find(|row| row[0] == "Placetown").unwrap();
+        assert_eq!((town[2].as_str(), town[place_type].as_str()), ("city", "town"));
+    }
+
+    #[test]
+    fn merge_case_insensitive_keeps_the_dominant_spelling() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.001" lon="0.0" />
+  <node id="3" lat="0.002" lon="0.0" />
+  <node id="4" lat="0.003" lon="0.0" />
+  <node id="10" lat="0.001" lon="0.001">
+    <tag k="place" v="town" /><tag k="name" v="Testville" />
+  </node>
+  <way id="40">
+    <nd ref="1" /><nd ref="2" />
+    <tag k="highway" v="residential" /><tag k="name" v="HAUPTSTRASSE" />
+  </way>
+  <way id="41">
+    <nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="Hauptstraße" />
+  </way>
+  <way id="42">
+    <nd ref="3" /><nd ref="4" />
+    <tag k="highway" v="residential" /><tag k="name" v="Hauptstraße" />
+  </way>
+</osm>
+"#;
+        let streets = |options: &ExtractOptions| {
+            let mut names: Vec<String> = extract_rows(osm, options)
+                .into_iter()
+                .skip(1)
+                .filter(|row| row[2] == "street")
+                .map(|row| row[0].clone())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(streets(&ExtractOptions::default()), ["HAUPTSTRASSE", "Hauptstraße"]);
+        let options = ExtractOptions {
+            merge_case_insensitive: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(streets(&options), ["Hauptstraße"]);
+
+        let accents = ExtractOptions {
+            merge_accent_insensitive: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(merge_name_key("Rue de l'Église", &accents), "Rue de l'Eglise");
+        assert_eq!(merge_name_key("Hauptstraße", &options), "hauptstrasse");
+    }
+
+    #[test]
+    fn deadline_fails_a_slowed_loop_once_the_timeout_passes() {
+        let deadline = Deadline::start(Some(Duration::from_millis(20)));
+        let mut rounds = 0;
+        let err = loop {
+            if let Err(err) = deadline.check() {
+                break err;
+            }
+            rounds += 1;
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        assert!(rounds >= 1);
+        assert!(matches!(err, ExtractError::Timeout(limit) if limit == Duration::from_millis(20)));
+        assert!(Deadline::start(None).check().is_ok());
+    }
+
+    #[test]
+    fn timeout_aborts_extraction_with_a_timeout_error() {
+        let dir = tempdir().unwrap();
+        let input = [dir.path().join("sample.osm")];
+        std::fs::write(&input[0], OSM_SAMPLE).unwrap();
+        let options = ExtractOptions {
+            timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let err = extract_entries_from(&input, &options).unwrap_err();
+        assert!(matches!(err, ExtractError::Timeout(_)));
+        assert!(err.to_string().contains("timeout"));
+        let options = ExtractOptions {
+            timeout: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert!(!extract_entries_from(&input, &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn jsonl_writes_one_geojson_feature_per_line() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("input.osm");
+        let output = dir.path().join("out.jsonl");
+        std::fs::write(&input, OSM_MERGE_NEARBY).unwrap();
+        let options = ExtractOptions {
+            format: OutputFormat::Jsonl,
+            ..Default::default()
+        };
+        extract_to_csv(&input, &output, &options).unwrap();
+
+        let text = std::fs::read_to_string(&output).unwrap();
+        let features: Vec<serde_json::Value> =
+            text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert!(features.len() >= 2);
+        for feature in &features {
+            assert_eq!(feature["type"], "Feature");
+            assert!(feature["geometry"]["coordinates"].is_array());
+            assert!(feature["properties"]["kind"].is_string());
+        }
+        let drive = features
+            .iter()
+            .find(|feature| feature["properties"]["streetname"] == "Dave Burns Drive")
+            .unwrap();
+        // Ways 40 and 41 are merged into one row with both lines.
+        assert_eq!(drive["geometry"]["type"], "MultiLineString");
+        assert_eq!(drive["geometry"]["coordinates"].as_array().unwrap().len(), 2);
+        let city = features
+            .iter()
+            .find(|feature| feature["properties"]["kind"] == "city")
+            .unwrap();
+        assert_eq!(city["geometry"]["type"], "Point");
+    }
+
+    #[test]
+    fn unswap_coord_only_corrects_out_of_range_latitudes() {
+        // lat="120" lon="45": 120 can only be a longitude.
+        assert_eq!(unswap_coord((45.0, 120.0)), Some((120.0, 45.0)));
+        assert_eq!(unswap_coord((-45.0, -120.0)), Some((-120.0, -45.0)));
+        // Valid points, and values no swap can fix, are left alone.
+        assert_eq!(unswap_coord((120.0, 45.0)), None);
+        assert_eq!(unswap_coord((45.0, 200.0)), None);
+        assert_eq!(unswap_coord((100.0, 120.0)), None);
+
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="120.0" lon="45.0">
+    <tag k="tourism" v="museum" /><tag k="name" v="Swapped Museum" />
+  </node>
+</osm>
+"#;
+        let options = ExtractOptions {
+            detect_swapped_coords: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        let (lon, lat) = (column(&rows, "center_lon"), column(&rows, "center_lat"));
+        assert_eq!((rows[1][lon].as_str(), rows[1][lat].as_str()), ("120", "45"));
+        let rows = extract_rows(osm, &ExtractOptions::default());
+        assert_eq!((rows[1][lon].as_str(), rows[1][lat].as_str()), ("45", "120"));
+    }
+
+    #[test]
+    fn columns_selects_and_orders_the_written_columns() {
+        let options = ExtractOptions {
+            columns: parse_columns("center_lat, streetname,center_lon").unwrap(),
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_SAMPLE, &options);
+        assert_eq!(rows[0], ["center_lat", "streetname", "center_lon"]);
+
+        let plain = extract_rows(OSM_SAMPLE, &ExtractOptions::default());
+        let (name, lon, lat) = (0, column(&plain, "center_lon"), column(&plain, "center_lat"));
+        let expected: Vec<Vec<String>> = plain[1..]
+            .iter()
+            .map(|row| vec![row[lat].clone(), row[name].clone(), row[lon].clone()])
+            .collect();
+        assert_eq!(rows[1..], expected[..]);
+
+        assert!(parse_columns("streetname,streetname").is_err());
+        assert!(parse_columns(" , ").is_err());
+        let unknown = ExtractOptions {
+            columns: vec!["name".to_string()],
+            ..ExtractOptions::default()
+        };
+        assert!(output_header(&unknown).is_err());
+    }
+
+    #[test]
+    fn xml_relation_members_are_read_with_type_ref_and_role() {
+        let member = |content: &str| read_member(&BytesStart::from_content(content, 6)).unwrap();
+        assert_eq!(
+            member(r#"member type="way" ref="40" role="outer""#),
+            Some((MemberType::Way, 40, "outer".to_string()))
+        );
+        assert_eq!(
+            member(r#"member type="relation" ref="7""#),
+            Some((MemberType::Relation, 7, String::new()))
+        );
+        assert_eq!(member(r#"member type="area" ref="7" role="outer""#), None);
+        assert_eq!(member(r#"member type="node" ref="x""#), None);
+
+        // Relations are collected and assembled from .osm input: the
+        // associatedStreet members give the houses their street.
+        let rows = extract_rows(OSM_ASSOCIATED_STREET, &ExtractOptions::default());
+        let addr_street = column(&rows, "addr_street");
+        let museum = rows.iter().find(|row| row[0] == "Harbor Museum").unwrap();
+        assert_eq!(museum[addr_street], "Quay Street");
+    }
+
+    #[test]
+    fn place_filter_skips_loading_other_place_types() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0"><tag k="place" v="hamlet" /><tag k="name" v="Tinyham" /></node>
+  <node id="2" lat="0.001" lon="0.0" />
+  <node id="3" lat="0.001" lon="0.001" />
+  <way id="10">
+    <nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="Farm Lane" />
+  </way>
+</osm>
+"#;
+        let options = ExtractOptions {
+            place_filter: parse_place_filter("city, town").unwrap(),
+            ..ExtractOptions::default()
+        };
+        let mut tags = Tags::new();
+        tags.insert("place".into(), "hamlet".into());
+        tags.insert("name".into(), "Tinyham".into());
+        assert!(place_node_from_tags(&tags, (0.0, 0.0), &options).is_none());
+        assert!(place_node_from_tags(&tags, (0.0, 0.0), &ExtractOptions::default()).is_some());
+
+        let rows = extract_rows(osm, &options);
+        assert_eq!(rows.len(), 2, "only Farm Lane, no hamlet row");
+        let lane = &rows[1];
+        assert_eq!(lane[0], "Farm Lane");
+        assert_eq!(lane[column(&rows, "city_place_node")], "");
+
+        assert!(parse_place_filter("city,metropolis").is_err());
+        assert!(parse_place_filter(",").is_err());
+    }
+
+    #[test]
+    fn with_sinuosity_compares_street_length_to_its_chord() {
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.0" lon="0.001" />
+  <node id="3" lat="0.0" lon="0.002" />
+  <node id="4" lat="0.1" lon="0.0" />
+  <node id="5" lat="0.101" lon="0.001" />
+  <node id="6" lat="0.1" lon="0.002" />
+  <node id="7" lat="0.2" lon="0.0" />
+  <node id="8" lat="0.201" lon="0.0" />
+  <node id="9" lat="0.201" lon="0.001" />
+  <way id="10">
+    <nd ref="1" /><nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="Straight Street" />
+  </way>
+  <way id="11">
+    <nd ref="4" /><nd ref="5" /><nd ref="6" />
+    <tag k="highway" v="residential" /><tag k="name" v="Bent Street" />
+  </way>
+  <way id="12">
+    <nd ref="7" /><nd ref="8" /><nd ref="9" /><nd ref="7" />
+    <tag k="highway" v="residential" /><tag k="name" v="Loop Street" />
+  </way>
+</osm>
+"#;
+        let options = ExtractOptions {
+            with_sinuosity: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(osm, &options);
+        let sinuosity = column(&rows, "sinuosity");
+        let value = |name: &str| {
+            rows.iter().find(|row| row[0] == name).unwrap()[sinuosity].clone()
+        };
+        assert_eq!(value("Straight Street"), "1.000");
+        let bent: f64 = value("Bent Street").parse().unwrap();
+        // Two legs at 45 degrees: sqrt(2) times the chord.
+        assert!((bent - 2f64.sqrt()).abs() < 0.01, "{bent}");
+        assert_eq!(value("Loop Street"), "");
+    }
+
+    #[test]
+    fn ring_search_matches_a_full_scan() {
+        let mut places = grid_places((13.0, 52.0), 0.3, 6);
+        places.extend(grid_places((179.2, -17.3), 0.25, 5));
+        places.extend(grid_places((-179.95, -16.9), 0.4, 2));
+        let queries = [
+            (13.71, 52.44),
+            (12.2, 51.6),
+            (14.9, 53.9),
+            (179.99, -17.0),
+            (-179.6, -16.5),
+            (-178.5, -16.0),
+            (100.0, 0.0),
+        ];
+        for cell_size_deg in [0.1, 0.3, 0.7, 1.0, 5.0] {
+            let index = PlaceIndex::new(places.clone(), cell_size_deg);
+            for query in queries {
+                let expected = places
+                    .iter()
+                    .map(|place| (haversine_km(query, place.coord), place.coord))
+                    .filter(|(km, _)| *km <= MAX_PLACE_DISTANCE_KM)
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                    .map(|(_, coord)| coord);
+                let actual = index.nearest(query, PlaceFilter::ANY).map(|p| p.coord);
+                assert_eq!(actual, expected, "cell size {cell_size_deg}, query {query:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn tag_stats_applies_area_name_and_sample_filters() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        fn counts(map: &BTreeMap<String, usize>) -> Vec<(&str, usize)> {
+            map.iter().map(|(key, count)| (key.as_str(), *count)).collect()
+        }
+
+        // Only Open Way lies north of latitude 1.2.
+        let options = ExtractOptions {
+            bbox: Some(BoundingBox::parse("-1,1.2,3,3").unwrap()),
+            ..ExtractOptions::default()
+        };
+        let stats = tag_stats(std::slice::from_ref(&osm_path), &options).unwrap();
+        assert_eq!(counts(&stats.name_keys), [("name", 1)]);
+        assert_eq!(counts(&stats.highways), [("residential", 1)]);
+        assert!(stats.place_types.is_empty());
+
+        let options = ExtractOptions {
+            name_exclude: Some(Regex::new("^Open").unwrap()),
+            ..ExtractOptions::default()
+        };
+        let stats = tag_stats(std::slice::from_ref(&osm_path), &options).unwrap();
+        assert_eq!(counts(&stats.name_keys), [("alt_name", 1), ("name", 1)]);
+        assert_eq!(counts(&stats.place_types), [("town", 1)]);
+
+        let options = ExtractOptions {
+            sample_rate: Some(0.0),
+            ..ExtractOptions::default()
+        };
+        let stats = tag_stats(&[osm_path], &options).unwrap();
+        assert!(stats.name_keys.is_empty() && stats.place_types.is_empty());
+    }
+
+    #[test]
+    fn city_confidence_uses_the_boundary_and_the_configured_radius() {
+        // Gardenway lies inside the Borough boundary; Farcity is 22 km east.
+        let osm = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.2"><tag k="place" v="town" /><tag k="name" v="Farcity" /></node>
+  <node id="2" lat="0.0" lon="0.0" />
+  <node id="3" lat="0.0" lon="0.002" />
+  <way id="10">
+    <nd ref="2" /><nd ref="3" />
+    <tag k="highway" v="residential" /><tag k="name" v="Gardenway" />
+  </way>
+  <node id="20" lat="-0.01" lon="-0.01" />
+  <node id="21" lat="-0.01" lon="0.01" />
+  <node id="22" lat="0.01" lon="0.01" />
+  <node id="23" lat="0.01" lon="-0.01" />
+  <way id="30">
+    <nd ref="20" /><nd ref="21" /><nd ref="22" /><nd ref="23" /><nd ref="20" />
+    <tag k="boundary" v="administrative" /><tag k="admin_level" v="8" />
+    <tag k="name" v="Borough" />
+  </way>
+</osm>
+"#;
+        let resolved_in = |osm: &str, options: ExtractOptions| {
+            let options = ExtractOptions {
+                city_confidence: true,
+                ..options
+            };
+            let rows = extract_rows(osm, &options);
+            let city = column(&rows, "city_resolved");
+            let confidence = column(&rows, "city_confidence");
+            let row = rows.iter().find(|row| row[0] == "Gardenway").unwrap();
+            (row[city].clone(), row[confidence].parse::<f64>().unwrap())
+        };
+        let resolved = |options| resolved_in(osm, options);
+
+        // 0.4 * (1 - 22 / 75) under the default radius.
+        let (city, confidence) = resolved(ExtractOptions::default());
+        assert_eq!(city, "Farcity");
+        assert!((confidence - 0.28).abs() < 0.011, "{confidence}");
+        // 0.4 * (1 - 22 / 44) under a 44 km town radius.
+        let (city, confidence) = resolved(ExtractOptions {
+            place_radii: PlaceRadii {
+                town_km: 44.0,
+                hamlet_km: 44.0,
+            },
+            ..ExtractOptions::default()
+        });
+        assert_eq!(city, "Farcity");
+        assert!((confidence - 0.2).abs() < 0.011, "{confidence}");
+
+        // A boundary only scores the city it names; it never replaces it.
+        let in_boundary = || ExtractOptions {
+            prefer_places_in_boundary: true,
+            ..ExtractOptions::default()
+        };
+        let (city, confidence) = resolved(in_boundary());
+        assert_eq!(city, "Farcity");
+        assert!((confidence - 0.28).abs() < 0.011, "{confidence}");
+        let (city, confidence) = resolved_in(&osm.replace("Borough", "Farcity"), in_boundary());
+        assert_eq!((city.as_str(), confidence), ("Farcity", CONFIDENCE_BOUNDARY));
+    }
+
+    #[test]
+    fn json_map_holds_every_field_with_its_type() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("poi.osm");
+        std::fs::write(&osm_path, OSM_POI).unwrap();
+        let entries = extract_entries(&osm_path, &ExtractOptions::default()).unwrap();
+        let eiffel = entries.iter().find(|entry| entry.name == "Eiffel Tower").unwrap();
+
+        let map = eiffel.to_json_map();
+        assert_eq!(map.len(), 38);
+        assert_eq!(map["wikidata"], "Q243");
+        assert_eq!(map["center_lon"], eiffel.center_lon);
+        assert_eq!(map["segment_count"], 1);
+        assert!(map["aliases"].is_array() && map["merged_from"].is_array());
+        let coord = eiffel.city_place_coord.map(|(lon, lat)| vec![lon, lat]);
+        assert_eq!(map["city_place_coord"], serde_json::json!(coord));
+    }
+
+    #[cfg(feature = "pbf-writer")]
+    #[test]
+    fn synthetic_pbf_extracts_like_the_xml() {
+        let dir = tempdir().unwrap();
+        let shape = SyntheticOsm {
+            nodes: 40_000,
+            ways: 9_000,
+            names: 700,
+        };
+        let rows = |file: &str| {
+            let path = dir.path().join(file);
+            write_synthetic_osm(&path, shape).unwrap();
+            let mut rows: Vec<(String, String, usize, String, String)> =
+                extract_entries(&path, &ExtractOptions::default())
+                    .unwrap()
+                    .into_iter()
+                    .map(|entry| {
+                        let center = format!("{:.6},{:.6}", entry.center_lon, entry.center_lat);
+                        (entry.name, entry.kind, entry.segment_count, entry.city_resolved, center)
+                    })
+                    .collect();
+            rows.sort();
+            rows
+        };
+        let xml = rows("bench.osm");
+        assert_eq!(xml.iter().filter(|row| row.1 == "city").count(), 90);
+        assert_eq!(rows("bench.pbf"), xml);
+    }
+
+    #[test]
+    fn include_places_labels_place_rows_with_their_type() {
+        let options = ExtractOptions {
+            include_places: true,
+            ..ExtractOptions::default()
+        };
+        let rows = extract_rows(OSM_SAMPLE, &options);
+        let place_type = column(&rows, "place_type");
+        let town = rows.iter().find(|row| row[0] == "Placetown").unwrap();
+        assert_eq!((town[2].as_str(), town[place_type].as_str()), ("place", "town"));
+        let main = rows.iter().find(|row| row[0] == "Main Street").unwrap();
+        assert_eq!((main[2].as_str(), main[place_type].as_str()), ("street", ""));
+        assert!(rows[1..].iter().all(|row| row[2] != "city"));
+    }
+
+    #[cfg(feature = "pbf-writer")]
+    #[test]
+    fn mmap_reads_synthetic_pbf_like_buffered_reader() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("bench.pbf");
+        let shape = SyntheticOsm {
+            nodes: 40_000,
+            ways: 9_000,
+            names: 700,
+        };
+        write_synthetic_osm(&input, shape).unwrap();
+
+        let buffered = extract_entries(&input, &ExtractOptions::default()).unwrap();
+        let options = ExtractOptions {
+            mmap: true,
+            ..ExtractOptions::default()
+        };
+        let mapped = extract_entries(&input, &options).unwrap();
+        assert!(buffered.iter().filter(|entry| entry.kind == "street").count() > 600);
+        assert_eq!(mapped, buffered);
+    }
+
+    #[cfg(not(feature = "pbf-writer"))]
+    #[test]
+    fn synthetic_pbf_needs_the_pbf_writer_feature() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("bench.pbf");
+        let shape = SyntheticOsm {
+            nodes: 20,
+            ways: 2,
+            names: 2,
+        };
+        let err = write_synthetic_osm(&path, shape).unwrap_err();
+        assert!(err.to_string().contains("pbf-writer"), "{err}");
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn diff_outputs_reads_projected_centers_and_rejects_renamed_columns() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let write = |file: &str, options: ExtractOptions| {
+            let path = dir.path().join(file);
+            extract_to_csv(&osm_path, &path, &options).unwrap();
+            path
+        };
+        let degrees = write("degrees.csv", ExtractOptions::default());
+        let meters = write(
+            "meters.csv",
+            ExtractOptions {
+                crs: Crs::WebMercator,
+                ..ExtractOptions::default()
+            },
+        );
+        let renamed = write(
+            "renamed.csv",
+            ExtractOptions {
+                header_map: parse_header_map("streetname=name").unwrap(),
+                ..ExtractOptions::default()
+            },
+        );
+        let changes = dir.path().join("changes.csv");
+
+        let summary = diff_outputs(&degrees, &meters, &changes, 0.001).unwrap();
+        assert_eq!(summary, DiffSummary::default());
+        let err = diff_outputs(&degrees, &renamed, &changes, 0.001).unwrap_err();
+        assert!(err.to_string().contains("no streetname column"), "{err}");
+        assert!(err.to_string().contains("--header-map"), "{err}");
+    }
+
+    #[test]
+    fn checkpoint_survives_a_change_to_output_options() {
+        let dir = tempdir().unwrap();
+        let osm_path = dir.path().join("input.osm");
+        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
+        let checkpoint = dir.path().join("run.checkpoint");
+        let options = ExtractOptions {
+            checkpoint: Some(checkpoint.clone()),
+            ..ExtractOptions::default()
+        };
+        let out_path = dir.path().join("out.csv");
+        extract_to_csv(&osm_path, &out_path, &options).unwrap();
+        let saved = std::fs::read_to_string(&checkpoint).unwrap();
+        std::fs::write(&checkpoint, saved.replace("Open Way", "Resumed Way")).unwrap();
+
+        let written = ExtractOptions {
+            crlf: true,
+            crs: Crs::WebMercator,
+            header_map: parse_header_map("streetname=name").unwrap(),
+            with_ele: true,
+            max_rows_per_name: Some(1),
+            sample_rate: Some(1.0),
+            merge_case_insensitive: true,
+            ..options.clone()
+        };
+        let inputs = [osm_path.clone()];
+        let fingerprint = |options| checkpoint_fingerprint(&inputs, options).unwrap();
+        assert_eq!(fingerprint(&written), fingerprint(&options));
+        extract_to_csv(&osm_path, &out_path, &written).unwrap();
+        assert!(std::fs::read_to_string(&out_path).unwrap().contains("Resumed Way"));
+
+        let collected = ExtractOptions {
+            include_places: true,
+            ..options.clone()
+        };
+        assert_ne!(fingerprint(&collected), fingerprint(&options));
+    }
+}
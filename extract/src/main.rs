@@ -1,1326 +1,718 @@
-use csv::Writer;
-use osmpbfreader::{OsmId, OsmObj, OsmPbfReader, Tags};
-use quick_xml::events::{BytesStart, Event};
-use quick_xml::Reader;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use extract_street_polygons::{
+    diff_outputs, extract_files_to_csv, find_default_pbf, input_dir_files, parse_columns,
+    parse_date, parse_extra_tags, parse_header_map, parse_name_keys, parse_place_filter,
+    schema_json, tag_stats, write_synthetic_osm, BoundingBox, ClipPolygon, CoordSnap,
+    CountryPolygons, Crs, ExtractOptions, LengthUnit, MergeWeight, OutputFormat, PlaceCellSize,
+    PlaceRank, PoiLength, RenameMap, SyntheticOsm, DEFAULT_MOVE_THRESHOLD_KM, MAX_GEOHASH_LEN,
+    NAME_KEYS, STDOUT_PATH,
+};
+use regex::Regex;
 use std::env;
 use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+/// Argument errors are plain messages; library errors print through
+/// `ExtractError`'s `Display`.
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
-const NAME_KEYS: [&str; 6] = [
-    "name",
-    "alt_name",
-    "old_name",
-    "official_name",
-    "loc_name",
-    "short_name",
-];
-
-const NAME_PREFIXES: [&str; 6] = [
-    "name:",
-    "alt_name:",
-    "old_name:",
-    "official_name:",
-    "loc_name:",
-    "short_name:",
-];
-
-fn is_name_key(key: &str) -> bool {
-    NAME_KEYS.contains(&key) || NAME_PREFIXES.iter().any(|prefix| key.starts_with(prefix))
-}
-
-fn split_names(value: &str) -> Vec<String> {
-    value
-        .split(';')
-        .map(|part| part.trim())
-        .filter(|part| !part.is_empty())
-        .map(|part| part.to_string())
-        .collect()
-}
-
-#[derive(Clone)]
-struct NameVariant {
-    name: String,
-    lang: String,
-}
-
-fn add_names(
-    value: &str,
-    lang: &str,
-    variants: &mut Vec<NameVariant>,
-    seen: &mut HashSet<(String, String)>,
-) {
-    for name in split_names(value) {
-        let key = (name.clone(), lang.to_string());
-        if seen.insert(key.clone()) {
-            variants.push(NameVariant {
-                name,
-                lang: lang.to_string(),
-            });
-        }
-    }
-}
-
-fn collect_name_variants(tags: &Tags) -> Vec<NameVariant> {
-    let mut variants = Vec::new();
-    let mut seen: HashSet<(String, String)> = HashSet::new();
-
-    for key in NAME_KEYS {
-        if let Some(value) = tags.get(key) {
-            if !value.is_empty() {
-                add_names(value, "", &mut variants, &mut seen);
-            }
-        }
-    }
-
-    for (key, value) in tags.iter() {
-        if value.is_empty() {
-            continue;
-        }
-        if NAME_KEYS.contains(&key.as_str()) {
-            continue;
-        }
-        if let Some(prefix) = NAME_PREFIXES.iter().find(|prefix| key.starts_with(*prefix)) {
-            let lang = key.strip_prefix(prefix).unwrap_or("");
-            add_names(value, lang, &mut variants, &mut seen);
-        }
-    }
-
-    variants
-}
-
-fn polygon_centroid(coords: &[(f64, f64)]) -> Result<(f64, f64)> {
-    if coords.len() < 3 {
-        return Err("polygon must have at least 3 points".into());
-    }
-
-    let is_closed = coords.len() >= 4 && coords.first() == coords.last();
-    let mut area = 0.0;
-    let mut cx = 0.0;
-    let mut cy = 0.0;
-
-    for i in 0..(coords.len() - 1) {
-        let (x0, y0) = coords[i];
-        let (x1, y1) = coords[i + 1];
-        let cross = x0 * y1 - x1 * y0;
-        area += cross;
-        cx += (x0 + x1) * cross;
-        cy += (y0 + y1) * cross;
-    }
-    if !is_closed {
-        let (x0, y0) = coords[coords.len() - 1];
-        let (x1, y1) = coords[0];
-        let cross = x0 * y1 - x1 * y0;
-        area += cross;
-        cx += (x0 + x1) * cross;
-        cy += (y0 + y1) * cross;
-    }
-
-    area *= 0.5;
-    if area.abs() < 1e-12 {
-        let mut sum_x = 0.0;
-        let mut sum_y = 0.0;
-        let count = if is_closed { coords.len() - 1 } else { coords.len() };
-        for (x, y) in coords.iter().take(count) {
-            sum_x += x;
-            sum_y += y;
-        }
-        let count = count as f64;
-        return Ok((sum_x / count, sum_y / count));
-    }
-
-    Ok((cx / (6.0 * area), cy / (6.0 * area)))
-}
-
-fn line_midpoint(coords: &[(f64, f64)]) -> Result<(f64, f64)> {
-    if coords.len() < 2 {
-        return Err("line must have at least 2 points".into());
-    }
-
-    let mut total = 0.0;
-    for i in 0..(coords.len() - 1) {
-        let (x0, y0) = coords[i];
-        let (x1, y1) = coords[i + 1];
-        let dx = x1 - x0;
-        let dy = y1 - y0;
-        total += (dx * dx + dy * dy).sqrt();
-    }
-
-    if total == 0.0 {
-        let mut sum_x = 0.0;
-        let mut sum_y = 0.0;
-        for (x, y) in coords {
-            sum_x += x;
-            sum_y += y;
-        }
-        let count = coords.len() as f64;
-        return Ok((sum_x / count, sum_y / count));
-    }
-
-    let halfway = total / 2.0;
-    let mut acc = 0.0;
-    for i in 0..(coords.len() - 1) {
-        let (x0, y0) = coords[i];
-        let (x1, y1) = coords[i + 1];
-        let dx = x1 - x0;
-        let dy = y1 - y0;
-        let len = (dx * dx + dy * dy).sqrt();
-        if acc + len >= halfway {
-            let t = (halfway - acc) / len;
-            return Ok((x0 + (x1 - x0) * t, y0 + (y1 - y0) * t));
-        }
-        acc += len;
-    }
-
-    Ok(*coords.last().unwrap())
-}
-
-fn has_name_tags(tags: &Tags) -> bool {
-    tags.iter()
-        .any(|(key, value)| is_name_key(key) && !value.is_empty())
-}
-
-fn is_place_node(tags: &Tags) -> bool {
-    let place = tags.get("place").map(|value| value.as_str()).unwrap_or("");
-    let has_name = has_name_tags(tags);
-    has_name
-        && matches!(
-            place,
-            "city" | "town" | "village" | "hamlet" | "suburb" | "locality"
-        )
-}
-
-fn place_node_from_tags(tags: &Tags, coord: (f64, f64)) -> Option<PlaceNode> {
-    if !is_place_node(tags) {
-        return None;
-    }
-    let names = collect_name_variants(tags);
-    if names.is_empty() {
-        return None;
-    }
-    let place_type = tags.get("place")?.to_string();
-    let population = tags
-        .get("population")
-        .and_then(|value| parse_population(value));
-    Some(PlaceNode::new(names, place_type, coord, population))
-}
-
-fn is_city_or_town(place_type: &str) -> bool {
-    matches!(place_type, "city" | "town")
-}
-
-fn haversine_km(a: (f64, f64), b: (f64, f64)) -> f64 {
-    let (lon1, lat1) = a;
-    let (lon2, lat2) = b;
-    let r = 6371.0_f64;
-    let dlat = (lat2 - lat1).to_radians();
-    let dlon = (lon2 - lon1).to_radians();
-    let lat1 = lat1.to_radians();
-    let lat2 = lat2.to_radians();
-    let sin_dlat = (dlat / 2.0).sin();
-    let sin_dlon = (dlon / 2.0).sin();
-    let h = sin_dlat * sin_dlat + lat1.cos() * lat2.cos() * sin_dlon * sin_dlon;
-    2.0 * r * h.sqrt().asin()
-}
-
-fn path_length_km(coords: &[(f64, f64)]) -> f64 {
-    if coords.len() < 2 {
-        return 0.0;
-    }
-    let mut total = 0.0;
-    for i in 0..(coords.len() - 1) {
-        total += haversine_km(coords[i], coords[i + 1]);
-    }
-    total
-}
-
-const MAX_PLACE_DISTANCE_KM: f64 = 75.0;
-const EARTH_RADIUS_KM: f64 = 6371.0;
-
-#[derive(Copy, Clone)]
-enum PlaceFilter {
-    Any,
-    CityTown,
-}
-
-struct PlaceIndex {
-    places: Vec<PlaceNode>,
-    grid: HashMap<(i32, i32), Vec<usize>>,
-    cell_size_deg: f64,
-}
-
-impl PlaceIndex {
-    fn new(places: Vec<PlaceNode>, cell_size_deg: f64) -> Self {
-        let mut grid: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
-        for (idx, place) in places.iter().enumerate() {
-            let cell = Self::cell_for(place.coord, cell_size_deg);
-            grid.entry(cell).or_default().push(idx);
-        }
-        Self {
-            places,
-            grid,
-            cell_size_deg,
-        }
-    }
-
-    fn nearest(&self, point: (f64, f64), filter: PlaceFilter) -> Option<&PlaceNode> {
-        let (lon, lat) = point;
-        let lat_rad = lat.to_radians();
-        let lon_rad = lon.to_radians();
-        let cos_lat = lat_rad.cos().abs();
-        let delta_lat = MAX_PLACE_DISTANCE_KM / 111.0;
-        let delta_lon = if cos_lat < 1e-6 {
-            180.0
-        } else {
-            MAX_PLACE_DISTANCE_KM / (111.0 * cos_lat)
+const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+enum Command {
+    Extract {
+        input_paths: Vec<PathBuf>,
+        output: PathBuf,
+        options: ExtractOptions,
+    },
+    /// Print the output schema for the given options instead of extracting.
+    Schema(ExtractOptions),
+    /// Print tag frequencies of the kept features instead of writing rows.
+    TagStats {
+        input_paths: Vec<PathBuf>,
+        options: ExtractOptions,
+    },
+    /// Write a synthetic extract for benchmarking.
+    GenBench {
+        shape: SyntheticOsm,
+        output: PathBuf,
+    },
+    /// Compare two earlier outputs.
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        output: PathBuf,
+        move_threshold_km: f64,
+    },
+}
+
+/// Parses the (unlisted) `gen-bench` subcommand.
+fn parse_gen_bench_args(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut shape = SyntheticOsm {
+        nodes: 100_000,
+        ways: 10_000,
+        names: 1_000,
+    };
+    let mut output = PathBuf::from("bench.osm");
+    while let Some(arg) = args.next() {
+        let mut count = |target: &mut usize| -> Result<()> {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{arg} requires a count"))?;
+            *target = value
+                .parse()
+                .map_err(|_| format!("invalid {arg}: {value}"))?;
+            Ok(())
         };
-
-        let min_cell = Self::cell_for((lon - delta_lon, lat - delta_lat), self.cell_size_deg);
-        let max_cell = Self::cell_for((lon + delta_lon, lat + delta_lat), self.cell_size_deg);
-        let mut best: Option<(&PlaceNode, f64)> = None;
-        for x in min_cell.0..=max_cell.0 {
-            for y in min_cell.1..=max_cell.1 {
-                let Some(bucket) = self.grid.get(&(x, y)) else {
-                    continue;
-                };
-                for &idx in bucket {
-                    let place = &self.places[idx];
-                    if matches!(filter, PlaceFilter::CityTown) && !place.is_city_town {
-                        continue;
-                    }
-                    let distance = equirectangular_km(
-                        lon_rad,
-                        lat_rad,
-                        cos_lat,
-                        place.lon_rad,
-                        place.lat_rad,
-                        place.cos_lat,
-                    );
-                    if distance > MAX_PLACE_DISTANCE_KM {
-                        continue;
-                    }
-                    match best {
-                        None => best = Some((place, distance)),
-                        Some((_, best_distance)) if distance < best_distance => {
-                            best = Some((place, distance))
-                        }
-                        _ => {}
-                    }
-                }
+        match arg.as_str() {
+            "--nodes" => count(&mut shape.nodes)?,
+            "--ways" => count(&mut shape.ways)?,
+            "--names" => count(&mut shape.names)?,
+            "--output" => {
+                output = args
+                    .next()
+                    .ok_or("--output requires a path")
+                    .map(PathBuf::from)?;
             }
+            _ => return Err(format!("unknown gen-bench argument: {arg}").into()),
         }
-        best.map(|(place, _)| place)
-    }
-
-    fn cell_for(coord: (f64, f64), cell_size_deg: f64) -> (i32, i32) {
-        let x = (coord.0 / cell_size_deg).floor() as i32;
-        let y = (coord.1 / cell_size_deg).floor() as i32;
-        (x, y)
     }
+    Ok(Command::GenBench { shape, output })
 }
 
-fn equirectangular_km(
-    lon1: f64,
-    lat1: f64,
-    cos_lat1: f64,
-    lon2: f64,
-    lat2: f64,
-    cos_lat2: f64,
-) -> f64 {
-    let avg_cos = (cos_lat1 + cos_lat2) * 0.5;
-    let x = (lon2 - lon1) * avg_cos;
-    let y = lat2 - lat1;
-    EARTH_RADIUS_KM * (x * x + y * y).sqrt()
-}
-
-fn is_in_city(tags: &Tags) -> Option<String> {
-    for key in ["is_in:city", "is_in:town", "is_in:municipality", "is_in:locality"] {
-        if let Some(value) = tags.get(key) {
-            if !value.is_empty() {
-                return Some(value.to_string());
+fn parse_diff_args(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut old = None;
+    let mut new = None;
+    let mut output = PathBuf::from("changes.csv");
+    let mut move_threshold_km = DEFAULT_MOVE_THRESHOLD_KM;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--old" => old = Some(args.next().ok_or("--old requires a path")?),
+            "--new" => new = Some(args.next().ok_or("--new requires a path")?),
+            "--output" => {
+                output = args
+                    .next()
+                    .ok_or("--output requires a path")
+                    .map(PathBuf::from)?;
             }
-        }
-    }
-    if let Some(value) = tags.get("is_in") {
-        let parts: Vec<&str> = value.split(',').map(|part| part.trim()).collect();
-        if let Some(first) = parts.first() {
-            if !first.is_empty() {
-                return Some(first.to_string());
+            "--move-threshold" => {
+                let value = args
+                    .next()
+                    .ok_or("--move-threshold requires a distance in km")?;
+                match value.parse::<f64>() {
+                    Ok(km) if km >= 0.0 && km.is_finite() => move_threshold_km = km,
+                    _ => return Err(format!("invalid --move-threshold: {value}").into()),
+                }
             }
-        }
-    }
-    None
-}
-
-fn resolve_first_non_empty(values: &[Option<&str>]) -> Option<String> {
-    values
-        .iter()
-        .find_map(|value| value.and_then(|text| if text.is_empty() { None } else { Some(text) }))
-        .map(|value| value.to_string())
-}
-
-fn has_tag_value(tags: &Tags, key: &str, values: &[&str]) -> bool {
-    tags.get(key)
-        .map(|value| values.contains(&value.as_str()))
-        .unwrap_or(false)
-}
-
-fn has_tag(tags: &Tags, key: &str) -> bool {
-    tags.get(key).map(|value| !value.is_empty()).unwrap_or(false)
-}
-
-fn is_airport(tags: &Tags) -> bool {
-    has_tag_value(tags, "aeroway", &["aerodrome", "airport", "terminal"])
-}
-
-fn is_train_station(tags: &Tags) -> bool {
-    has_tag_value(tags, "railway", &["station", "halt"])
-        || has_tag_value(tags, "public_transport", &["station"])
-}
-
-fn is_bus_stop(tags: &Tags) -> bool {
-    has_tag_value(tags, "highway", &["bus_stop"])
-        || has_tag_value(tags, "public_transport", &["platform", "stop_position"])
-}
-
-fn is_ferry_terminal(tags: &Tags) -> bool {
-    has_tag_value(tags, "amenity", &["ferry_terminal"])
-        || has_tag_value(tags, "seamark:type", &["ferry_terminal"])
-}
-
-fn is_university(tags: &Tags) -> bool {
-    has_tag_value(tags, "amenity", &["university", "college"])
-        || has_tag_value(tags, "education", &["university", "college"])
-        || has_tag_value(tags, "building", &["university", "college"])
-}
-
-fn is_museum(tags: &Tags) -> bool {
-    has_tag_value(tags, "tourism", &["museum"])
-        || has_tag_value(tags, "amenity", &["museum"])
-        || has_tag_value(tags, "building", &["museum"])
-}
-
-fn is_civic_building(tags: &Tags) -> bool {
-    has_tag_value(
-        tags,
-        "amenity",
-        &[
-            "library",
-            "theatre",
-            "arts_centre",
-            "community_centre",
-            "townhall",
-            "courthouse",
-            "public_building",
-        ],
-    ) || has_tag_value(
-        tags,
-        "building",
-        &[
-            "library",
-            "theatre",
-            "arts_centre",
-            "community_centre",
-            "townhall",
-            "courthouse",
-            "public",
-        ],
-    ) || has_tag_value(tags, "tourism", &["gallery"])
-        || has_tag_value(tags, "office", &["government"])
-}
-
-fn is_major_sight(tags: &Tags) -> bool {
-    if !has_name_tags(tags) {
-        return false;
-    }
-    let has_wiki = tags.contains_key("wikipedia") || tags.contains_key("wikidata");
-    if !has_wiki {
-        return false;
-    }
-    let has_tourism = has_tag(tags, "tourism");
-    let has_historic = has_tag(tags, "historic");
-    let has_man_made = has_tag(tags, "man_made");
-    let has_landmark = has_tag(tags, "landmark");
-    let has_tower = has_tag(tags, "tower:type");
-    has_tourism || has_historic || has_man_made || has_landmark || has_tower
-}
-
-fn poi_kind(tags: &Tags) -> Option<&'static str> {
-    if !has_name_tags(tags) {
-        return None;
-    }
-    if is_airport(tags) {
-        return Some("airport");
-    }
-    if is_train_station(tags) {
-        return Some("train_station");
-    }
-    if is_bus_stop(tags) {
-        return Some("bus_stop");
-    }
-    if is_ferry_terminal(tags) {
-        return Some("ferry_terminal");
-    }
-    if is_university(tags) {
-        return Some("university");
-    }
-    if is_museum(tags) {
-        return Some("museum");
-    }
-    if is_civic_building(tags) {
-        return Some("civic_building");
-    }
-    if is_major_sight(tags) {
-        return Some("sight");
-    }
-    None
-}
-
-fn is_poi(tags: &Tags) -> bool {
-    poi_kind(tags).is_some()
-}
-
-fn resolve_city_fields(
-    tags: &Tags,
-    center: (f64, f64),
-    place_index: &PlaceIndex,
-) -> (String, String, String, String, String) {
-    let city_addr = tags.get("addr:city");
-    let city_place = tags.get("addr:place");
-    let city = city_addr.or(city_place);
-    let city_boundary: Option<String> = None;
-    let place_match = place_index.nearest(center, PlaceFilter::Any);
-    let city_place_node = place_match.map(|place| place.name.clone());
-    let city_place_type = place_match.map(|place| place.place_type.clone());
-    let city_place_city_node = match place_match {
-        Some(place) if is_city_or_town(&place.place_type) => Some(place),
-        Some(_) => place_index.nearest(center, PlaceFilter::CityTown),
-        None => None,
-    };
-    let city_place_city = city_place_city_node.map(|place| place.name.clone());
-    let city_is_in = is_in_city(tags);
-    let city_resolved = resolve_first_non_empty(&[
-        city.map(|value| value.as_str()),
-        city_boundary.as_deref(),
-        city_is_in.as_deref(),
-        city_place_city.as_deref(),
-        city_place_node.as_deref(),
-    ]);
-    let city_population = city_place_city_node
-        .and_then(|place| place.population)
-        .or_else(|| place_match.and_then(|place| place.population))
-        .map(|value: u64| value.to_string())
-        .unwrap_or_default();
-    (
-        city_place_node.unwrap_or_default(),
-        city_place_type.unwrap_or_default(),
-        city_place_city.unwrap_or_default(),
-        city_resolved.unwrap_or_default(),
-        city_population,
-    )
-}
-
-fn city_fields_for_place(
-    place: &PlaceNode,
-    place_index: &PlaceIndex,
-) -> (String, String, String, String, String) {
-    let city_place_node = place.name.clone();
-    let city_place_type = place.place_type.clone();
-    let city_place_city_node = if place.is_city_town {
-        Some(place)
-    } else {
-        place_index.nearest(place.coord, PlaceFilter::CityTown)
-    };
-    let city_place_city = city_place_city_node
-        .map(|city| city.name.clone())
-        .unwrap_or_default();
-    let city_resolved = if !city_place_city.is_empty() {
-        city_place_city.clone()
-    } else {
-        city_place_node.clone()
-    };
-    let city_population = city_place_city_node
-        .and_then(|city| city.population)
-        .or(place.population)
-        .map(|value| value.to_string())
-        .unwrap_or_default();
-    (
-        city_place_node,
-        city_place_type,
-        city_place_city,
-        city_resolved,
-        city_population,
-    )
-}
-
-fn add_place_entries(
-    place_nodes: &[PlaceNode],
-    place_index: &PlaceIndex,
-    entries: &mut Vec<StreetEntry>,
-) {
-    for place in place_nodes {
-        let (city_place_node, city_place_type, city_place_city, city_resolved, city_population) =
-            city_fields_for_place(place, place_index);
-        for name in &place.names {
-            entries.push(StreetEntry {
-                name: name.name.clone(),
-                name_lang: name.lang.clone(),
-                kind: "city".to_string(),
-                center_lon: place.coord.0,
-                center_lat: place.coord.1,
-                length_km: 0.0,
-                city_place_node: city_place_node.clone(),
-                city_place_type: city_place_type.clone(),
-                city_place_city: city_place_city.clone(),
-                city_resolved: city_resolved.clone(),
-                city_population: city_population.clone(),
-            });
-        }
-    }
-}
-
-
-fn collect_pbf_place_nodes(objs: &BTreeMap<OsmId, OsmObj>) -> Vec<PlaceNode> {
-    let mut places = Vec::new();
-    for obj in objs.values() {
-        if let OsmObj::Node(node) = obj {
-            if let Some(place) = place_node_from_tags(&node.tags, (node.lon(), node.lat())) {
-                places.push(place);
+            "-h" | "--help" => {
+                println!(
+                    "Usage: extract_street_polygons diff --old OLD.csv --new NEW.csv [--output changes.csv] [--move-threshold KM]\n"
+                );
+                println!(
+                    "Writes one row per added, removed or moved street (keyed on streetname, kind and city_resolved)."
+                );
+                println!(
+                    "--move-threshold KM  Report a street as moved once its center shifted more than KM. Defaults to {DEFAULT_MOVE_THRESHOLD_KM}."
+                );
+                std::process::exit(0);
             }
+            _ => return Err(format!("unknown diff argument: {arg}").into()),
         }
     }
-    places
-}
-
-#[derive(Default, Clone)]
-struct WayData {
-    id: Option<i64>,
-    node_refs: Vec<i64>,
-    tags: Tags,
-}
-
-#[derive(Clone)]
-struct PlaceNode {
-    name: String,
-    names: Vec<NameVariant>,
-    place_type: String,
-    coord: (f64, f64),
-    lat_rad: f64,
-    lon_rad: f64,
-    cos_lat: f64,
-    is_city_town: bool,
-    population: Option<u64>,
-}
-
-impl PlaceNode {
-    fn new(
-        mut names: Vec<NameVariant>,
-        place_type: String,
-        coord: (f64, f64),
-        population: Option<u64>,
-    ) -> Self {
-        if names.is_empty() {
-            names.push(NameVariant {
-                name: "".to_string(),
-                lang: "".to_string(),
-            });
-        }
-        let name = names[0].name.clone();
-        let lat_rad = coord.1.to_radians();
-        let lon_rad = coord.0.to_radians();
-        let cos_lat = lat_rad.cos();
-        let is_city_town = is_city_or_town(&place_type);
-        Self {
-            name,
-            names,
-            place_type,
-            coord,
-            lat_rad,
-            lon_rad,
-            cos_lat,
-            is_city_town,
-            population,
-        }
-    }
-}
-
-#[derive(Default)]
-struct NodeData {
-    id: Option<i64>,
-    coord: Option<(f64, f64)>,
-    tags: Tags,
-}
-
-#[derive(Clone)]
-struct StreetEntry {
-    name: String,
-    name_lang: String,
-    kind: String,
-    center_lon: f64,
-    center_lat: f64,
-    length_km: f64,
-    city_place_node: String,
-    city_place_type: String,
-    city_place_city: String,
-    city_resolved: String,
-    city_population: String,
-}
-
-const MERGE_DISTANCE_KM: f64 = 1.0;
-
-fn merge_city_key(entry: &StreetEntry) -> String {
-    if !entry.city_resolved.is_empty() {
-        return entry.city_resolved.clone();
-    }
-    if !entry.city_place_city.is_empty() {
-        return entry.city_place_city.clone();
-    }
-    if !entry.city_place_node.is_empty() {
-        return entry.city_place_node.clone();
-    }
-    String::new()
-}
-
-fn pick_mode(entries: &[StreetEntry], indices: &[usize], getter: fn(&StreetEntry) -> &str) -> String {
-    let mut counts: HashMap<String, usize> = HashMap::new();
-    for idx in indices {
-        let value = getter(&entries[*idx]);
-        if value.is_empty() {
-            continue;
-        }
-        *counts.entry(value.to_string()).or_insert(0) += 1;
-    }
-    counts
-        .into_iter()
-        .max_by_key(|(_, count)| *count)
-        .map(|(value, _)| value)
-        .unwrap_or_default()
+    Ok(Command::Diff {
+        old: old.ok_or("diff requires --old")?.into(),
+        new: new.ok_or("diff requires --new")?.into(),
+        output,
+        move_threshold_km,
+    })
 }
 
-fn merge_cluster(entries: &[StreetEntry], indices: &[usize]) -> StreetEntry {
-    let mut weighted_lon = 0.0;
-    let mut weighted_lat = 0.0;
-    let mut weight_sum = 0.0;
-    let mut length_sum = 0.0;
-
-    for idx in indices {
-        let entry = &entries[*idx];
-        let weight = if entry.length_km > 0.0 { entry.length_km } else { 1.0 };
-        weighted_lon += entry.center_lon * weight;
-        weighted_lat += entry.center_lat * weight;
-        weight_sum += weight;
-        length_sum += entry.length_km;
-    }
-
-    let center_lon = if weight_sum > 0.0 {
-        weighted_lon / weight_sum
-    } else {
-        entries[indices[0]].center_lon
-    };
-    let center_lat = if weight_sum > 0.0 {
-        weighted_lat / weight_sum
-    } else {
-        entries[indices[0]].center_lat
-    };
-
-    let name = entries[indices[0]].name.clone();
-    let name_lang = entries[indices[0]].name_lang.clone();
-    let kind = pick_mode(entries, indices, |e| e.kind.as_str());
-    let city_place_node = pick_mode(entries, indices, |e| e.city_place_node.as_str());
-    let city_place_type = pick_mode(entries, indices, |e| e.city_place_type.as_str());
-    let city_place_city = pick_mode(entries, indices, |e| e.city_place_city.as_str());
-    let city_resolved = pick_mode(entries, indices, |e| e.city_resolved.as_str());
-    let city_population = pick_mode(entries, indices, |e| e.city_population.as_str());
+fn parse_args() -> Result<Command> {
+    let mut inputs: Vec<PathBuf> = Vec::new();
+    let mut input_dir: Option<PathBuf> = None;
+    let mut output = PathBuf::from("street_polygons.csv");
+    let mut options = ExtractOptions::default();
+    let mut tag_stats = false;
+    let mut bbox_from: Option<PathBuf> = None;
 
-    StreetEntry {
-        name,
-        name_lang,
-        kind,
-        center_lon,
-        center_lat,
-        length_km: length_sum,
-        city_place_node,
-        city_place_type,
-        city_place_city,
-        city_resolved,
-        city_population,
+    let mut args = env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "diff").is_some() {
+        return parse_diff_args(args);
     }
-}
-
-fn merge_entries(entries: Vec<StreetEntry>) -> Vec<StreetEntry> {
-    let mut grouped: Vec<((String, String), Vec<StreetEntry>)> = Vec::new();
-    let mut index: HashMap<(String, String), usize> = HashMap::new();
-    for entry in entries {
-        let key = (
-            format!(
-                "{}\u{1f}{}\u{1f}{}",
-                entry.name, entry.name_lang, entry.kind
-            ),
-            merge_city_key(&entry),
-        );
-        if let Some(&position) = index.get(&key) {
-            grouped[position].1.push(entry);
-        } else {
-            index.insert(key.clone(), grouped.len());
-            grouped.push((key, vec![entry]));
-        }
+    if args.next_if(|arg| arg == "gen-bench").is_some() {
+        return parse_gen_bench_args(args);
     }
-
-    let mut merged = Vec::new();
-    for ((_, city_key), group) in grouped {
-        if city_key.is_empty() {
-            merged.extend(group.into_iter());
-            continue;
-        }
-        let mut remaining = vec![true; group.len()];
-        for i in 0..group.len() {
-            if !remaining[i] {
-                continue;
+    let schema = args.next_if(|arg| arg == "schema").is_some();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => {
+                inputs.push(
+                    args.next()
+                        .ok_or("--input requires a path")
+                        .map(PathBuf::from)?,
+                );
             }
-            remaining[i] = false;
-            let mut cluster = vec![i];
-            let mut queue = vec![i];
-
-            while let Some(idx) = queue.pop() {
-                let base = (group[idx].center_lon, group[idx].center_lat);
-                for j in 0..group.len() {
-                    if !remaining[j] {
-                        continue;
-                    }
-                    let other = (group[j].center_lon, group[j].center_lat);
-                    if haversine_km(base, other) <= MERGE_DISTANCE_KM {
-                        remaining[j] = false;
-                        queue.push(j);
-                        cluster.push(j);
-                    }
+            "--input-dir" => {
+                input_dir = Some(
+                    args.next()
+                        .ok_or("--input-dir requires a path")
+                        .map(PathBuf::from)?,
+                );
+            }
+            "--output" => {
+                output = args
+                    .next()
+                    .ok_or("--output requires a path")
+                    .map(PathBuf::from)?;
+            }
+            "--format" => {
+                let value = args.next().ok_or("--format requires a value")?;
+                options.format = OutputFormat::parse(&value)?;
+            }
+            "--pretty" => options.pretty = true,
+            "--crlf" => options.crlf = true,
+            "--gzip" => options.gzip = true,
+            "--with-metadata" => options.with_metadata = true,
+            "--include-poi-relations" => options.include_poi_relations = true,
+            "--length-unit" => {
+                let value = args.next().ok_or("--length-unit requires a value")?;
+                options.length_unit = Some(LengthUnit::parse(&value)?);
+            }
+            "--max-cluster-span" => {
+                let value = args.next().ok_or("--max-cluster-span requires a value")?;
+                match value.parse::<f64>() {
+                    Ok(km) if km >= 0.0 => options.max_cluster_span_km = Some(km),
+                    _ => return Err(format!("invalid --max-cluster-span: {value}").into()),
                 }
             }
-
-            merged.push(merge_cluster(&group, &cluster));
-        }
-    }
-
-    merged
-}
-
-fn get_attr_value(event: &BytesStart<'_>, key: &[u8]) -> Result<Option<String>> {
-    for attr in event.attributes().with_checks(false) {
-        let attr = attr?;
-        if attr.key.as_ref() == key {
-            return Ok(Some(attr.unescape_value()?.to_string()));
-        }
-    }
-    Ok(None)
-}
-
-fn extract_osm_xml_to_writer(input_path: &Path, writer: &mut Writer<File>) -> Result<()> {
-    let file = File::open(input_path)?;
-    let mut reader = Reader::from_reader(BufReader::new(file));
-    reader.trim_text(true);
-
-    let mut nodes: HashMap<i64, (f64, f64)> = HashMap::new();
-    let mut ways: Vec<WayData> = Vec::new();
-    let mut place_nodes: Vec<PlaceNode> = Vec::new();
-    let mut poi_nodes: Vec<NodeData> = Vec::new();
-    let mut current_node: Option<NodeData> = None;
-    let mut current_way: Option<WayData> = None;
-    let mut buf = Vec::new();
-
-    loop {
-        match reader.read_event_into(&mut buf)? {
-            Event::Eof => break,
-            Event::Start(e) => {
-                let name = e.name().as_ref().to_vec();
-                match name.as_slice() {
-                    b"node" => {
-                        let id = get_attr_value(&e, b"id")?
-                            .and_then(|value| value.parse::<i64>().ok());
-                        let lat = get_attr_value(&e, b"lat")?
-                            .and_then(|value| value.parse::<f64>().ok());
-                        let lon = get_attr_value(&e, b"lon")?
-                            .and_then(|value| value.parse::<f64>().ok());
-                        if id.is_some() && lat.is_some() && lon.is_some() {
-                            current_node = Some(NodeData {
-                                id,
-                                coord: Some((lon.unwrap(), lat.unwrap())),
-                                tags: Tags::new(),
-                            });
-                        }
-                    }
-                    b"way" => {
-                        let id = get_attr_value(&e, b"id")?
-                            .and_then(|value| value.parse::<i64>().ok());
-                        current_way = Some(WayData {
-                            id,
-                            ..WayData::default()
-                        });
-                    }
-                    b"nd" => {
-                        if let Some(way) = current_way.as_mut() {
-                            if let Some(reference) = get_attr_value(&e, b"ref")?
-                                .and_then(|value| value.parse::<i64>().ok())
-                            {
-                                way.node_refs.push(reference);
-                            }
-                        }
-                    }
-                    b"tag" => {
-                        if let Some(way) = current_way.as_mut() {
-                            let key = get_attr_value(&e, b"k")?;
-                            let value = get_attr_value(&e, b"v")?;
-                            if let (Some(key), Some(value)) = (key, value) {
-                                way.tags.insert(key.into(), value.into());
-                            }
-                        }
-                        if let Some(node) = current_node.as_mut() {
-                            let key = get_attr_value(&e, b"k")?;
-                            let value = get_attr_value(&e, b"v")?;
-                            if let (Some(key), Some(value)) = (key, value) {
-                                node.tags.insert(key.into(), value.into());
-                            }
-                        }
-                    }
-                    _ => {}
+            "--dedup-distance" | "--drop-duplicate-names-within-distance" => {
+                let value = args
+                    .next()
+                    .ok_or(format!("{arg} requires a distance in km"))?;
+                match value.parse::<f64>() {
+                    Ok(km) if km > 0.0 && km.is_finite() => options.dedup_distance_km = Some(km),
+                    _ => return Err(format!("invalid {arg}: {value}").into()),
                 }
             }
-            Event::Empty(e) => {
-                let name = e.name().as_ref().to_vec();
-                match name.as_slice() {
-                    b"node" => {
-                        let id = get_attr_value(&e, b"id")?
-                            .and_then(|value| value.parse::<i64>().ok());
-                        let lat = get_attr_value(&e, b"lat")?
-                            .and_then(|value| value.parse::<f64>().ok());
-                        let lon = get_attr_value(&e, b"lon")?
-                            .and_then(|value| value.parse::<f64>().ok());
-                        if let (Some(id), Some(lat), Some(lon)) = (id, lat, lon) {
-                            nodes.insert(id, (lon, lat));
-                        }
-                    }
-                    b"way" => {
-                        let id = get_attr_value(&e, b"id")?
-                            .and_then(|value| value.parse::<i64>().ok());
-                        let way = WayData {
-                            id,
-                            ..WayData::default()
-                        };
-                        ways.push(way);
-                    }
-                    b"nd" => {
-                        if let Some(way) = current_way.as_mut() {
-                            if let Some(reference) = get_attr_value(&e, b"ref")?
-                                .and_then(|value| value.parse::<i64>().ok())
-                            {
-                                way.node_refs.push(reference);
-                            }
-                        }
+            "--max-rows-per-name" => {
+                let value = args.next().ok_or("--max-rows-per-name requires a count")?;
+                match value.parse::<usize>() {
+                    Ok(max) if max > 0 => options.max_rows_per_name = Some(max),
+                    _ => return Err(format!("invalid --max-rows-per-name: {value}").into()),
+                }
+            }
+            "--sample-rate" => {
+                let value = args.next().ok_or("--sample-rate requires a fraction")?;
+                match value.parse::<f64>() {
+                    Ok(rate) if (0.0..=1.0).contains(&rate) => options.sample_rate = Some(rate),
+                    _ => {
+                        return Err(
+                            format!("invalid --sample-rate (expected 0.0-1.0): {value}").into()
+                        )
                     }
-                    b"tag" => {
-                        if let Some(way) = current_way.as_mut() {
-                            let key = get_attr_value(&e, b"k")?;
-                            let value = get_attr_value(&e, b"v")?;
-                            if let (Some(key), Some(value)) = (key, value) {
-                                way.tags.insert(key.into(), value.into());
-                            }
-                        }
-                        if let Some(node) = current_node.as_mut() {
-                            let key = get_attr_value(&e, b"k")?;
-                            let value = get_attr_value(&e, b"v")?;
-                            if let (Some(key), Some(value)) = (key, value) {
-                                node.tags.insert(key.into(), value.into());
-                            }
-                        }
+                }
+            }
+            "--center-on-geometry" => options.center_on_geometry = true,
+            "--drop-zero-length" => options.drop_zero_length = true,
+            "--max-nodes-per-way" => {
+                let value = args.next().ok_or("--max-nodes-per-way requires a value")?;
+                match value.parse::<usize>() {
+                    Ok(max) if max > 0 => options.max_nodes_per_way = Some(max),
+                    _ => return Err(format!("invalid --max-nodes-per-way: {value}").into()),
+                }
+            }
+            "--min-area-m2" => {
+                let value = args.next().ok_or("--min-area-m2 requires a value")?;
+                match value.parse::<f64>() {
+                    Ok(area) if area >= 0.0 && area.is_finite() => options.min_area_m2 = Some(area),
+                    _ => return Err(format!("invalid --min-area-m2: {value}").into()),
+                }
+            }
+            "--poi-length" => {
+                let value = args.next().ok_or("--poi-length requires a value")?;
+                options.poi_length = PoiLength::parse(&value)?;
+            }
+            "--merge-case-insensitive" => options.merge_case_insensitive = true,
+            "--merge-accent-insensitive" => options.merge_accent_insensitive = true,
+            "--merge-weight" => {
+                let value = args.next().ok_or("--merge-weight requires a value")?;
+                options.merge_weight = MergeWeight::parse(&value)?;
+            }
+            "--after-date" => {
+                let value = args.next().ok_or("--after-date requires a date")?;
+                let date = parse_date(&value).ok_or_else(|| {
+                    format!("invalid --after-date (expected YYYY-MM-DD): {value}")
+                })?;
+                options.after_date = Some(date);
+            }
+            "--drop-undated" => options.drop_undated = true,
+            "--keep-noname" => options.keep_noname = true,
+            "--fail-on-empty" => options.fail_on_empty = true,
+            "--strict" => options.strict = true,
+            "--no-split-names" => options.no_split_names = true,
+            "--raw-names" => options.raw_names = true,
+            "--with-ascii-name" => options.ascii_name = true,
+            "--include-proposed" => options.include_proposed = true,
+            "--with-surface" => options.with_surface = true,
+            "--with-is-in" => options.is_in_hierarchy = true,
+            "--dedup-poi-in-building" => options.dedup_poi_in_building = true,
+            "--include-disused" => options.include_disused = true,
+            "--include-natural" => options.include_natural = true,
+            "--include-named-buildings" => options.include_named_buildings = true,
+            "--named-buildings-need-wiki" => options.named_buildings_need_wiki = true,
+            "--only-with-wiki" => options.only_with_wiki = true,
+            "--with-place-coords" => options.place_coords = true,
+            "--with-suburb" => options.suburb = true,
+            "--with-level" => options.with_level = true,
+            "--with-ele" => options.with_ele = true,
+            "--with-operator-brand" => options.with_operator_brand = true,
+            "--with-merged-from" => options.with_merged_from = true,
+            "--use-destination" => options.use_destination = true,
+            "--with-sinuosity" => options.with_sinuosity = true,
+            "--with-city-confidence" => options.city_confidence = true,
+            "--tag-stats" => tag_stats = true,
+            "--crs" => {
+                let value = args.next().ok_or("--crs requires 4326 or 3857")?;
+                options.crs = Crs::parse(&value)?;
+            }
+            "--name-keys" => {
+                let value = args.next().ok_or("--name-keys requires a list of keys")?;
+                options.name_keys = parse_name_keys(&value)?;
+            }
+            "--mmap" => options.mmap = true,
+            "--aliases" => options.group_aliases = true,
+            "--tolerant-numbers" => options.tolerant_numbers = true,
+            "--detect-swapped-coords" => options.detect_swapped_coords = true,
+            "--compact-coords" => options.compact_coords = true,
+            "--progress" => {
+                options.progress = options.progress.or(Some(DEFAULT_PROGRESS_INTERVAL));
+            }
+            "--progress-interval" => {
+                let value = args.next().ok_or("--progress-interval requires seconds")?;
+                match value.parse::<f64>() {
+                    Ok(secs) if secs > 0.0 && secs.is_finite() => {
+                        options.progress = Some(Duration::from_secs_f64(secs));
                     }
-                    _ => {}
+                    _ => return Err(format!("invalid --progress-interval: {value}").into()),
                 }
             }
-            Event::End(e) => {
-                if e.name().as_ref() == b"node" {
-                    if let Some(node) = current_node.take() {
-                        if let (Some(id), Some(coord)) = (node.id, node.coord) {
-                            nodes.insert(id, coord);
-                            if let Some(place_node) = place_node_from_tags(&node.tags, coord) {
-                                place_nodes.push(place_node);
-                            }
-                            if is_poi(&node.tags) {
-                                poi_nodes.push(node);
-                            }
-                        }
+            "--timeout" => {
+                let value = args.next().ok_or("--timeout requires seconds")?;
+                match value.parse::<f64>() {
+                    Ok(secs) if secs > 0.0 && secs.is_finite() => {
+                        options.timeout = Some(Duration::from_secs_f64(secs));
                     }
-                } else if e.name().as_ref() == b"way" {
-                    if let Some(way) = current_way.take() {
-                        ways.push(way);
+                    _ => return Err(format!("invalid --timeout: {value}").into()),
+                }
+            }
+            "--with-geohash" => {
+                let value = args.next().ok_or("--with-geohash requires a length")?;
+                match value.parse::<usize>() {
+                    Ok(len) if (1..=MAX_GEOHASH_LEN).contains(&len) => {
+                        options.geohash_len = Some(len);
                     }
+                    _ => return Err(format!("invalid --with-geohash: {value}").into()),
                 }
             }
-            _ => {}
-        }
-        buf.clear();
-    }
-
-    let place_index = PlaceIndex::new(place_nodes, 1.0);
-    let mut entries: Vec<StreetEntry> = Vec::new();
-    add_place_entries(&place_index.places, &place_index, &mut entries);
-    for node in poi_nodes {
-        let coord = match node.coord {
-            Some(coord) => coord,
-            None => continue,
-        };
-        let kind = match poi_kind(&node.tags) {
-            Some(kind) => kind,
-            None => continue,
-        };
-        let names = collect_name_variants(&node.tags);
-        if names.is_empty() {
-            continue;
-        }
-        let (city_place_node, city_place_type, city_place_city, city_resolved, city_population) =
-            resolve_city_fields(&node.tags, coord, &place_index);
-        for name in names {
-            entries.push(StreetEntry {
-                name: name.name,
-                name_lang: name.lang,
-                kind: kind.to_string(),
-                center_lon: coord.0,
-                center_lat: coord.1,
-                length_km: 0.0,
-                city_place_node: city_place_node.clone(),
-                city_place_type: city_place_type.clone(),
-                city_place_city: city_place_city.clone(),
-                city_resolved: city_resolved.clone(),
-                city_population: city_population.clone(),
-            });
-        }
-    }
-    for way in ways {
-        let is_street = way.tags.contains_key("highway") && has_name_tags(&way.tags);
-        let is_poi_way = is_poi(&way.tags);
-        if !is_street && !is_poi_way {
-            continue;
-        }
-        let kind = if is_street {
-            "street"
-        } else {
-            match poi_kind(&way.tags) {
-                Some(kind) => kind,
-                None => continue,
+            "--hamlet-radius" | "--town-radius" => {
+                let value = args
+                    .next()
+                    .ok_or(format!("{arg} requires a distance in km"))?;
+                let km = match value.parse::<f64>() {
+                    Ok(km) if km >= 0.0 && km.is_finite() => km,
+                    _ => return Err(format!("invalid {arg}: {value}").into()),
+                };
+                if arg == "--hamlet-radius" {
+                    options.place_radii.hamlet_km = km;
+                } else {
+                    options.place_radii.town_km = km;
+                }
             }
-        };
-
-        let names = collect_name_variants(&way.tags);
-        if names.is_empty() {
-            continue;
-        }
-
-        let mut coords = Vec::with_capacity(way.node_refs.len());
-        let mut valid = true;
-        for node_id in &way.node_refs {
-            if let Some(coord) = nodes.get(node_id) {
-                coords.push(*coord);
-            } else {
-                valid = false;
-                break;
+            "--schema-comment" => options.schema_comment = true,
+            "--checkpoint" => {
+                let path = args.next().ok_or("--checkpoint requires a path")?;
+                options.checkpoint = Some(PathBuf::from(path));
             }
-        }
-        if !valid {
-            continue;
-        }
-
-        let is_closed = way.node_refs.len() >= 2 && way.node_refs.first() == way.node_refs.last();
-        let (center_lon, center_lat) = if is_closed {
-            if coords.len() < 4 {
-                continue;
+            "--name-include" | "--name-exclude" => {
+                let value = args.next().ok_or(format!("{arg} requires a regex"))?;
+                let pattern = Regex::new(&value).map_err(|err| format!("invalid {arg}: {err}"))?;
+                if arg == "--name-include" {
+                    options.name_include = Some(pattern);
+                } else {
+                    options.name_exclude = Some(pattern);
+                }
             }
-            match polygon_centroid(&coords) {
-                Ok(value) => value,
-                Err(_) => continue,
+            "--header-map" => {
+                let value = args.next().ok_or("--header-map requires old=new pairs")?;
+                options.header_map = parse_header_map(&value)?;
             }
-        } else {
-            if coords.len() < 2 {
-                continue;
+            "--columns" => {
+                let value = args.next().ok_or("--columns requires a list of columns")?;
+                options.columns = parse_columns(&value)?;
             }
-            match line_midpoint(&coords) {
-                Ok(value) => value,
-                Err(_) => continue,
+            "--extra-tags" => {
+                let value = args.next().ok_or("--extra-tags requires a list of keys")?;
+                options.extra_tags = parse_extra_tags(&value)?;
             }
-        };
-
-        let (city_place_node, city_place_type, city_place_city, city_resolved, city_population) =
-            resolve_city_fields(&way.tags, (center_lon, center_lat), &place_index);
-        let length_km = if is_street { path_length_km(&coords) } else { 0.0 };
-        for name in names {
-            entries.push(StreetEntry {
-                name: name.name,
-                name_lang: name.lang,
-                kind: kind.to_string(),
-                center_lon,
-                center_lat,
-                length_km,
-                city_place_node: city_place_node.clone(),
-                city_place_type: city_place_type.clone(),
-                city_place_city: city_place_city.clone(),
-                city_resolved: city_resolved.clone(),
-                city_population: city_population.clone(),
-            });
-        }
-    }
-
-    for entry in merge_entries(entries) {
-        writer.write_record([
-            entry.name,
-            entry.name_lang,
-            entry.kind,
-            format!("{}", entry.center_lon),
-            format!("{}", entry.center_lat),
-            entry.city_place_node,
-            entry.city_place_type,
-            entry.city_place_city,
-            entry.city_resolved,
-            entry.city_population,
-        ])?;
-    }
-
-    Ok(())
-}
-
-fn find_default_pbf(folder: &Path) -> Result<PathBuf> {
-    let mut pbfs = Vec::new();
-    for entry in folder.read_dir()? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.extension().and_then(|ext| ext.to_str()) == Some("pbf") {
-            pbfs.push(path);
-        }
-    }
-    pbfs.sort();
-
-    match pbfs.len() {
-        0 => Err("no .pbf files found in current directory".into()),
-        1 => Ok(pbfs.remove(0)),
-        _ => Err("multiple .pbf files found; pass --input explicitly".into()),
-    }
-}
-
-fn extract_pbf_to_writer(input_path: &Path, writer: &mut Writer<File>) -> Result<()> {
-    let file = File::open(input_path)?;
-    let mut pbf = OsmPbfReader::new(file);
-
-    let objs = pbf.get_objs_and_deps(|obj| match obj {
-        OsmObj::Way(w) => {
-            (w.tags.contains_key("highway") && has_name_tags(&w.tags)) || is_poi(&w.tags)
-        }
-        OsmObj::Node(n) => is_place_node(&n.tags) || is_poi(&n.tags),
-        OsmObj::Relation(_) => false,
-    })?;
-    let place_nodes = collect_pbf_place_nodes(&objs);
-    let place_index = PlaceIndex::new(place_nodes, 1.0);
-
-    let mut entries: Vec<StreetEntry> = Vec::new();
-    add_place_entries(&place_index.places, &place_index, &mut entries);
-    for obj in objs.values() {
-        match obj {
-            OsmObj::Way(way) => {
-                let is_street = way.tags.contains_key("highway") && has_name_tags(&way.tags);
-                let is_poi_way = is_poi(&way.tags);
-                if !is_street && !is_poi_way {
-                    continue;
-                }
-                let kind = if is_street {
-                    "street"
-                } else {
-                    match poi_kind(&way.tags) {
-                        Some(kind) => kind,
-                        None => continue,
-                    }
-                };
-
-                let names = collect_name_variants(&way.tags);
-                if names.is_empty() {
-                    continue;
-                }
-
-                let mut coords = Vec::with_capacity(way.nodes.len());
-                let mut valid = true;
-                for node_id in &way.nodes {
-                    match objs.get(&OsmId::Node(node_id.clone())) {
-                        Some(OsmObj::Node(node)) => {
-                            coords.push((node.lon(), node.lat()));
-                        }
-                        _ => {
-                            valid = false;
-                            break;
-                        }
-                    }
-                }
-                if !valid {
-                    continue;
-                }
-
-                let is_closed = way.nodes.len() >= 2 && way.nodes.first() == way.nodes.last();
-                let (center_lon, center_lat) = if is_closed {
-                    if coords.len() < 4 {
-                        continue;
-                    }
-                    match polygon_centroid(&coords) {
-                        Ok(value) => value,
-                        Err(_) => continue,
-                    }
-                } else {
-                    if coords.len() < 2 {
-                        continue;
-                    }
-                    match line_midpoint(&coords) {
-                        Ok(value) => value,
-                        Err(_) => continue,
+            "--bbox" => {
+                let value = args.next().ok_or("--bbox requires a value")?;
+                options.bbox = Some(BoundingBox::parse(&value)?);
+            }
+            "--bbox-from" => {
+                let path = args.next().ok_or("--bbox-from requires a path")?;
+                bbox_from = Some(PathBuf::from(path));
+            }
+            "--exclude-bbox" => {
+                let value = args.next().ok_or("--exclude-bbox requires a value")?;
+                options.exclude_bboxes.push(BoundingBox::parse(&value)?);
+            }
+            "--country-polygons" => {
+                let path = args.next().ok_or("--country-polygons requires a path")?;
+                options.countries = Some(CountryPolygons::load(Path::new(&path))?);
+            }
+            "--boundaries-output" => {
+                let path = args.next().ok_or("--boundaries-output requires a path")?;
+                options.boundaries_output = Some(PathBuf::from(path));
+            }
+            "--prefer-places-in-boundary" => options.prefer_places_in_boundary = true,
+            "--places-output" => {
+                let path = args.next().ok_or("--places-output requires a path")?;
+                options.places_output = Some(PathBuf::from(path));
+            }
+            "--min-place-rank" => {
+                let value = args
+                    .next()
+                    .ok_or("--min-place-rank requires a place type")?;
+                options.min_place_rank = PlaceRank::parse(&value)?;
+            }
+            "--include-places" => options.include_places = true,
+            "--place-filter" => {
+                let value = args.next().ok_or("--place-filter requires place types")?;
+                options.place_filter = parse_place_filter(&value)?;
+            }
+            "--rename-map" => {
+                let path = args.next().ok_or("--rename-map requires a path")?;
+                options.rename_map = Some(RenameMap::load(Path::new(&path))?);
+            }
+            "--clip" => {
+                let path = args.next().ok_or("--clip requires a path")?;
+                options.clip = Some(ClipPolygon::load(Path::new(&path))?);
+            }
+            "--snap-precision" => {
+                let value = args.next().ok_or("--snap-precision requires a value")?;
+                match value.parse::<u32>() {
+                    Ok(places) if places <= 15 => options.snap = Some(CoordSnap::Decimals(places)),
+                    _ => {
+                        return Err(
+                            format!("invalid --snap-precision (expected 0-15): {value}").into()
+                        )
                     }
-                };
-
-                let (city_place_node, city_place_type, city_place_city, city_resolved, city_population) =
-                    resolve_city_fields(&way.tags, (center_lon, center_lat), &place_index);
-                let length_km = if is_street { path_length_km(&coords) } else { 0.0 };
-                for name in names {
-                    entries.push(StreetEntry {
-                        name: name.name,
-                        name_lang: name.lang,
-                        kind: kind.to_string(),
-                        center_lon,
-                        center_lat,
-                        length_km,
-                        city_place_node: city_place_node.clone(),
-                        city_place_type: city_place_type.clone(),
-                        city_place_city: city_place_city.clone(),
-                        city_resolved: city_resolved.clone(),
-                        city_population: city_population.clone(),
-                    });
                 }
             }
-            OsmObj::Node(node) => {
-                let kind = match poi_kind(&node.tags) {
-                    Some(kind) => kind,
-                    None => continue,
-                };
-                let names = collect_name_variants(&node.tags);
-                if names.is_empty() {
-                    continue;
-                }
-                let center = (node.lon(), node.lat());
-                let (city_place_node, city_place_type, city_place_city, city_resolved, city_population) =
-                    resolve_city_fields(&node.tags, center, &place_index);
-                for name in names {
-                    entries.push(StreetEntry {
-                        name: name.name,
-                        name_lang: name.lang,
-                        kind: kind.to_string(),
-                        center_lon: center.0,
-                        center_lat: center.1,
-                        length_km: 0.0,
-                        city_place_node: city_place_node.clone(),
-                        city_place_type: city_place_type.clone(),
-                        city_place_city: city_place_city.clone(),
-                        city_resolved: city_resolved.clone(),
-                        city_population: city_population.clone(),
-                    });
+            "--snap-meters" => {
+                let value = args.next().ok_or("--snap-meters requires a value")?;
+                match value.parse::<f64>() {
+                    Ok(meters) if meters > 0.0 && meters.is_finite() => {
+                        options.snap = Some(CoordSnap::Meters(meters))
+                    }
+                    _ => return Err(format!("invalid --snap-meters: {value}").into()),
                 }
             }
-            _ => {}
-        }
-    }
-
-    for entry in merge_entries(entries) {
-        writer.write_record([
-            entry.name,
-            entry.name_lang,
-            entry.kind,
-            format!("{:.7}", entry.center_lon),
-            format!("{:.7}", entry.center_lat),
-            entry.city_place_node,
-            entry.city_place_type,
-            entry.city_place_city,
-            entry.city_resolved,
-            entry.city_population,
-        ])?;
-    }
-
-    Ok(())
-}
-
-const CSV_HEADERS: [&str; 10] = [
-    "streetname",
-    "name_lang",
-    "kind",
-    "center_lon",
-    "center_lat",
-    "city_place_node",
-    "city_place_type",
-    "city_place_city",
-    "city_resolved",
-    "city_population",
-];
-
-fn extract_to_csv(input_path: &Path, output_path: &Path) -> Result<()> {
-    if let Some(parent) = output_path.parent() {
-        if !parent.as_os_str().is_empty() {
-            std::fs::create_dir_all(parent)?;
-        }
-    }
-
-    let mut writer = Writer::from_path(output_path)?;
-    writer.write_record(CSV_HEADERS)?;
-
-    let ext = input_path.extension().and_then(|value| value.to_str());
-    match ext {
-        Some("osm") => extract_osm_xml_to_writer(input_path, &mut writer)?,
-        _ => extract_pbf_to_writer(input_path, &mut writer)?,
-    }
-
-    writer.flush()?;
-    Ok(())
-}
-
-fn parse_args() -> Result<(PathBuf, PathBuf)> {
-    let mut input = None;
-    let mut output = PathBuf::from("street_polygons.csv");
-
-    let mut args = env::args().skip(1);
-    while let Some(arg) = args.next() {
-        match arg.as_str() {
-            "--input" => {
-                input = Some(
-                    args.next()
-                        .ok_or("--input requires a path")
-                        .map(PathBuf::from)?,
-                );
-            }
-            "--output" => {
-                output = args
-                    .next()
-                    .ok_or("--output requires a path")
-                    .map(PathBuf::from)?;
+            "--place-cell-size" => {
+                let value = args.next().ok_or("--place-cell-size requires a value")?;
+                options.place_cell_size = PlaceCellSize::parse(&value)?;
             }
             "-h" | "--help" => {
                 println!(
-                    "Usage: extract_street_polygons [--input FILE] [--output FILE]\n\n"
+                    "Usage: extract_street_polygons [--input FILE] [--output FILE] [OPTIONS]\n       extract_street_polygons schema [OPTIONS]\n       extract_street_polygons diff --old OLD.csv --new NEW.csv [--output FILE]\n\n"
+                );
+                println!(
+                    "schema  Print the schema version and the columns OPTIONS would produce, as JSON."
+                );
+                println!(
+                    "--input   Path to a .pbf, .osm or .osc (osmChange) file; repeat to combine several into one output with a shared place index. Defaults to the only .pbf in the current folder."
+                );
+                println!(
+                    "--input-dir DIR  Extract every .pbf/.osm file in DIR (e.g. tiles of one region) into one CSV, with places shared across files."
+                );
+                println!(
+                    "--output  Output CSV path, or - for stdout. Defaults to street_polygons.csv."
+                );
+                println!(
+                    "--format csv|json|jsonl  Output format. json writes one array of objects keyed by the CSV header; jsonl writes one GeoJSON Feature per line with those objects as properties. Defaults to csv."
+                );
+                println!(
+                    "--fail-on-empty  Exit with an error if no data rows were written (e.g. filters dropped everything)."
+                );
+                println!(
+                    "--strict  Exit with an error if an .osm file repeats a node id with a different coordinate, instead of warning."
+                );
+                println!("--pretty  Indent --format json output (for small extracts).");
+                println!("--crlf  End --format csv lines with CRLF (for Excel on Windows).");
+                println!("--gzip  Gzip the output (implied by an output path ending in .gz).");
+                println!(
+                    "--with-metadata  Add osm_version/osm_timestamp columns (newest edit per merged row). Only .osm input carries this metadata."
+                );
+                println!(
+                    "--place-cell-size DEG|auto  Grid cell size of the place index, 0.1 to 5 degrees. auto sizes cells from place density. Defaults to 1.0."
+                );
+                println!(
+                    "--include-poi-relations  Also extract POIs mapped as type=multipolygon relations (centroid of the largest outer ring)."
+                );
+                println!(
+                    "--length-unit km|mi|m  Add a length_<unit> column with the (merged) street length in that unit."
                 );
                 println!(
-                    "--input   Path to a .pbf or .osm file. Defaults to the only .pbf in the current folder."
+                    "--max-cluster-span KM  Stop growing a merge cluster once its bounding-box diagonal would exceed KM."
+                );
+                println!(
+                    "--dedup-distance KM  After merging, drop rows whose name, kind and city repeat a longer row within KM, without combining them. Alias: --drop-duplicate-names-within-distance."
+                );
+                println!(
+                    "--max-rows-per-name N  After merging, write at most N rows per name: the longest, then the largest by area, then the first."
+                );
+                println!(
+                    "--sample-rate P  Keep a reproducible fraction P (0.0-1.0) of the merged rows, chosen by a stable hash of name, kind and center."
+                );
+                println!(
+                    "--poi-length perimeter|extent|zero  Length of POIs mapped as ways: outline length, bounding-box diagonal, or 0 (default)."
+                );
+                println!(
+                    "--center-on-geometry  Snap the center of a way to its vertex nearest the midpoint or centroid."
+                );
+                println!(
+                    "--drop-zero-length  Skip street ways whose nodes all share one coordinate (length 0)."
+                );
+                println!(
+                    "--min-area-m2 M2  Skip closed ways mapped as areas (plazas, POI outlines) that enclose less than M2 square meters."
+                );
+                println!(
+                    "--max-nodes-per-way N  Skip (with a warning) street and POI ways with more than N nodes. Defaults to 100000."
+                );
+                println!(
+                    "--merge-weight length|count|equal  Weight merged centers by street length (default), node count, or equally."
+                );
+                println!(
+                    "--merge-case-insensitive  Merge names that differ only in case (Hauptstraße/HAUPTSTRASSE), keeping the most common spelling."
+                );
+                println!(
+                    "--merge-accent-insensitive  Merge names that differ only in accents (Église/Eglise), keeping the most common spelling."
+                );
+                println!(
+                    "--after-date YYYY-MM-DD  Only extract features edited on or after this date (place nodes are still used for association)."
+                );
+                println!(
+                    "--drop-undated  With --after-date, also drop features without a timestamp (all .pbf features). Kept by default."
+                );
+                println!(
+                    "--keep-noname  Keep named streets that are also tagged noname=yes (dropped by default)."
+                );
+                println!(
+                    "--name-keys KEY,KEY  Name keys to read, in precedence order; the first present gives the primary name. Defaults to {}.",
+                    NAME_KEYS.join(",")
+                );
+                println!(
+                    "--no-split-names  Treat ';' in name tags as part of the name instead of a multi-value separator."
+                );
+                println!(
+                    "--raw-names  Keep names exactly as tagged instead of removing zero-width and control characters and collapsing whitespace."
+                );
+                println!(
+                    "--rename-map FILE.csv  Replace names listed in a from,to CSV with their canonical spelling before merging."
+                );
+                println!(
+                    "--mmap  Memory-map .pbf input instead of reading it through the file handle. The file must not change during the run; avoid on network filesystems."
+                );
+                println!(
+                    "--aliases  Write one row per element under its primary name and list the other names in an aliases column (;-separated)."
+                );
+                println!(
+                    "--bbox MINLON,MINLAT,MAXLON,MAXLAT  Keep only rows whose center lies inside this box."
+                );
+                println!(
+                    "--bbox-from FILE  Use the bounding box of every node in FILE (e.g. a small reference extract) as --bbox."
+                );
+                println!(
+                    "--boundaries-output FILE.csv  Also write each named administrative boundary's name, admin_level, centroid and area to FILE.csv."
+                );
+                println!(
+                    "--places-output FILE.csv  Also write every place node's name, type, location and population to FILE.csv."
+                );
+                println!(
+                    "--exclude-bbox MINLON,MINLAT,MAXLON,MAXLAT  Drop rows whose center lies inside this box (repeatable). Applied after --bbox and --clip."
+                );
+                println!(
+                    "--clip FILE  Keep only rows whose center lies inside the Polygon/MultiPolygon in this GeoJSON file."
+                );
+                println!(
+                    "--country-polygons FILE  Add a country_code column (ISO 3166-1 alpha-2) from the country outlines in this GeoJSON FeatureCollection (e.g. Natural Earth admin 0, ISO_A2 property)."
+                );
+                println!(
+                    "--tolerant-numbers  Accept decimal commas and stray whitespace in .osm lat/lon attributes instead of dropping the node."
+                );
+                println!(
+                    "--detect-swapped-coords  Swap back .osm node coordinates whose lat is beyond ±90 but would be a valid lon, and whose lon would be a valid lat."
+                );
+                println!(
+                    "--compact-coords  Trim trailing zeros from .pbf coordinates (0.0000000 is written as 0)."
+                );
+                println!(
+                    "--hamlet-radius KM  Only associate hamlets and localities within KM. Defaults to 75."
+                );
+                println!(
+                    "--town-radius KM  Only associate other places (city, town, village, suburb) within KM. Defaults to 75."
+                );
+                println!(
+                    "--min-place-rank TYPE  Ignore places ranked below TYPE (city > town > village > hamlet > suburb > locality)."
+                );
+                println!(
+                    "--place-filter TYPE,...  Load only these place types (e.g. city,town); other places are not associated or written."
+                );
+                println!(
+                    "--include-places  Write place nodes as kind=place rows with a place_type column instead of kind=city."
+                );
+                println!(
+                    "--prefer-places-in-boundary  Prefer places inside the same administrative boundary as a feature over nearer ones outside it."
+                );
+                println!(
+                    "--with-geohash N  Add a geohash column (N characters, 1-12) computed from the written center."
+                );
+                println!(
+                    "--with-ascii-name  Add a name_ascii column with the name transliterated to ASCII (Straße -> Strasse)."
+                );
+                println!(
+                    "--include-proposed  Keep named highway=proposed/planned/construction ways and add a status column (proposed, construction or active)."
+                );
+                println!(
+                    "--with-surface  Add surface and smoothness columns with the street's tags (empty for POIs and places)."
+                );
+                println!(
+                    "--with-is-in  Add is_in_region and is_in_country columns parsed from is_in:* keys or the tokens after the city in is_in."
+                );
+                println!(
+                    "--dedup-poi-in-building  Drop a named building way when a named POI node lies inside it."
+                );
+                println!(
+                    "--include-disused  Keep POIs tagged disused=yes, abandoned=yes etc., and read disused:railway=station as railway=station."
+                );
+                println!(
+                    "--include-natural  Also extract named natural=* features (peaks, bays, capes, water) and place=island/islet, as kind=natural."
+                );
+                println!(
+                    "--include-named-buildings  Also extract named buildings that match no other POI kind, as kind=building."
+                );
+                println!(
+                    "--named-buildings-need-wiki  With --include-named-buildings, only take buildings with a wikipedia or wikidata tag."
+                );
+                println!(
+                    "--only-with-wiki  Keep only POIs (of any kind) that have a wikipedia or wikidata tag."
+                );
+                println!(
+                    "--with-place-coords  Add place_node_lon/place_node_lat columns with the location of the city_place_node place."
+                );
+                println!(
+                    "--with-suburb  Add a suburb column from addr:suburb, addr:district or the nearest place=suburb node."
+                );
+                println!(
+                    "--with-level  Add a level column from level (or layer). Features on different levels are never merged."
+                );
+                println!(
+                    "--with-ele  Add an ele_m column with a POI's ele tag in meters (m and ft suffixes are converted)."
+                );
+                println!(
+                    "--with-operator-brand  Add operator and brand columns with a POI's tags (empty for streets and places)."
+                );
+                println!(
+                    "--with-merged-from  Add a merged_from column listing the OSM elements merged into each row (n/w/r plus id)."
+                );
+                println!(
+                    "--use-destination  Name highways that have no name tag after their destination (or junction:ref) tag, and add a name_source column."
+                );
+                println!(
+                    "--with-sinuosity  Add a sinuosity column: street length over the straight-line distance between its ends (empty for closed ways)."
+                );
+                println!(
+                    "--with-city-confidence  Add a city_confidence column (0-1): 1 for addr:city, 0.6 for is_in, at most 0.4 for a nearby place node, decaying to 0 at its --town-radius or --hamlet-radius; at least 0.9 when an enclosing boundary of the same name confirms the city (with --prefer-places-in-boundary)."
+                );
+                println!(
+                    "--tag-stats  Instead of writing rows, print the most common name keys, place types, highway classes and POI categories among kept features to stderr."
+                );
+                println!(
+                    "--crs 4326|3857  Write centers as WGS84 degrees (center_lon/center_lat, default) or Web Mercator meters (x/y)."
+                );
+                println!(
+                    "--schema-comment  Write a '# schema_version=N' line before the CSV header."
+                );
+                println!(
+                    "--name-include REGEX  Only write rows for names matching REGEX (e.g. 'gasse$')."
+                );
+                println!(
+                    "--name-exclude REGEX  Drop rows for names matching REGEX. Applied after --name-include."
+                );
+                println!(
+                    "--extra-tags KEY,KEY  Add one column per tag key with the element's value (empty if absent)."
+                );
+                println!(
+                    "--header-map OLD=NEW,...  Rename output columns in the header (and schema); column order and data are unchanged."
+                );
+                println!(
+                    "--columns COL,...  Write only these columns, in this order, e.g. streetname,center_lon,center_lat."
+                );
+                println!(
+                    "--checkpoint FILE  Save the rows to FILE once all input is read and associated; a rerun with the same inputs and options resumes from there and only merges and writes. Merge, row-limit and output options may change between runs."
+                );
+                println!(
+                    "--progress  Log objects/sec (and an ETA for .pbf input) to stderr every 5 seconds."
+                );
+                println!("--progress-interval SECS  Like --progress, with a custom interval.");
+                println!(
+                    "--timeout SECS  Abort with an error if reading and merging take longer than SECS."
+                );
+                println!(
+                    "--snap-precision N  Round centers to N decimal places before writing; identical rows are written once."
+                );
+                println!(
+                    "--snap-meters M  Snap centers to a grid of about M meters before writing; identical rows are written once."
                 );
-                println!("--output  Output CSV path. Defaults to street_polygons.csv.");
                 std::process::exit(0);
             }
             _ => return Err(format!("unknown argument: {arg}").into()),
         }
     }
 
-    let input_path = match input {
-        Some(path) => path,
-        None => find_default_pbf(&env::current_dir()?)?,
+    if options.pretty && options.format != OutputFormat::Json {
+        return Err("--pretty only applies to --format json".into());
+    }
+    if options.crlf && options.format != OutputFormat::Csv {
+        return Err("--crlf only applies to --format csv".into());
+    }
+    if let Some(path) = bbox_from {
+        if options.bbox.is_some() {
+            return Err("pass either --bbox or --bbox-from, not both".into());
+        }
+        options.bbox = Some(BoundingBox::from_input(&path, &options)?);
+    }
+    if schema {
+        return Ok(Command::Schema(options));
+    }
+
+    let input_paths = match (inputs.is_empty(), input_dir) {
+        (false, Some(_)) => return Err("pass either --input or --input-dir, not both".into()),
+        (false, None) => inputs,
+        (true, Some(dir)) => input_dir_files(&dir)?,
+        (true, None) => vec![find_default_pbf(&env::current_dir()?)?],
     };
 
-    Ok((input_path, output))
+    if tag_stats {
+        return Ok(Command::TagStats {
+            input_paths,
+            options,
+        });
+    }
+    Ok(Command::Extract {
+        input_paths,
+        output,
+        options,
+    })
 }
 
 fn main() {
@@ -1331,537 +723,56 @@ fn main() {
 }
 
 fn run() -> Result<()> {
-    let (input_path, output_path) = parse_args()?;
-    extract_to_csv(&input_path, &output_path)
-}
-
-fn parse_population(value: &str) -> Option<u64> {
-    let digits: String = value.chars().filter(|ch| ch.is_ascii_digit()).collect();
-    if digits.is_empty() {
-        return None;
-    }
-    digits.parse::<u64>().ok()
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use csv::ReaderBuilder;
-    use tempfile::tempdir;
-
-    const OSM_SAMPLE: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
-<osm version="0.6" generator="test">
-  <node id="1" lat="0.0" lon="0.0" />
-  <node id="2" lat="0.0" lon="1.0" />
-  <node id="3" lat="1.0" lon="1.0" />
-  <node id="4" lat="1.0" lon="0.0" />
-  <node id="5" lat="2.0" lon="0.0" />
-  <node id="6" lat="2.0" lon="1.0" />
-  <node id="7" lat="0.5" lon="0.5">
-    <tag k="place" v="town" />
-    <tag k="name" v="Placetown" />
-    <tag k="name:fr" v="Ville Place" />
-    <tag k="population" v="12345" />
-  </node>
-  <node id="100" lat="-1.0" lon="-1.0" />
-  <node id="101" lat="-1.0" lon="3.0" />
-  <node id="102" lat="3.0" lon="3.0" />
-  <node id="103" lat="3.0" lon="-1.0" />
-  <node id="200" lat="-5.0" lon="-5.0" />
-  <node id="201" lat="-5.0" lon="5.0" />
-  <node id="202" lat="5.0" lon="5.0" />
-  <node id="203" lat="5.0" lon="-5.0" />
-  <way id="10">
-    <nd ref="1" />
-    <nd ref="2" />
-    <nd ref="3" />
-    <nd ref="4" />
-    <nd ref="1" />
-    <tag k="highway" v="residential" />
-    <tag k="name" v="Main Street" />
-    <tag k="alt_name" v="Old Main" />
-    <tag k="is_in" v="Placetown, Testland" />
-  </way>
-  <way id="11">
-    <nd ref="4" />
-    <nd ref="5" />
-    <nd ref="6" />
-    <tag k="highway" v="residential" />
-    <tag k="name" v="Open Way" />
-  </way>
-  <way id="300">
-    <nd ref="100" />
-    <nd ref="101" />
-    <nd ref="102" />
-    <nd ref="103" />
-    <nd ref="100" />
-    <tag k="boundary" v="administrative" />
-    <tag k="admin_level" v="8" />
-    <tag k="name" v="Testville" />
-  </way>
-  <way id="301">
-    <nd ref="200" />
-    <nd ref="201" />
-    <nd ref="202" />
-    <nd ref="203" />
-    <nd ref="200" />
-    <tag k="boundary" v="administrative" />
-    <tag k="admin_level" v="2" />
-    <tag k="name" v="Testland" />
-  </way>
-</osm>
-"#;
-
-    const OSM_MULTI_NAME: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
-<osm version="0.6" generator="test">
-  <node id="1" lat="0.0" lon="0.0" />
-  <node id="2" lat="0.0" lon="2.0" />
-  <node id="3" lat="2.0" lon="2.0" />
-  <node id="4" lat="2.0" lon="0.0" />
-  <way id="20">
-    <nd ref="1" />
-    <nd ref="2" />
-    <nd ref="3" />
-    <nd ref="4" />
-    <nd ref="1" />
-    <tag k="highway" v="primary" />
-    <tag k="name" v="First;Second" />
-    <tag k="name:de" v="Erste Straße" />
-  </way>
-</osm>
-"#;
-
-    const OSM_LARGER_PLACE: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
-<osm version="0.6" generator="test">
-  <node id="1" lat="0.0" lon="0.0" />
-  <node id="2" lat="0.0" lon="0.2" />
-  <node id="10" lat="0.0" lon="0.1">
-    <tag k="place" v="hamlet" />
-    <tag k="name" v="Tinyham" />
-  </node>
-  <node id="11" lat="0.0" lon="0.5">
-    <tag k="place" v="town" />
-    <tag k="name" v="Bigtown" />
-  </node>
-  <way id="20">
-    <nd ref="1" />
-    <nd ref="2" />
-    <tag k="highway" v="residential" />
-    <tag k="name" v="Hamlet Road" />
-  </way>
-</osm>
-"#;
-
-    const OSM_MERGE_NEARBY: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
-<osm version="0.6" generator="test">
-  <node id="1" lat="0.0" lon="0.0" />
-  <node id="2" lat="0.001" lon="0.0" />
-  <node id="3" lat="0.002" lon="0.0" />
-  <node id="10" lat="0.001" lon="0.001">
-    <tag k="place" v="city" />
-    <tag k="name" v="Testville" />
-    <tag k="population" v="100000" />
-  </node>
-  <way id="40">
-    <nd ref="1" />
-    <nd ref="2" />
-    <tag k="highway" v="residential" />
-    <tag k="name" v="Dave Burns Drive" />
-  </way>
-  <way id="41">
-    <nd ref="2" />
-    <nd ref="3" />
-    <tag k="highway" v="residential" />
-    <tag k="name" v="Dave Burns Drive" />
-  </way>
-</osm>
-"#;
-
-    const OSM_MERGE_DIFFERENT_CITY: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
-<osm version="0.6" generator="test">
-  <node id="1" lat="0.0" lon="0.0" />
-  <node id="2" lat="0.001" lon="0.0" />
-  <node id="3" lat="0.008" lon="0.0" />
-  <node id="4" lat="0.009" lon="0.0" />
-  <node id="10" lat="0.001" lon="0.001">
-    <tag k="place" v="city" />
-    <tag k="name" v="Alpha City" />
-    <tag k="population" v="50000" />
-  </node>
-  <node id="11" lat="0.0085" lon="0.001">
-    <tag k="place" v="city" />
-    <tag k="name" v="Beta City" />
-    <tag k="population" v="75000" />
-  </node>
-  <way id="40">
-    <nd ref="1" />
-    <nd ref="2" />
-    <tag k="highway" v="residential" />
-    <tag k="name" v="Dave Burns Drive" />
-  </way>
-  <way id="41">
-    <nd ref="3" />
-    <nd ref="4" />
-    <tag k="highway" v="residential" />
-    <tag k="name" v="Dave Burns Drive" />
-  </way>
-</osm>
-"#;
-
-    const OSM_POI: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
-<osm version="0.6" generator="test">
-  <node id="1" lat="48.8584" lon="2.2945">
-    <tag k="name" v="Eiffel Tower" />
-    <tag k="tourism" v="attraction" />
-    <tag k="wikipedia" v="en:Eiffel_Tower" />
-  </node>
-  <node id="2" lat="48.0" lon="2.0">
-    <tag k="name" v="Local Statue" />
-    <tag k="tourism" v="attraction" />
-  </node>
-  <node id="3" lat="40.0" lon="-73.0">
-    <tag k="name" v="Central Station" />
-    <tag k="railway" v="station" />
-  </node>
-  <node id="4" lat="41.0" lon="-74.0">
-    <tag k="name" v="Main Bus Stop" />
-    <tag k="highway" v="bus_stop" />
-  </node>
-  <node id="5" lat="42.0" lon="-75.0">
-    <tag k="name" v="City Airport" />
-    <tag k="aeroway" v="aerodrome" />
-  </node>
-  <node id="6" lat="43.0" lon="-76.0">
-    <tag k="name" v="Harbor Ferry Terminal" />
-    <tag k="amenity" v="ferry_terminal" />
-  </node>
-  <node id="7" lat="44.0" lon="-77.0">
-    <tag k="name" v="State University" />
-    <tag k="amenity" v="university" />
-  </node>
-  <node id="8" lat="45.0" lon="-78.0">
-    <tag k="name" v="City Museum" />
-    <tag k="tourism" v="museum" />
-  </node>
-  <node id="9" lat="46.0" lon="-79.0">
-    <tag k="name" v="Central Library" />
-    <tag k="amenity" v="library" />
-  </node>
-</osm>
-"#;
-
-    #[test]
-    fn split_and_collect_names() {
-        let mut tags = Tags::new();
-        tags.insert("name".into(), "Main St;Second St".into());
-        tags.insert("alt_name".into(), "Alt".into());
-        tags.insert("name:de".into(), "Hauptstraße".into());
-        tags.insert("foo".into(), "bar".into());
-
-        let variants = collect_name_variants(&tags);
-        let results: Vec<(String, String)> = variants
-            .into_iter()
-            .map(|variant| (variant.name, variant.lang))
-            .collect();
-        assert_eq!(
-            results,
-            vec![
-                ("Main St".to_string(), "".to_string()),
-                ("Second St".to_string(), "".to_string()),
-                ("Alt".to_string(), "".to_string()),
-                ("Hauptstraße".to_string(), "de".to_string()),
-            ]
-        );
-    }
-
-    #[test]
-    fn polygon_centroid_square() {
-        let coords = vec![(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0), (0.0, 0.0)];
-        let (cx, cy) = polygon_centroid(&coords).unwrap();
-        assert!((cx - 1.0).abs() < 1e-9);
-        assert!((cy - 1.0).abs() < 1e-9);
-    }
-
-    #[test]
-    fn line_midpoint_basic() {
-        let coords = vec![(0.0, 0.0), (4.0, 0.0)];
-        let (mx, my) = line_midpoint(&coords).unwrap();
-        assert!((mx - 2.0).abs() < 1e-9);
-        assert!(my.abs() < 1e-9);
-    }
-
-    #[test]
-    fn place_index_picks_nearest_and_filters() {
-        let places = vec![
-            PlaceNode::new(
-                vec![NameVariant {
-                    name: "Near".to_string(),
-                    lang: "".to_string(),
-                }],
-                "town".to_string(),
-                (0.0, 0.0),
-                None,
-            ),
-            PlaceNode::new(
-                vec![NameVariant {
-                    name: "Far".to_string(),
-                    lang: "".to_string(),
-                }],
-                "hamlet".to_string(),
-                (5.0, 5.0),
-                None,
-            ),
-        ];
-        let index = PlaceIndex::new(places, 1.0);
-
-        let nearest = index
-            .nearest((0.1, 0.1), PlaceFilter::Any)
-            .unwrap();
-        assert_eq!(nearest.name, "Near");
-
-        let filtered = index
-            .nearest((0.1, 0.1), PlaceFilter::CityTown)
-            .map(|place| place.name.clone());
-        assert_eq!(filtered.as_deref(), Some("Near"));
-    }
-
-    #[test]
-    fn extract_to_csv_from_osm() {
-        let dir = tempdir().unwrap();
-        let osm_path = dir.path().join("sample.osm");
-        let out_path = dir.path().join("out.csv");
-        std::fs::write(&osm_path, OSM_SAMPLE).unwrap();
-
-        extract_to_csv(&osm_path, &out_path).unwrap();
-
-        let mut reader = ReaderBuilder::new()
-            .has_headers(false)
-            .from_path(&out_path)
-            .unwrap();
-        let rows: Vec<Vec<String>> = reader
-            .records()
-            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
-            .collect();
-
-        let expected_headers: Vec<String> =
-            CSV_HEADERS.iter().map(|header| (*header).to_string()).collect();
-        assert_eq!(rows[0], expected_headers);
-        let mut names: Vec<String> =
-            rows[1..].iter().map(|row| row[0].to_string()).collect();
-        names.sort();
-        assert_eq!(
-            names,
-            vec![
-                "Main Street",
-                "Old Main",
-                "Open Way",
-                "Placetown",
-                "Ville Place",
-            ]
-        );
-
-        let open_row = rows
-            .iter()
-            .skip(1)
-            .find(|row| row[0] == "Open Way")
-            .unwrap();
-        let idx = |name: &str| {
-            CSV_HEADERS
-                .iter()
-                .position(|header| header == &name)
-                .unwrap()
-        };
-        assert_eq!(open_row[idx("name_lang")], "");
-        assert_eq!(open_row[idx("kind")], "street");
-        assert_eq!(open_row[idx("center_lon")], "0");
-        assert_eq!(open_row[idx("center_lat")], "2");
-        assert_eq!(open_row[idx("city_place_node")], "");
-        assert_eq!(open_row[idx("city_place_type")], "");
-        assert_eq!(open_row[idx("city_place_city")], "");
-        assert_eq!(open_row[idx("city_resolved")], "");
-        assert_eq!(open_row[idx("city_population")], "");
-
-        let main_row = rows
-            .iter()
-            .skip(1)
-            .find(|row| row[0] == "Main Street")
-            .unwrap();
-        assert_eq!(main_row[idx("name_lang")], "");
-        assert_eq!(main_row[idx("kind")], "street");
-        assert_eq!(main_row[idx("city_place_node")], "Placetown");
-        assert_eq!(main_row[idx("city_place_type")], "town");
-        assert_eq!(main_row[idx("city_place_city")], "Placetown");
-        assert_eq!(main_row[idx("city_resolved")], "Placetown");
-        assert_eq!(main_row[idx("city_population")], "12345");
-
-        let translated_row = rows
-            .iter()
-            .skip(1)
-            .find(|row| row[0] == "Ville Place")
-            .unwrap();
-        assert_eq!(translated_row[idx("name_lang")], "fr");
-    }
-
-    #[test]
-    fn extract_to_csv_splits_multi_names() {
-        let dir = tempdir().unwrap();
-        let osm_path = dir.path().join("multi.osm");
-        let out_path = dir.path().join("out.csv");
-        std::fs::write(&osm_path, OSM_MULTI_NAME).unwrap();
-
-        extract_to_csv(&osm_path, &out_path).unwrap();
-
-        let mut reader = ReaderBuilder::new()
-            .has_headers(false)
-            .from_path(&out_path)
-            .unwrap();
-        let rows: Vec<Vec<String>> = reader
-            .records()
-            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
-            .collect();
-
-        let names: Vec<&str> = rows[1..].iter().map(|row| row[0].as_str()).collect();
-        assert_eq!(names, vec!["First", "Second", "Erste Straße"]);
-    }
-
-    #[test]
-    fn extract_to_csv_promotes_town_or_city() {
-        let dir = tempdir().unwrap();
-        let osm_path = dir.path().join("larger_place.osm");
-        let out_path = dir.path().join("out.csv");
-        std::fs::write(&osm_path, OSM_LARGER_PLACE).unwrap();
-
-        extract_to_csv(&osm_path, &out_path).unwrap();
-
-        let mut reader = ReaderBuilder::new()
-            .has_headers(false)
-            .from_path(&out_path)
-            .unwrap();
-        let rows: Vec<Vec<String>> = reader
-            .records()
-            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
-            .collect();
-
-        let hamlet_row = rows
-            .iter()
-            .skip(1)
-            .find(|row| row[0] == "Hamlet Road")
-            .unwrap();
-        assert_eq!(hamlet_row[2], "street");
-        assert_eq!(hamlet_row[5], "Tinyham");
-        assert_eq!(hamlet_row[6], "hamlet");
-        assert_eq!(hamlet_row[7], "Bigtown");
-        assert_eq!(hamlet_row[8], "Bigtown");
-    }
-
-    #[test]
-    fn extract_to_csv_merges_nearby_segments() {
-        let dir = tempdir().unwrap();
-        let osm_path = dir.path().join("merge.osm");
-        let out_path = dir.path().join("out.csv");
-        std::fs::write(&osm_path, OSM_MERGE_NEARBY).unwrap();
-
-        extract_to_csv(&osm_path, &out_path).unwrap();
-
-        let mut reader = ReaderBuilder::new()
-            .has_headers(false)
-            .from_path(&out_path)
-            .unwrap();
-        let rows: Vec<Vec<String>> = reader
-            .records()
-            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
-            .collect();
-
-        let data_rows: Vec<&Vec<String>> = rows
-            .iter()
-            .skip(1)
-            .filter(|row| row[2] != "city")
-            .collect();
-        assert_eq!(data_rows.len(), 1);
-        assert_eq!(data_rows[0][0], "Dave Burns Drive");
-
-        let lat: f64 = data_rows[0][4].parse().unwrap();
-        assert!((lat - 0.001).abs() < 1e-9);
-    }
-
-    #[test]
-    fn extract_to_csv_keeps_same_name_separate_for_different_cities() {
-        let dir = tempdir().unwrap();
-        let osm_path = dir.path().join("merge_city.osm");
-        let out_path = dir.path().join("out.csv");
-        std::fs::write(&osm_path, OSM_MERGE_DIFFERENT_CITY).unwrap();
-
-        extract_to_csv(&osm_path, &out_path).unwrap();
-
-        let mut reader = ReaderBuilder::new()
-            .has_headers(false)
-            .from_path(&out_path)
-            .unwrap();
-        let rows: Vec<Vec<String>> = reader
-            .records()
-            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
-            .collect();
-
-        let data_rows: Vec<&Vec<String>> = rows
-            .iter()
-            .skip(1)
-            .filter(|row| row[2] != "city")
-            .collect();
-        assert_eq!(data_rows.len(), 2);
-        let mut cities: Vec<&str> = data_rows.iter().map(|row| row[8].as_str()).collect();
-        cities.sort();
-        assert_eq!(cities, vec!["Alpha City", "Beta City"]);
-    }
-
-    #[test]
-    fn extract_to_csv_includes_poi_and_filters_minor_sights() {
-        let dir = tempdir().unwrap();
-        let osm_path = dir.path().join("poi.osm");
-        let out_path = dir.path().join("out.csv");
-        std::fs::write(&osm_path, OSM_POI).unwrap();
-
-        extract_to_csv(&osm_path, &out_path).unwrap();
-
-        let mut reader = ReaderBuilder::new()
-            .has_headers(false)
-            .from_path(&out_path)
-            .unwrap();
-        let rows: Vec<Vec<String>> = reader
-            .records()
-            .map(|row| row.unwrap().iter().map(|value| value.to_string()).collect())
-            .collect();
-        let mut names: Vec<String> = rows.iter().skip(1).map(|row| row[0].to_string()).collect();
-        names.sort();
-        let expected: Vec<String> = vec![
-            "Central Library",
-            "Central Station",
-            "City Airport",
-            "City Museum",
-            "Eiffel Tower",
-            "Harbor Ferry Terminal",
-            "Main Bus Stop",
-            "State University",
-        ]
-        .into_iter()
-        .map(String::from)
-        .collect();
-        assert_eq!(names, expected);
-
-        let mut kinds: Vec<String> = rows.iter().skip(1).map(|row| row[2].to_string()).collect();
-        kinds.sort();
-        let expected_kinds: Vec<String> = vec![
-            "airport",
-            "bus_stop",
-            "civic_building",
-            "ferry_terminal",
-            "museum",
-            "sight",
-            "train_station",
-            "university",
-        ]
-        .into_iter()
-        .map(String::from)
-        .collect();
-        assert_eq!(kinds, expected_kinds);
+    match parse_args()? {
+        Command::Extract {
+            input_paths,
+            output,
+            options,
+        } => {
+            let written = extract_files_to_csv(&input_paths, &output, &options)?;
+            if output == Path::new(STDOUT_PATH) {
+                eprintln!("wrote {written} rows to stdout");
+            } else {
+                eprintln!("wrote {written} rows to {}", output.display());
+            }
+            Ok(())
+        }
+        Command::Schema(options) => {
+            println!("{}", schema_json(&options)?);
+            Ok(())
+        }
+        Command::TagStats {
+            input_paths,
+            options,
+        } => {
+            eprint!("{}", tag_stats(&input_paths, &options)?.report());
+            Ok(())
+        }
+        Command::GenBench { shape, output } => {
+            write_synthetic_osm(&output, shape)?;
+            eprintln!(
+                "wrote {} ways over {} nodes to {}",
+                shape.ways,
+                shape.nodes,
+                output.display()
+            );
+            Ok(())
+        }
+        Command::Diff {
+            old,
+            new,
+            output,
+            move_threshold_km,
+        } => {
+            let summary = diff_outputs(&old, &new, &output, move_threshold_km)?;
+            eprintln!(
+                "{} added, {} removed, {} moved; written to {}",
+                summary.added,
+                summary.removed,
+                summary.moved,
+                output.display()
+            );
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,244 @@
+//! Python bindings, compiled with the `pyo3` feature (e.g. `maturin develop
+//! --features pyo3`, which builds the crate as a `cdylib`). The module is
+//! importable as `extract_street_polygons`.
+
+use crate::{
+    extract_entries, parse_date, parse_extra_tags, parse_name_keys, parse_place_filter,
+    BoundingBox, ClipPolygon, ExtractOptions, MergeWeight, PlaceCellSize, PlaceRank, PoiLength,
+    RenameMap, StreetEntry,
+};
+use pyo3::exceptions::{PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+fn json_to_py(py: Python<'_>, value: serde_json::Value) -> PyResult<PyObject> {
+    use serde_json::Value;
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(flag) => flag.into_pyobject(py)?.to_owned().into_any().unbind(),
+        Value::Number(number) => match number.as_i64() {
+            Some(int) => int.into_pyobject(py)?.into_any().unbind(),
+            None => number.as_f64().into_pyobject(py)?.into_any().unbind(),
+        },
+        Value::String(text) => text.into_pyobject(py)?.into_any().unbind(),
+        Value::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| json_to_py(py, item))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items)?.into_any().unbind()
+        }
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, value) in map {
+                dict.set_item(key, json_to_py(py, value)?)?;
+            }
+            dict.into_any().unbind()
+        }
+    })
+}
+
+fn entry_dict<'py>(py: Python<'py>, entry: &StreetEntry) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    for (key, value) in entry.to_json_map() {
+        dict.set_item(key, json_to_py(py, value)?)?;
+    }
+    Ok(dict)
+}
+
+fn bad_value(key: &str, err: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(format!("invalid {key}: {err}"))
+}
+
+/// Sets the `ExtractOptions` field named `key`. Flags take a bool; the
+/// other options take what their command-line flag takes, and the place
+/// radii are `town_radius_km` and `hamlet_radius_km`. Options that only
+/// shape the CLI's output file (`format`, `columns`, `header_map`, `crs`,
+/// `snap`, `length_unit`, `geohash_len`, `countries`, `compact_coords`,
+/// `pretty`, `crlf`, `gzip`, `schema_comment`, `fail_on_empty` and
+/// `progress`) are not accepted: every row field is returned regardless.
+fn set_option(options: &mut ExtractOptions, key: &str, value: &Bound<'_, PyAny>) -> PyResult<()> {
+    macro_rules! flags {
+        ($($field:ident),* $(,)?) => {
+            match key {
+                $(stringify!($field) => {
+                    options.$field = value.extract()?;
+                    return Ok(());
+                })*
+                _ => {}
+            }
+        };
+    }
+    flags!(
+        with_metadata,
+        include_poi_relations,
+        drop_undated,
+        keep_noname,
+        mmap,
+        group_aliases,
+        tolerant_numbers,
+        detect_swapped_coords,
+        merge_case_insensitive,
+        merge_accent_insensitive,
+        no_split_names,
+        center_on_geometry,
+        drop_zero_length,
+        strict,
+        ascii_name,
+        include_proposed,
+        with_surface,
+        is_in_hierarchy,
+        dedup_poi_in_building,
+        place_coords,
+        include_places,
+        suburb,
+        with_level,
+        prefer_places_in_boundary,
+        with_ele,
+        with_operator_brand,
+        with_merged_from,
+        use_destination,
+        with_sinuosity,
+        include_natural,
+        include_named_buildings,
+        named_buildings_need_wiki,
+        only_with_wiki,
+        city_confidence,
+        raw_names,
+        include_disused,
+    );
+    // Counts and distances take a number, as on the command line.
+    let positive_count = || match value.extract::<usize>()? {
+        0 => Err(bad_value(key, 0)),
+        count => Ok(Some(count)),
+    };
+    let non_negative = || match value.extract::<f64>()? {
+        number if number >= 0.0 && number.is_finite() => Ok(number),
+        number => Err(bad_value(key, number)),
+    };
+    let positive = || match non_negative()? {
+        0.0 => Err(bad_value(key, 0.0)),
+        number => Ok(number),
+    };
+    let path = || value.extract::<PathBuf>();
+    match key {
+        "bbox" => {
+            let text: String = value.extract()?;
+            options.bbox = Some(BoundingBox::parse(&text).map_err(|err| bad_value(key, err))?);
+        }
+        "name_include" | "name_exclude" => {
+            let pattern: String = value.extract()?;
+            let regex = Regex::new(&pattern).map_err(|err| bad_value(key, err))?;
+            if key == "name_include" {
+                options.name_include = Some(regex);
+            } else {
+                options.name_exclude = Some(regex);
+            }
+        }
+        "extra_tags" => {
+            let text: String = value.extract()?;
+            options.extra_tags = parse_extra_tags(&text).map_err(|err| bad_value(key, err))?;
+        }
+        "place_cell_size" => {
+            let text = value.str()?.to_string();
+            options.place_cell_size =
+                PlaceCellSize::parse(&text).map_err(|err| bad_value(key, err))?;
+        }
+        "sample_rate" => match value.extract::<f64>()? {
+            rate if (0.0..=1.0).contains(&rate) => options.sample_rate = Some(rate),
+            rate => return Err(bad_value(key, format!("{rate} (expected 0.0-1.0)"))),
+        },
+        "dedup_distance_km" => options.dedup_distance_km = Some(positive()?),
+        "exclude_bboxes" => {
+            let boxes: Vec<String> = value.extract()?;
+            options.exclude_bboxes = boxes
+                .iter()
+                .map(|text| BoundingBox::parse(text).map_err(|err| bad_value(key, err)))
+                .collect::<PyResult<_>>()?;
+        }
+        "clip" => {
+            let clip = ClipPolygon::load(&path()?).map_err(|err| bad_value(key, err))?;
+            options.clip = Some(clip);
+        }
+        "rename_map" => {
+            let map = RenameMap::load(&path()?).map_err(|err| bad_value(key, err))?;
+            options.rename_map = Some(map);
+        }
+        "checkpoint" => options.checkpoint = Some(path()?),
+        "boundaries_output" => options.boundaries_output = Some(path()?),
+        "places_output" => options.places_output = Some(path()?),
+        "name_keys" => {
+            let text: String = value.extract()?;
+            options.name_keys = parse_name_keys(&text).map_err(|err| bad_value(key, err))?;
+        }
+        "place_filter" => {
+            let text: String = value.extract()?;
+            options.place_filter = parse_place_filter(&text).map_err(|err| bad_value(key, err))?;
+        }
+        "min_place_rank" => {
+            let text: String = value.extract()?;
+            options.min_place_rank = PlaceRank::parse(&text).map_err(|err| bad_value(key, err))?;
+        }
+        "merge_weight" => {
+            let text: String = value.extract()?;
+            options.merge_weight = MergeWeight::parse(&text).map_err(|err| bad_value(key, err))?;
+        }
+        "poi_length" => {
+            let text: String = value.extract()?;
+            options.poi_length = PoiLength::parse(&text).map_err(|err| bad_value(key, err))?;
+        }
+        "after_date" => {
+            let text: String = value.extract()?;
+            let date = parse_date(&text).ok_or_else(|| bad_value(key, "expected YYYY-MM-DD"))?;
+            options.after_date = Some(date);
+        }
+        "timeout" => options.timeout = Some(Duration::from_secs_f64(positive()?)),
+        "max_cluster_span_km" => options.max_cluster_span_km = Some(non_negative()?),
+        "min_area_m2" => options.min_area_m2 = Some(non_negative()?),
+        "town_radius_km" => options.place_radii.town_km = non_negative()?,
+        "hamlet_radius_km" => options.place_radii.hamlet_km = non_negative()?,
+        "max_nodes_per_way" => options.max_nodes_per_way = positive_count()?,
+        "max_rows_per_name" => options.max_rows_per_name = positive_count()?,
+        _ => return Err(PyTypeError::new_err(format!("unknown option: {key}"))),
+    }
+    Ok(())
+}
+
+/// Extracts a `.pbf`/`.osm` file and returns the merged rows as a list of
+/// dicts (one key per `StreetEntry` field, see
+/// `StreetEntry::to_json_map`), ready for `pandas.DataFrame(...)`.
+///
+/// Keyword arguments set `ExtractOptions` fields by name, e.g.
+/// `extract_to_dataframe("city.pbf", bbox="13.3,52.4,13.5,52.6",
+/// with_ele=True)`; see `set_option` for the accepted values. Unknown
+/// names and output-only options raise `TypeError`.
+#[pyfunction]
+#[pyo3(signature = (input_path, **options))]
+fn extract_to_dataframe<'py>(
+    py: Python<'py>,
+    input_path: &str,
+    options: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    let mut extract_options = ExtractOptions::default();
+    if let Some(options) = options {
+        for (key, value) in options {
+            let key: String = key.extract()?;
+            set_option(&mut extract_options, &key, &value)?;
+        }
+    }
+    let entries = py
+        .allow_threads(|| {
+            extract_entries(Path::new(input_path), &extract_options).map_err(|err| err.to_string())
+        })
+        .map_err(PyRuntimeError::new_err)?;
+    entries.iter().map(|entry| entry_dict(py, entry)).collect()
+}
+
+#[pymodule]
+fn extract_street_polygons(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(extract_to_dataframe, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! A minimal OSM PBF writer for [`write_synthetic_osm`](crate::write_synthetic_osm),
+//! compiled with the `pbf-writer` feature. It only covers what synthetic
+//! extracts need: dense nodes and ways with tags.
+
+use crate::{Result, SyntheticElement};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Elements per PBF data block, as recommended by the format.
+const SYNTHETIC_PBF_BLOCK: usize = 8000;
+
+/// Writes `nodes` (as dense nodes) and `ways` in the OSM PBF format, with
+/// zlib-compressed blocks of at most `SYNTHETIC_PBF_BLOCK` elements.
+pub(crate) fn write_synthetic_pbf(
+    out: &mut impl Write,
+    nodes: &[SyntheticElement],
+    ways: &[SyntheticElement],
+) -> Result<()> {
+    let mut header = Protobuf::default();
+    header.bytes(4, b"OsmSchema-V0.6");
+    header.bytes(4, b"DenseNodes");
+    write_pbf_blob(out, "OSMHeader", &header.0)?;
+
+    // Coordinates in the default granularity of 100 nanodegrees.
+    let fixed = |degrees: f64| (degrees * 1e7).round() as i64;
+    for block in nodes.chunks(SYNTHETIC_PBF_BLOCK) {
+        let mut strings = PbfStringTable::default();
+        let mut keys_vals = Vec::new();
+        for node in block {
+            for (key, value) in &node.tags {
+                keys_vals.push(strings.index(key));
+                keys_vals.push(strings.index(value));
+            }
+            keys_vals.push(0);
+        }
+        let deltas = |value: &dyn Fn(&SyntheticElement) -> i64| {
+            let mut previous = 0;
+            block
+                .iter()
+                .map(|node| {
+                    let current = value(node);
+                    let delta = current - previous;
+                    previous = current;
+                    delta
+                })
+                .collect::<Vec<i64>>()
+        };
+        let mut dense = Protobuf::default();
+        dense.packed_sint(1, &deltas(&|node| node.id as i64));
+        dense.packed_sint(8, &deltas(&|node| fixed(node.coord.1)));
+        dense.packed_sint(9, &deltas(&|node| fixed(node.coord.0)));
+        dense.packed_uint(10, &keys_vals);
+        let mut group = Protobuf::default();
+        group.bytes(2, &dense.0);
+        write_pbf_blob(out, "OSMData", &strings.primitive_block(&group.0))?;
+    }
+    for block in ways.chunks(SYNTHETIC_PBF_BLOCK) {
+        let mut strings = PbfStringTable::default();
+        let mut group = Protobuf::default();
+        for way in block {
+            let mut message = Protobuf::default();
+            message.uint(1, way.id as u64);
+            let keys: Vec<u64> = way.tags.iter().map(|(key, _)| strings.index(key)).collect();
+            let values: Vec<u64> = way
+                .tags
+                .iter()
+                .map(|(_, value)| strings.index(value))
+                .collect();
+            message.packed_uint(2, &keys);
+            message.packed_uint(3, &values);
+            let mut previous = 0;
+            let refs: Vec<i64> = way
+                .refs
+                .iter()
+                .map(|&node| {
+                    let delta = node as i64 - previous;
+                    previous = node as i64;
+                    delta
+                })
+                .collect();
+            message.packed_sint(8, &refs);
+            group.bytes(3, &message.0);
+        }
+        write_pbf_blob(out, "OSMData", &strings.primitive_block(&group.0))?;
+    }
+    Ok(())
+}
+
+/// Frames `data` as one PBF blob: the big-endian header length, the
+/// `BlobHeader` and the zlib-compressed `Blob`.
+fn write_pbf_blob(out: &mut impl Write, kind: &str, data: &[u8]) -> Result<()> {
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(data)?;
+    let mut blob = Protobuf::default();
+    blob.uint(2, data.len() as u64);
+    blob.bytes(3, &zlib.finish()?);
+    let mut header = Protobuf::default();
+    header.bytes(1, kind.as_bytes());
+    header.uint(3, blob.0.len() as u64);
+    out.write_all(&(header.0.len() as u32).to_be_bytes())?;
+    out.write_all(&header.0)?;
+    out.write_all(&blob.0)?;
+    Ok(())
+}
+
+/// The string table of one PBF block; index 0 is the empty string.
+#[derive(Default)]
+struct PbfStringTable {
+    strings: Vec<String>,
+    indices: HashMap<String, u64>,
+}
+
+impl PbfStringTable {
+    fn index(&mut self, text: &str) -> u64 {
+        if self.strings.is_empty() {
+            self.strings.push(String::new());
+        }
+        if let Some(&index) = self.indices.get(text) {
+            return index;
+        }
+        let index = self.strings.len() as u64;
+        self.strings.push(text.to_string());
+        self.indices.insert(text.to_string(), index);
+        index
+    }
+
+    /// A `PrimitiveBlock` of this table and one encoded `PrimitiveGroup`.
+    fn primitive_block(mut self, group: &[u8]) -> Vec<u8> {
+        self.index("");
+        let mut table = Protobuf::default();
+        for text in &self.strings {
+            table.bytes(1, text.as_bytes());
+        }
+        let mut block = Protobuf::default();
+        block.bytes(1, &table.0);
+        block.bytes(2, group);
+        block.0
+    }
+}
+
+/// Just enough protobuf encoding for [`write_synthetic_pbf`].
+#[derive(Default)]
+struct Protobuf(Vec<u8>);
+
+impl Protobuf {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn uint(&mut self, field: u64, value: u64) {
+        self.varint(field << 3);
+        self.varint(value);
+    }
+
+    fn bytes(&mut self, field: u64, data: &[u8]) {
+        self.varint(field << 3 | 2);
+        self.varint(data.len() as u64);
+        self.0.extend_from_slice(data);
+    }
+
+    fn packed_uint(&mut self, field: u64, values: &[u64]) {
+        let mut packed = Protobuf::default();
+        for &value in values {
+            packed.varint(value);
+        }
+        self.bytes(field, &packed.0);
+    }
+
+    /// Zigzag-encoded, as protobuf's `sint64`.
+    fn packed_sint(&mut self, field: u64, values: &[i64]) {
+        let zigzag: Vec<u64> = values
+            .iter()
+            .map(|&value| ((value << 1) ^ (value >> 63)) as u64)
+            .collect();
+        self.packed_uint(field, &zigzag);
+    }
+}
@@ -0,0 +1,107 @@
+import importlib.machinery
+import importlib.util
+import tempfile
+import unittest
+from pathlib import Path
+
+EXTRACT_DIR = Path(__file__).resolve().parent.parent / "extract"
+
+SAMPLE_OSM = """<osm version="0.6">
+  <node id="1" lat="0.0" lon="0.0" />
+  <node id="2" lat="0.001" lon="0.0" />
+  <node id="3" lat="0.0" lon="0.001"><tag k="place" v="town" /><tag k="name" v="Here" /></node>
+  <way id="10"><nd ref="1" /><nd ref="2" /><tag k="highway" v="residential" /><tag k="name" v="Near Road" /></way>
+</osm>
+"""
+
+
+def load_extension():
+    """Import the module installed by maturin, or fall back to a local
+    `cargo rustc --lib --features pyo3 --crate-type cdylib` artifact.
+    Returns None if neither works."""
+    try:
+        import extract_street_polygons
+
+        return extract_street_polygons
+    except ImportError:
+        pass
+    for profile in ("release", "debug"):
+        path = EXTRACT_DIR / "target" / profile / "libextract_street_polygons.so"
+        if not path.exists():
+            continue
+        loader = importlib.machinery.ExtensionFileLoader("extract_street_polygons", str(path))
+        spec = importlib.util.spec_from_loader("extract_street_polygons", loader)
+        try:
+            module = importlib.util.module_from_spec(spec)
+            loader.exec_module(module)
+            return module
+        except ImportError:
+            # Built without the pyo3 feature.
+            continue
+    return None
+
+
+class ExtractPythonBindingTests(unittest.TestCase):
+    def test_extract_to_dataframe_returns_rows(self):
+        module = load_extension()
+        if module is None:
+            self.skipTest("extension not built (see load_extension)")
+        with tempfile.TemporaryDirectory() as tmp:
+            input_path = Path(tmp) / "sample.osm"
+            input_path.write_text(SAMPLE_OSM)
+            rows = module.extract_to_dataframe(str(input_path))
+
+        streets = [row for row in rows if row["kind"] == "street"]
+        self.assertEqual([row["name"] for row in streets], ["Near Road"])
+        self.assertEqual(streets[0]["city_resolved"], "Here")
+        self.assertIsInstance(streets[0]["center_lat"], float)
+
+    def test_extract_to_dataframe_takes_keyword_options(self):
+        module = load_extension()
+        if module is None:
+            self.skipTest("extension not built (see load_extension)")
+        with tempfile.TemporaryDirectory() as tmp:
+            input_path = Path(tmp) / "sample.osm"
+            input_path.write_text(SAMPLE_OSM)
+            rows = module.extract_to_dataframe(str(input_path), name_exclude="^Near")
+            with self.assertRaises(TypeError):
+                module.extract_to_dataframe(str(input_path), no_such_option=True)
+
+        self.assertEqual([row["kind"] for row in rows], ["city"])
+        for key in ("city_confidence", "city_place_coord", "wikidata", "merged_from", "chord_km"):
+            self.assertIn(key, rows[0])
+
+    def test_extract_to_dataframe_takes_non_flag_options(self):
+        module = load_extension()
+        if module is None:
+            self.skipTest("extension not built (see load_extension)")
+        with tempfile.TemporaryDirectory() as tmp:
+            input_path = Path(tmp) / "sample.osm"
+            input_path.write_text(SAMPLE_OSM)
+            extract = lambda **options: module.extract_to_dataframe(str(input_path), **options)
+            near = extract(town_radius_km=50.0, with_merged_from=True, city_confidence=True)
+            far = extract(town_radius_km=0.01, min_place_rank="town", max_rows_per_name=1)
+            undated = extract(after_date="2020-01-01", drop_undated=True)
+            places = extract(include_places=True)
+            with self.assertRaises(ValueError):
+                extract(after_date="yesterday")
+            with self.assertRaises(ValueError):
+                extract(max_rows_per_name=0)
+
+        street = lambda rows: next(row for row in rows if row["kind"] == "street")
+        self.assertEqual(street(near)["city_resolved"], "Here")
+        self.assertEqual(street(far)["city_resolved"], "")
+        self.assertEqual([row["kind"] for row in undated], ["city"])
+        place = next(row for row in places if row["name"] == "Here")
+        self.assertEqual((place["kind"], place["city_place_type"]), ("place", "town"))
+
+    def test_extract_to_dataframe_raises_on_missing_input(self):
+        module = load_extension()
+        if module is None:
+            self.skipTest("extension not built (see load_extension)")
+        with self.assertRaises(RuntimeError):
+            module.extract_to_dataframe("/nonexistent/input.osm")
+
+
+if __name__ == "__main__":
+    unittest.main()